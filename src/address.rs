@@ -0,0 +1,173 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use error::*;
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The address of the system bus if `DBUS_SYSTEM_BUS_ADDRESS` is not set.
+pub const DEFAULT_SYSTEM_BUS_ADDRESS: &'static str = "unix:path=/var/run/dbus/system_bus_socket";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A single address of a D-Bus server.
+///
+/// The address format is documented in the [D-Bus
+/// specification](https://dbus.freedesktop.org/doc/dbus-specification.html#addresses).
+pub struct Address {
+    transport: String,
+    params: BTreeMap<String, String>,
+}
+
+fn _unescape(address: &str, value: &str) -> Result<String> {
+    let invalid = |reason: &str| ErrorKind::InvalidAddress(address.to_string(), reason.to_string());
+
+    let mut bytes = vec![];
+    let mut iter = value.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = iter.next()
+                .and_then(|hi| iter.next().map(|lo| [hi, lo]))
+                .ok_or_else(|| invalid("truncated escape sequence"))?;
+            let hex = ::std::str::from_utf8(&hex).map_err(|_| invalid("invalid escape sequence"))?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid("invalid escape sequence"))?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+
+    String::from_utf8(bytes).map_err(|_| invalid("non-UTF-8 value").into())
+}
+
+fn _escape(value: &str) -> String {
+    value.bytes()
+        .map(|byte| {
+            let c = byte as char;
+            if c.is_ascii_alphanumeric() || "-_/.\\*".contains(c) {
+                c.to_string()
+            } else {
+                format!("%{:02x}", byte)
+            }
+        })
+        .collect()
+}
+
+impl Address {
+    /// Create an address for the given transport.
+    pub fn new<T>(transport: T) -> Self
+        where T: ToString,
+    {
+        Address {
+            transport: transport.to_string(),
+            params: BTreeMap::new(),
+        }
+    }
+
+    /// Add a parameter to the address.
+    pub fn param<K, V>(mut self, key: K, value: V) -> Self
+        where K: ToString,
+              V: ToString,
+    {
+        self.params.insert(key.to_string(), value.to_string());
+
+        self
+    }
+
+    /// Parse a single address.
+    pub fn parse(address: &str) -> Result<Self> {
+        let invalid = |reason: &str| ErrorKind::InvalidAddress(address.to_string(), reason.to_string());
+
+        let colon = address.find(':').ok_or_else(|| invalid("missing transport"))?;
+        let (transport, params) = (&address[..colon], &address[colon + 1..]);
+        if transport.is_empty() {
+            bail!(invalid("missing transport"));
+        }
+
+        let mut parsed = Address::new(transport);
+        for param in params.split(',').filter(|param| !param.is_empty()) {
+            let eq = param.find('=').ok_or_else(|| invalid("parameter without a value"))?;
+            let (key, value) = (&param[..eq], &param[eq + 1..]);
+            if key.is_empty() {
+                bail!(invalid("parameter without a key"));
+            }
+            if parsed.params.contains_key(key) {
+                bail!(invalid("duplicate parameter"));
+            }
+
+            parsed.params.insert(key.to_string(), _unescape(address, value)?);
+        }
+
+        Ok(parsed)
+    }
+
+    /// Parse a `;`-separated list of addresses.
+    ///
+    /// Clients should try each address in order until a connection succeeds.
+    pub fn parse_list(addresses: &str) -> Result<Vec<Self>> {
+        let parsed = addresses.split(';')
+            .filter(|address| !address.is_empty())
+            .map(Self::parse)
+            .collect::<Result<Vec<_>>>()?;
+
+        if parsed.is_empty() {
+            bail!(ErrorKind::InvalidAddress(addresses.to_string(), "empty address".to_string()));
+        }
+
+        Ok(parsed)
+    }
+
+    /// The transport of the address (e.g., `unix` or `tcp`).
+    pub fn transport(&self) -> &str {
+        &self.transport
+    }
+
+    /// Get the value of a parameter of the address.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params.get(key).map(String::as_str)
+    }
+
+    /// Get the value of a parameter which must be present.
+    pub fn require(&self, key: &str) -> Result<&str> {
+        self.get(key).ok_or_else(|| {
+            ErrorKind::InvalidAddress(self.to_string(), format!("missing '{}' parameter", key))
+                .into()
+        })
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.transport)?;
+
+        let params = self.params
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, _escape(value)))
+            .collect::<Vec<_>>();
+        write!(f, "{}", params.join(","))
+    }
+}
+
+#[test]
+fn test_parse_addresses() {
+    let addresses = Address::parse_list("unix:path=/tmp/dbus%2dtest;tcp:host=localhost,port=1234;")
+        .unwrap();
+
+    assert_eq!(addresses.len(), 2);
+    assert_eq!(addresses[0].transport(), "unix");
+    assert_eq!(addresses[0].get("path"), Some("/tmp/dbus-test"));
+    assert_eq!(addresses[1].transport(), "tcp");
+    assert_eq!(addresses[1].get("host"), Some("localhost"));
+    assert_eq!(addresses[1].get("port"), Some("1234"));
+    assert_eq!(addresses[1].get("family"), None);
+
+    assert_eq!(Address::parse("unix:path=/tmp/a b").unwrap().to_string(),
+               "unix:path=/tmp/a%20b");
+
+    assert!(Address::parse("").is_err());
+    assert!(Address::parse("unix").is_err());
+    assert!(Address::parse(":path=/tmp").is_err());
+    assert!(Address::parse("unix:path").is_err());
+    assert!(Address::parse("unix:path=/a,path=/b").is_err());
+    assert!(Address::parse("unix:path=%2").is_err());
+    assert!(Address::parse_list(";").is_err());
+}
@@ -1,25 +1,43 @@
 // Distributed under the OSI-approved BSD 3-Clause License.
 // See accompanying LICENSE file for details.
 
+use convert::FromDBus;
+use fd::UnixFd;
 use interface::ErrorMessage;
 use message::Message;
+use signature::Sig;
 use value::{BasicValue, Value};
 
+use std::cell::RefCell;
+
+/// The arguments of a method call.
+///
+/// Errors are suitable for replying to the method call with.
 pub struct Arguments {
     values: Vec<Value>,
+    signatures: Vec<Sig>,
+    fds: RefCell<Vec<Option<UnixFd>>>,
 }
 
 impl Arguments {
+    /// Extract the arguments from a message.
+    ///
+    /// File descriptors are taken out of the message; those which are not taken from the
+    /// arguments are closed when the arguments are dropped.
     pub fn new(msg: &Message) -> Result<Arguments, ErrorMessage> {
         Ok(Arguments {
             values: msg.values().ok().and_then(|x| x).ok_or(Self::invalid_arguments())?,
+            signatures: Sig::parse_list(&msg.signature()).map_err(|_| Self::invalid_arguments())?,
+            fds: RefCell::new(msg.take_fds()),
         })
     }
 
+    /// The argument at the given index.
     pub fn extract(&self, index: usize) -> Result<&Value, ErrorMessage> {
         self.values.get(index).ok_or_else(|| Self::invalid_argument(index))
     }
 
+    /// The string argument at the given index.
     pub fn extract_string(&self, index: usize) -> Result<&String, ErrorMessage> {
         let value = self.extract(index)?;
         if let Value::BasicValue(BasicValue::String(ref s)) = *value {
@@ -29,6 +47,30 @@ impl Arguments {
         }
     }
 
+    /// Get the argument at the given index as a Rust type.
+    pub fn get<T>(&self, index: usize) -> Result<T, ErrorMessage>
+        where T: FromDBus,
+    {
+        T::from_dbus(self.extract(index)?).ok_or_else(|| Self::invalid_argument(index))
+    }
+
+    /// Take the file descriptor argument at the given index.
+    ///
+    /// A file descriptor may only be taken once.
+    pub fn take_fd(&self, index: usize) -> Result<UnixFd, ErrorMessage> {
+        if self.signatures.get(index) != Some(&Sig::UnixFd) {
+            return Err(Self::invalid_argument(index));
+        }
+
+        let fd_index = self.get::<u32>(index)?;
+        self.fds
+            .borrow_mut()
+            .get_mut(fd_index as usize)
+            .and_then(Option::take)
+            .ok_or_else(|| Self::invalid_argument(index))
+    }
+
+    /// The error for arguments which do not match the method's signature.
     pub fn invalid_arguments() -> ErrorMessage {
         ErrorMessage::new("org.freedesktop.DBus.Error.InvalidArgs",
                           "invalid arguments")
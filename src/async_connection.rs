@@ -0,0 +1,412 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use crates::futures_core::Stream;
+
+use connection::{Connection, remote_error};
+use error::*;
+use executor::{WakePipe, wait_readable};
+use message::{Message, MessageType};
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+struct WatcherState {
+    wakers: Vec<Waker>,
+    stop: bool,
+}
+
+type SharedWatcherState = Arc<(Mutex<WatcherState>, Condvar)>;
+
+/// A thread which wakes tasks when the connection becomes readable.
+///
+/// The thread only waits while there are tasks waiting on the connection.
+struct Watcher {
+    state: SharedWatcherState,
+    stop: Arc<WakePipe>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watcher {
+    fn new(fd: RawFd) -> Result<Self> {
+        let state = Arc::new((Mutex::new(WatcherState {
+                                  wakers: vec![],
+                                  stop: false,
+                              }),
+                              Condvar::new()));
+        let stop = Arc::new(WakePipe::new()?);
+
+        let thread_state = state.clone();
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || Self::watch(fd, &thread_state, &thread_stop));
+
+        Ok(Watcher {
+            state: state,
+            stop: stop,
+            thread: Some(thread),
+        })
+    }
+
+    fn watch(fd: RawFd, state: &SharedWatcherState, stop: &WakePipe) {
+        let (ref lock, ref cvar) = **state;
+
+        loop {
+            {
+                let mut guard = lock.lock().unwrap();
+                while guard.wakers.is_empty() && !guard.stop {
+                    guard = cvar.wait(guard).unwrap();
+                }
+                if guard.stop {
+                    return;
+                }
+            }
+
+            let ready = wait_readable(&[fd, stop.fd()], None);
+            let wakers = mem::replace(&mut lock.lock().unwrap().wakers, vec![]);
+
+            match ready {
+                Ok(ref ready) if ready[1] => return,
+                Ok(ref ready) if !ready[0] => {
+                    // Interrupted; put the wakers back and try again.
+                    lock.lock().unwrap().wakers.extend(wakers);
+                },
+                // Tasks will see the error when they read from the connection.
+                _ => {
+                    for waker in wakers {
+                        waker.wake();
+                    }
+                },
+            }
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        let (ref lock, ref cvar) = *self.state;
+        let mut guard = lock.lock().unwrap();
+
+        if !guard.wakers.iter().any(|w| w.will_wake(waker)) {
+            guard.wakers.push(waker.clone());
+        }
+        cvar.notify_one();
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        {
+            let (ref lock, ref cvar) = *self.state;
+            lock.lock().unwrap().stop = true;
+            cvar.notify_one();
+        }
+        self.stop.wake();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Inner {
+    // Fields are dropped in order; the watcher must stop waiting on the connection's socket before
+    // the connection closes it.
+    watcher: Watcher,
+    conn: Connection,
+    replies: RefCell<HashMap<u32, Option<Message>>>,
+    messages: RefCell<VecDeque<Message>>,
+    // The number of `MessageStream`s; messages are only kept while one exists.
+    streams: Cell<usize>,
+}
+
+impl Inner {
+    /// Read all available messages and sort them into replies and incoming messages.
+    fn pump(&self) -> Result<()> {
+        while let Some(msg) = self.conn.read_message(Some(Duration::from_secs(0)))? {
+            let serial = msg.reply_serial()
+                .and_then(|serial| if self.replies.borrow().contains_key(&serial) {
+                    Some(serial)
+                } else {
+                    None
+                });
+
+            if let Some(serial) = serial {
+                self.replies.borrow_mut().insert(serial, Some(msg));
+                continue;
+            }
+
+            match msg.message_type() {
+                MessageType::MethodCall | MessageType::Signal if self.streams.get() > 0 => {
+                    self.messages.borrow_mut().push_back(msg)
+                },
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+/// An asynchronous interface to a `Connection`.
+///
+/// The futures and streams returned do not depend on a specific executor. They are woken by a
+/// background thread which waits for the connection to become readable.
+pub struct AsyncConnection {
+    inner: Rc<Inner>,
+}
+
+impl AsyncConnection {
+    /// Create an asynchronous interface to a connection.
+    ///
+    /// The interface reads every message from the connection, so the connection cannot be shared
+    /// with a `Runner`. Asynchronous methods handled by a runner cannot wait for calls on the
+    /// runner's connection through this; they should either use a separate connection or make
+    /// blocking calls with `Connection::call`.
+    pub fn new(conn: Connection) -> Result<Self> {
+        let watcher = Watcher::new(conn.as_raw_fd())?;
+
+        Ok(AsyncConnection {
+            inner: Rc::new(Inner {
+                watcher: watcher,
+                conn: conn,
+                replies: RefCell::new(HashMap::new()),
+                messages: RefCell::new(VecDeque::new()),
+                streams: Cell::new(0),
+            }),
+        })
+    }
+
+    /// The underlying connection.
+    pub fn connection(&self) -> &Connection {
+        &self.inner.conn
+    }
+
+    /// Call a method.
+    ///
+    /// The returned future resolves to the reply to the call.
+    pub fn call(&self, msg: Message) -> Result<ReplyFuture> {
+        let serial = self.inner.conn.send(msg)?;
+        self.inner.replies.borrow_mut().insert(serial, None);
+
+        Ok(ReplyFuture {
+            inner: self.inner.clone(),
+            serial: serial,
+        })
+    }
+
+    /// A stream of method calls and signals received from the bus.
+    ///
+    /// Method calls and signals are only kept while a stream exists; those received at other
+    /// times are discarded. Each message is given to only one of the streams.
+    pub fn messages(&self) -> MessageStream {
+        self.inner.streams.set(self.inner.streams.get() + 1);
+
+        MessageStream {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// A future for the reply to a method call.
+pub struct ReplyFuture {
+    inner: Rc<Inner>,
+    serial: u32,
+}
+
+impl Future for ReplyFuture {
+    type Output = Result<Message>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if let Err(err) = self.inner.pump() {
+            return Poll::Ready(Err(err));
+        }
+
+        let reply = self.inner
+            .replies
+            .borrow_mut()
+            .get_mut(&self.serial)
+            .and_then(Option::take);
+
+        match reply {
+            Some(reply) => {
+                self.inner.replies.borrow_mut().remove(&self.serial);
+
+                Poll::Ready(match reply.message_type() {
+                    MessageType::MethodReturn => Ok(reply),
+                    MessageType::Error => Err(remote_error(&reply).into()),
+                    _ => Err(ErrorKind::InvalidReply("unexpected reply type".to_string()).into()),
+                })
+            },
+            None => {
+                self.inner.watcher.register(cx.waker());
+
+                Poll::Pending
+            },
+        }
+    }
+}
+
+impl Drop for ReplyFuture {
+    fn drop(&mut self) {
+        self.inner.replies.borrow_mut().remove(&self.serial);
+    }
+}
+
+/// A stream of messages received from the bus.
+///
+/// The stream ends when reading from the connection fails. Messages are buffered until they are
+/// read from the stream, so streams which are not polled should be dropped.
+pub struct MessageStream {
+    inner: Rc<Inner>,
+}
+
+impl Stream for MessageStream {
+    type Item = Message;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if self.inner.pump().is_err() {
+            return Poll::Ready(None);
+        }
+
+        match self.inner.messages.borrow_mut().pop_front() {
+            Some(msg) => Poll::Ready(Some(msg)),
+            None => {
+                self.inner.watcher.register(cx.waker());
+
+                Poll::Pending
+            },
+        }
+    }
+}
+
+impl Drop for MessageStream {
+    fn drop(&mut self) {
+        let streams = self.inner.streams.get() - 1;
+        self.inner.streams.set(streams);
+        if streams == 0 {
+            self.inner.messages.borrow_mut().clear();
+        }
+    }
+}
+
+#[test]
+fn test_reply_future() {
+    use connection::test_connection;
+    use value::{BasicValue, Value};
+
+    use std::task::Wake;
+
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let (conn, bus) = test_connection(false, |msg| {
+        if msg.member() == Some("Fail".to_string()) {
+            vec![msg.error_message("org.example.Error.Failed").add_argument(&"failed".to_string())]
+        } else {
+            vec![msg.return_message().add_argument(&42u32)]
+        }
+    });
+    let conn = AsyncConnection::new(conn).unwrap();
+
+    let block_on = |mut future: ReplyFuture| {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            match Pin::new(&mut future).poll(&mut cx) {
+                Poll::Ready(res) => return res,
+                Poll::Pending => thread::park(),
+            }
+        }
+    };
+    let call = |method: &str| {
+        conn.call(Message::new_method_call("org.example.Test", "/", "org.example.Iface", method))
+            .unwrap()
+    };
+
+    let reply = block_on(call("Answer")).unwrap();
+    let values = reply.values().unwrap().unwrap();
+    assert_eq!(values.len(), 1);
+    match values[0] {
+        Value::BasicValue(BasicValue::Uint32(answer)) => assert_eq!(answer, 42),
+        ref value => panic!("unexpected value: {:?}", value),
+    }
+
+    match *block_on(call("Fail")).unwrap_err().kind() {
+        ErrorKind::RemoteError(ref name, ref message) => {
+            assert_eq!(name, "org.example.Error.Failed");
+            assert_eq!(message, "failed");
+        },
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    drop(conn);
+    assert_eq!(bus.join().unwrap().len(), 2);
+}
+
+#[test]
+fn test_message_stream() {
+    use connection::test_connection;
+
+    use std::task::Wake;
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    let (conn, bus) = test_connection(false, |msg| {
+        let member = msg.member().unwrap();
+        let signal = Message::new_signal("/org/example", "org.example.Iface", &member);
+        vec![signal, msg.return_message()]
+    });
+    let conn = AsyncConnection::new(conn).unwrap();
+    let waker = Waker::from(Arc::new(NoopWaker));
+    let mut cx = Context::from_waker(&waker);
+
+    let emit = |member: &str| {
+        let mut reply = conn.call(Message::new_method_call("org.example.Test",
+                                                           "/",
+                                                           "org.example.Iface",
+                                                           member))
+            .unwrap();
+        // The reply is sent after the signal, so both have arrived once it is ready.
+        while let Poll::Pending = Pin::new(&mut reply).poll(&mut cx) {
+            thread::sleep(Duration::from_millis(1));
+        }
+    };
+
+    // Signals are not kept without a stream to read them.
+    emit("Dropped");
+    assert!(conn.inner.messages.borrow().is_empty());
+
+    let mut stream = conn.messages();
+    emit("Kept");
+    match Pin::new(&mut stream).poll_next(&mut cx) {
+        Poll::Ready(Some(msg)) => assert_eq!(msg.member(), Some("Kept".to_string())),
+        _ => panic!("expected a signal"),
+    }
+    emit("Buffered");
+    assert_eq!(conn.inner.messages.borrow().len(), 1);
+
+    // Dropping the last stream discards buffered messages.
+    drop(stream);
+    assert!(conn.inner.messages.borrow().is_empty());
+
+    drop(conn);
+    bus.join().unwrap();
+}
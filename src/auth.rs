@@ -0,0 +1,534 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use crates::libc;
+use crates::sha1::Sha1;
+
+use context::Credentials;
+use error::*;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+/// Encode bytes as lowercase hexadecimal.
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode hexadecimal into bytes.
+pub fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+/// Generate a random hexadecimal string from the given number of bytes.
+pub fn random_hex(len: usize) -> Result<String> {
+    let mut bytes = vec![0; len];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+
+    Ok(hex_encode(&bytes))
+}
+
+/// The longest line accepted during authentication, including the line ending.
+const MAX_LINE_LENGTH: usize = 16384;
+
+fn _read_line<S>(stream: &mut S) -> Result<String>
+    where S: Read,
+{
+    let mut line = vec![];
+    let mut byte = [0; 1];
+
+    while !line.ends_with(b"\r\n") {
+        if line.len() == MAX_LINE_LENGTH {
+            bail!(ErrorKind::AuthenticationFailed("line too long".to_string()));
+        }
+        if stream.read(&mut byte)? == 0 {
+            bail!(ErrorKind::Disconnected);
+        }
+        line.push(byte[0]);
+    }
+
+    let len = line.len() - 2;
+    line.truncate(len);
+    String::from_utf8(line)
+        .map_err(|_| ErrorKind::AuthenticationFailed("non-UTF-8 reply".to_string()).into())
+}
+
+fn _data(line: &str) -> Option<&str> {
+    if line == "DATA" {
+        Some("")
+    } else if line.starts_with("DATA ") {
+        Some(&line[5..])
+    } else {
+        None
+    }
+}
+
+/// A SASL mechanism used to authenticate with a server.
+///
+/// The data exchanged with the server is hex-encoded by the caller; mechanisms deal with the raw
+/// bytes only.
+pub trait AuthMechanism {
+    /// The name of the mechanism (e.g., `EXTERNAL`).
+    fn name(&self) -> &str;
+
+    /// The initial response to send along with the `AUTH` command, if any.
+    ///
+    /// This is called at the start of every attempt to authenticate.
+    fn initial_response(&mut self) -> Result<Option<Vec<u8>>>;
+
+    /// Respond to a challenge sent by the server.
+    ///
+    /// If an error is returned, the attempt is cancelled and the next mechanism is tried.
+    fn challenge(&mut self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Authenticate using the credentials of the socket.
+///
+/// This is only usable over Unix sockets.
+pub struct External;
+
+impl AuthMechanism for External {
+    fn name(&self) -> &str {
+        "EXTERNAL"
+    }
+
+    fn initial_response(&mut self) -> Result<Option<Vec<u8>>> {
+        Ok(Some(unsafe { libc::getuid() }.to_string().into_bytes()))
+    }
+
+    fn challenge(&mut self, _: &[u8]) -> Result<Vec<u8>> {
+        bail!(ErrorKind::AuthenticationFailed("unexpected challenge for EXTERNAL".to_string()))
+    }
+}
+
+/// Connect without authenticating.
+///
+/// Servers must explicitly allow anonymous clients.
+pub struct Anonymous {
+    trace: String,
+}
+
+impl Anonymous {
+    /// Create an anonymous mechanism which sends the given trace information to the server.
+    pub fn new<T>(trace: T) -> Self
+        where T: ToString,
+    {
+        Anonymous {
+            trace: trace.to_string(),
+        }
+    }
+}
+
+impl AuthMechanism for Anonymous {
+    fn name(&self) -> &str {
+        "ANONYMOUS"
+    }
+
+    fn initial_response(&mut self) -> Result<Option<Vec<u8>>> {
+        Ok(Some(self.trace.clone().into_bytes()))
+    }
+
+    fn challenge(&mut self, _: &[u8]) -> Result<Vec<u8>> {
+        bail!(ErrorKind::AuthenticationFailed("unexpected challenge for ANONYMOUS".to_string()))
+    }
+}
+
+/// Authenticate by proving access to a secret cookie in the user's home directory.
+///
+/// Cookies are read from `~/.dbus-keyrings` which must not be accessible by other users.
+pub struct CookieSha1 {
+    keyring_dir: Option<PathBuf>,
+}
+
+impl CookieSha1 {
+    /// Create a mechanism which reads cookies from `~/.dbus-keyrings`.
+    pub fn new() -> Self {
+        CookieSha1 {
+            keyring_dir: env::var_os("HOME").map(|home| PathBuf::from(home).join(".dbus-keyrings")),
+        }
+    }
+
+    /// Create a mechanism which reads cookies from the given directory.
+    pub fn with_keyring_dir<P>(dir: P) -> Self
+        where P: Into<PathBuf>,
+    {
+        CookieSha1 {
+            keyring_dir: Some(dir.into()),
+        }
+    }
+
+    fn _cookie(&self, context: &str, id: &str) -> Result<String> {
+        let failed = |reason: String| ErrorKind::AuthenticationFailed(reason);
+
+        let dir = self.keyring_dir
+            .as_ref()
+            .ok_or_else(|| failed("no home directory for the keyring".to_string()))?;
+        if fs::metadata(dir)?.permissions().mode() & 0o077 != 0 {
+            bail!(failed(format!("keyring directory {} is accessible by other users",
+                                 dir.display())));
+        }
+
+        if context.is_empty() ||
+           context.chars().any(|c| c == '/' || c == '\\' || c == '.' || c.is_whitespace()) {
+            bail!(failed(format!("invalid cookie context '{}'", context)));
+        }
+
+        let keyring = BufReader::new(File::open(dir.join(context))?);
+        for line in keyring.lines() {
+            let line = line?;
+            let mut fields = line.split(' ');
+            if let (Some(cookie_id), Some(_), Some(cookie)) =
+                   (fields.next(), fields.next(), fields.next()) {
+                if cookie_id == id {
+                    return Ok(cookie.to_string());
+                }
+            }
+        }
+
+        bail!(failed(format!("no cookie {} in context '{}'", id, context)))
+    }
+}
+
+impl Default for CookieSha1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AuthMechanism for CookieSha1 {
+    fn name(&self) -> &str {
+        "DBUS_COOKIE_SHA1"
+    }
+
+    fn initial_response(&mut self) -> Result<Option<Vec<u8>>> {
+        Ok(Some(unsafe { libc::getuid() }.to_string().into_bytes()))
+    }
+
+    fn challenge(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let data = String::from_utf8(data.to_vec())
+            .map_err(|_| ErrorKind::AuthenticationFailed("non-UTF-8 challenge".to_string()))?;
+        let fields = data.split(' ').collect::<Vec<_>>();
+        if fields.len() != 3 {
+            bail!(ErrorKind::AuthenticationFailed(format!("invalid challenge '{}'", data)));
+        }
+
+        let cookie = self._cookie(fields[0], fields[1])?;
+        let client_challenge = random_hex(16)?;
+
+        let mut hash = Sha1::new();
+        hash.update(format!("{}:{}:{}", fields[2], client_challenge, cookie).as_bytes());
+
+        Ok(format!("{} {}", client_challenge, hash.digest()).into_bytes())
+    }
+}
+
+/// The SASL mechanisms to try when connecting to a server.
+///
+/// By default, `EXTERNAL` is tried followed by `DBUS_COOKIE_SHA1` and Unix file descriptor
+/// passing is negotiated when connecting over a Unix socket.
+pub struct Authenticator {
+    mechanisms: Vec<Box<AuthMechanism>>,
+    negotiate_unix_fd: bool,
+}
+
+impl Authenticator {
+    /// Create an authenticator without any mechanisms.
+    pub fn new() -> Self {
+        Authenticator {
+            mechanisms: vec![],
+            negotiate_unix_fd: true,
+        }
+    }
+
+    /// Add a mechanism to try.
+    ///
+    /// Mechanisms are tried in the order they are added.
+    pub fn add_mechanism<M>(mut self, mechanism: M) -> Self
+        where M: AuthMechanism + 'static,
+    {
+        self.mechanisms.push(Box::new(mechanism));
+
+        self
+    }
+
+    /// Whether to ask the server to allow passing Unix file descriptors.
+    pub fn negotiate_unix_fd(mut self, negotiate: bool) -> Self {
+        self.negotiate_unix_fd = negotiate;
+
+        self
+    }
+
+    /// Authenticate with a server.
+    ///
+    /// Returns whether Unix file descriptor passing was agreed to.
+    pub fn authenticate<S>(&mut self, stream: &mut S, can_pass_fds: bool) -> Result<bool>
+        where S: Read + Write,
+    {
+        stream.write_all(b"\0")?;
+
+        let mut authenticated = false;
+        for mechanism in &mut self.mechanisms {
+            if Self::_try_mechanism(stream, mechanism.as_mut())? {
+                authenticated = true;
+                break;
+            }
+        }
+
+        if !authenticated {
+            bail!(ErrorKind::AuthenticationFailed("no mechanism was accepted".to_string()));
+        }
+
+        let unix_fds = if self.negotiate_unix_fd && can_pass_fds {
+            stream.write_all(b"NEGOTIATE_UNIX_FD\r\n")?;
+            _read_line(stream)? == "AGREE_UNIX_FD"
+        } else {
+            false
+        };
+
+        stream.write_all(b"BEGIN\r\n")?;
+
+        Ok(unix_fds)
+    }
+
+    fn _try_mechanism<S>(stream: &mut S, mechanism: &mut AuthMechanism) -> Result<bool>
+        where S: Read + Write,
+    {
+        let command = match mechanism.initial_response()? {
+            Some(response) => format!("AUTH {} {}\r\n", mechanism.name(), hex_encode(&response)),
+            None => format!("AUTH {}\r\n", mechanism.name()),
+        };
+        stream.write_all(command.as_bytes())?;
+
+        loop {
+            let line = _read_line(stream)?;
+
+            if line == "OK" || line.starts_with("OK ") {
+                return Ok(true);
+            } else if line == "REJECTED" || line.starts_with("REJECTED ") {
+                return Ok(false);
+            } else if let Some(data) = _data(&line) {
+                let response = hex_decode(data)
+                    .ok_or_else(|| {
+                        ErrorKind::AuthenticationFailed("invalid hex in challenge".to_string())
+                            .into()
+                    })
+                    .and_then(|data| mechanism.challenge(&data));
+
+                match response {
+                    Ok(response) => {
+                        stream.write_all(format!("DATA {}\r\n", hex_encode(&response)).as_bytes())?
+                    },
+                    Err(_) => stream.write_all(b"CANCEL\r\n")?,
+                }
+            } else if line == "ERROR" || line.starts_with("ERROR ") {
+                stream.write_all(b"CANCEL\r\n")?;
+            } else {
+                bail!(ErrorKind::AuthenticationFailed(line));
+            }
+        }
+    }
+}
+
+impl Default for Authenticator {
+    fn default() -> Self {
+        Self::new()
+            .add_mechanism(External)
+            .add_mechanism(CookieSha1::new())
+    }
+}
+
+/// The clients a listening server accepts.
+pub struct AuthPolicy {
+    /// The users which may connect using `EXTERNAL`.
+    pub allowed_users: Vec<u32>,
+    /// Whether clients may connect using `ANONYMOUS`.
+    pub allow_anonymous: bool,
+    /// Whether to agree to pass Unix file descriptors.
+    pub unix_fds: bool,
+}
+
+impl AuthPolicy {
+    fn _mechanisms(&self) -> &'static str {
+        if self.allow_anonymous {
+            "EXTERNAL ANONYMOUS"
+        } else {
+            "EXTERNAL"
+        }
+    }
+
+    fn _check_external(&self, response: &[u8], credentials: &Credentials) -> bool {
+        let uid = match credentials.unix_user_id {
+            Some(uid) => uid,
+            None => return false,
+        };
+
+        // An empty response asks to be authenticated as whoever owns the socket.
+        let claimed = match ::std::str::from_utf8(response) {
+            Ok("") => Some(uid),
+            Ok(claimed) => claimed.parse::<u32>().ok(),
+            Err(_) => None,
+        };
+
+        claimed == Some(uid) && self.allowed_users.contains(&uid)
+    }
+
+    fn _check(&self, mechanism: &str, response: &[u8], credentials: &Credentials) -> bool {
+        match mechanism {
+            "EXTERNAL" => self._check_external(response, credentials),
+            "ANONYMOUS" => self.allow_anonymous,
+            _ => false,
+        }
+    }
+
+    /// Run the server side of the authentication handshake.
+    ///
+    /// Returns whether Unix file descriptor passing was agreed to.
+    pub fn accept<S>(&self, stream: &mut S, guid: &str, credentials: &Credentials) -> Result<bool>
+        where S: Read + Write,
+    {
+        let rejected = format!("REJECTED {}\r\n", self._mechanisms());
+
+        let mut nul = [0; 1];
+        stream.read_exact(&mut nul)?;
+        if nul[0] != 0 {
+            bail!(ErrorKind::AuthenticationFailed("missing initial nul byte".to_string()));
+        }
+
+        let mut authenticated = false;
+        let mut unix_fds = false;
+        loop {
+            let line = _read_line(stream)?;
+            let mut words = line.splitn(3, ' ');
+            let command = words.next().unwrap_or("");
+
+            match command {
+                "AUTH" if !authenticated => {
+                    let mechanism = words.next().unwrap_or("");
+                    if !self._mechanisms().split(' ').any(|name| name == mechanism) {
+                        stream.write_all(rejected.as_bytes())?;
+                        continue;
+                    }
+
+                    let response = match words.next() {
+                        Some(response) => response.to_string(),
+                        None => {
+                            stream.write_all(b"DATA\r\n")?;
+                            let line = _read_line(stream)?;
+                            match _data(&line) {
+                                Some(data) => data.to_string(),
+                                None => {
+                                    stream.write_all(rejected.as_bytes())?;
+                                    continue;
+                                },
+                            }
+                        },
+                    };
+
+                    let accepted = hex_decode(&response)
+                        .map_or(false, |response| self._check(mechanism, &response, credentials));
+                    if accepted {
+                        stream.write_all(format!("OK {}\r\n", guid).as_bytes())?;
+                        authenticated = true;
+                    } else {
+                        stream.write_all(rejected.as_bytes())?;
+                    }
+                },
+                "CANCEL" | "ERROR" if !authenticated => stream.write_all(rejected.as_bytes())?,
+                "NEGOTIATE_UNIX_FD" if authenticated => {
+                    if self.unix_fds {
+                        stream.write_all(b"AGREE_UNIX_FD\r\n")?;
+                        unix_fds = true;
+                    } else {
+                        stream.write_all(b"ERROR \"unix fd passing is not supported\"\r\n")?;
+                    }
+                },
+                "BEGIN" if authenticated => return Ok(unix_fds),
+                _ => stream.write_all(b"ERROR \"unexpected command\"\r\n")?,
+            }
+        }
+    }
+}
+
+#[test]
+fn test_cookie_sha1() {
+    use std::fs::DirBuilder;
+    use std::os::unix::fs::DirBuilderExt;
+
+    let dir = env::temp_dir().join(format!("rust-bus-keyring-{}", random_hex(8).unwrap()));
+    DirBuilder::new().mode(0o700).create(&dir).unwrap();
+    fs::write(dir.join("org_freedesktop_general"),
+              "1 1500000000 0123456789abcdef\n2 1500000000 fedcba9876543210\n")
+        .unwrap();
+
+    let mut mechanism = CookieSha1::with_keyring_dir(&dir);
+    let response = mechanism.challenge(b"org_freedesktop_general 2 server").unwrap();
+    let response = String::from_utf8(response).unwrap();
+    let fields = response.split(' ').collect::<Vec<_>>();
+
+    let mut hash = Sha1::new();
+    hash.update(format!("server:{}:fedcba9876543210", fields[0]).as_bytes());
+    assert_eq!(fields[1], hash.digest().to_string());
+
+    assert!(mechanism.challenge(b"org_freedesktop_general 3 server").is_err());
+    assert!(mechanism.challenge(b"../escape 1 server").is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_handshake() {
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    let (mut client, mut server) = UnixStream::pair().unwrap();
+    let uid = unsafe { libc::getuid() };
+
+    let handle = thread::spawn(move || {
+        let policy = AuthPolicy {
+            allowed_users: vec![uid],
+            allow_anonymous: false,
+            unix_fds: false,
+        };
+        let credentials = Credentials {
+            unix_user_id: Some(uid),
+            ..Credentials::default()
+        };
+
+        policy.accept(&mut server, "0123456789abcdef0123456789abcdef", &credentials)
+    });
+
+    // The anonymous attempt is rejected before `EXTERNAL` succeeds.
+    let mut auth = Authenticator::new()
+        .add_mechanism(Anonymous::new("test"))
+        .add_mechanism(External);
+    assert_eq!(auth.authenticate(&mut client, true).unwrap(), false);
+    assert_eq!(handle.join().unwrap().unwrap(), false);
+}
+
+#[test]
+fn test_read_line() {
+    use std::io::{Cursor, repeat};
+
+    let mut lines = Cursor::new(b"OK 0123\r\nDATA\r\nREJECTED".to_vec());
+    assert_eq!(_read_line(&mut lines).unwrap(), "OK 0123");
+    assert_eq!(_read_line(&mut lines).unwrap(), "DATA");
+    match *_read_line(&mut lines).unwrap_err().kind() {
+        ErrorKind::Disconnected => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    match *_read_line(&mut repeat(b'A')).unwrap_err().kind() {
+        ErrorKind::AuthenticationFailed(_) => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
@@ -1,11 +1,24 @@
 // Distributed under the OSI-approved BSD 3-Clause License.
 // See accompanying LICENSE file for details.
 
-use crates::dbus_bytestream::connection;
-
+use address::DEFAULT_SYSTEM_BUS_ADDRESS;
+use auth::Authenticator;
+use context::Credentials;
+use convert::{FromDBusArgs, ToDBus};
 use error::*;
+use executor::{Executor, wait_readable};
 use message::{Message, MessageType};
-use value::{BasicValue, Value};
+use transport::Transport;
+use value::{BasicValue, Value, Variant};
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+/// How long to wait for replies by default, in seconds.
+pub(crate) const DEFAULT_TIMEOUT: u64 = 25;
 
 bitflags! {
     /// Flags for use when requesting a name on the bus from the bus.
@@ -46,9 +59,18 @@ pub enum ReleaseNameReply {
     NotOwner,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+/// Replies from the server when starting a service.
+pub enum StartServiceReply {
+    /// The service was started.
+    Success,
+    /// The service was already running.
+    AlreadyRunning,
+}
+
 /// An iterator over messages received from the message bus.
 pub struct Messages<'a> {
-    conn: &'a connection::Connection,
+    conn: &'a Connection,
 }
 
 /// A connection to a bus.
@@ -56,25 +78,199 @@ pub struct Messages<'a> {
 /// A connection is usually to either the system bus or a session bus. User services (e.g.,
 /// `SecretService`, notification daemons, etc.) live on the session bus while system services
 /// (e.g., `Udisks2`, `NetworkManager`, etc.) live on the system bus.
+///
+/// The connection's file descriptor is available through `AsRawFd` so that it may be watched for
+/// readability by an external event loop.
+///
+/// Futures may be spawned onto the connection. They are run by the `Runner` handling the
+/// connection alongside incoming messages.
 pub struct Connection {
-    conn: connection::Connection,
+    transport: RefCell<Transport>,
+    serial: Cell<u32>,
+    queue: RefCell<VecDeque<Message>>,
+    unique_name: String,
+    tasks: Executor,
+    matches: RefCell<HashMap<String, usize>>,
+    peer: bool,
+}
+
+fn _daemon_method(method: &str) -> Message {
+    Message::new_method_call("org.freedesktop.DBus",
+                             "/org/freedesktop/DBus",
+                             "org.freedesktop.DBus",
+                             method)
+}
+
+/// Convert an error reply into an error.
+pub(crate) fn remote_error(reply: &Message) -> ErrorKind {
+    // The message of an error is its first argument, if it is a string.
+    let message = match reply.values() {
+        Ok(Some(values)) => {
+            match values.into_iter().next() {
+                Some(Value::BasicValue(BasicValue::String(message))) => message,
+                _ => String::new(),
+            }
+        },
+        _ => String::new(),
+    };
+
+    ErrorKind::RemoteError(reply.error_name().unwrap_or_default(), message)
 }
 
 impl Connection {
-    // TODO: Expose other connection methods?
+    fn _new(transport: Transport, peer: bool) -> Result<Self> {
+        Ok(Connection {
+            transport: RefCell::new(transport),
+            serial: Cell::new(0),
+            queue: RefCell::new(VecDeque::new()),
+            unique_name: String::new(),
+            tasks: Executor::new()?,
+            matches: RefCell::new(HashMap::new()),
+            peer: peer,
+        })
+    }
+
+    fn new(transport: Transport) -> Result<Self> {
+        let mut conn = Self::_new(transport, false)?;
+
+        conn.unique_name = conn.hello()?;
+
+        Ok(conn)
+    }
+
+    /// Create a connection directly to a peer rather than to a bus.
+    ///
+    /// No `Hello` is sent since there is no bus to assign a unique name.
+    pub(crate) fn new_peer(transport: Transport) -> Result<Self> {
+        Self::_new(transport, true)
+    }
+
+    /// Connect directly to a peer at the given address without a bus in between.
+    ///
+    /// This is used to talk to servers which accept connections using a `Listener`. The bus
+    /// daemon's methods (e.g., `request_name`) are not available on peer connections.
+    pub fn open_peer(address: &str) -> Result<Self> {
+        Self::new_peer(Transport::open(address, &mut Authenticator::default())?)
+    }
+
+    /// Connect to the bus at the given address.
+    ///
+    /// The address may be a `;`-separated list of addresses which are tried in order. The address
+    /// format is documented in the [D-Bus
+    /// specification](https://dbus.freedesktop.org/doc/dbus-specification.html#addresses).
+    pub fn open(address: &str) -> Result<Self> {
+        Self::open_with_auth(address, Authenticator::default())
+    }
+
+    /// Connect to the bus at the given address using the given authentication mechanisms.
+    pub fn open_with_auth(address: &str, mut auth: Authenticator) -> Result<Self> {
+        Self::new(Transport::open(address, &mut auth)?)
+    }
 
     /// Connect to the session bus.
+    ///
+    /// The address is read from the `DBUS_SESSION_BUS_ADDRESS` environment variable.
     pub fn session_new() -> Result<Self> {
-        Ok(Connection {
-            conn: connection::Connection::connect_session()?,
-        })
+        Self::new(Transport::open_env("DBUS_SESSION_BUS_ADDRESS",
+                                      None,
+                                      &mut Authenticator::default())?)
     }
 
     /// Connect to the system bus.
+    ///
+    /// The address is read from the `DBUS_SYSTEM_BUS_ADDRESS` environment variable, falling back
+    /// to the standard location of the system bus.
     pub fn system_new() -> Result<Self> {
-        Ok(Connection {
-            conn: connection::Connection::connect_system()?,
-        })
+        Self::new(Transport::open_env("DBUS_SYSTEM_BUS_ADDRESS",
+                                      Some(DEFAULT_SYSTEM_BUS_ADDRESS),
+                                      &mut Authenticator::default())?)
+    }
+
+    /// Connect to the bus which started the service.
+    ///
+    /// The address is read from the `DBUS_STARTER_ADDRESS` environment variable which is set by
+    /// the bus when activating a service.
+    pub fn starter_new() -> Result<Self> {
+        Self::new(Transport::open_env("DBUS_STARTER_ADDRESS",
+                                      None,
+                                      &mut Authenticator::default())?)
+    }
+
+    /// The unique name assigned to the connection by the bus.
+    ///
+    /// Peer connections do not have a unique name and this is empty.
+    pub fn unique_name(&self) -> &str {
+        &self.unique_name
+    }
+
+    /// Whether Unix file descriptors may be sent over the connection.
+    pub fn can_pass_unix_fds(&self) -> bool {
+        self.transport.borrow().unix_fds()
+    }
+
+    /// Whether the connection is directly to a peer rather than to a bus.
+    pub fn is_peer(&self) -> bool {
+        self.peer
+    }
+
+    /// The credentials of the peer, for connections accepted by a `Listener`.
+    pub fn peer_credentials(&self) -> Option<Credentials> {
+        self.transport.borrow().peer_credentials().cloned()
+    }
+
+    fn _next_serial(&self) -> u32 {
+        // Serials must be non-zero.
+        let serial = self.serial.get().wrapping_add(1).max(1);
+        self.serial.set(serial);
+        serial
+    }
+
+    /// Call a method and wait for its reply.
+    ///
+    /// Messages received while waiting for the reply are queued for the `Messages` iterator. The
+    /// call times out after 25 seconds.
+    pub fn call_sync(&self, msg: Message) -> Result<Option<Vec<Value>>> {
+        self.call(msg, Some(Duration::from_secs(DEFAULT_TIMEOUT)))?.values()
+    }
+
+    /// Call a method and wait for its reply message.
+    ///
+    /// A timeout of `None` waits forever. Messages received while waiting for the reply are
+    /// queued for the `Messages` iterator. Error replies are returned as
+    /// `ErrorKind::RemoteError`.
+    pub fn call(&self, msg: Message, timeout: Option<Duration>) -> Result<Message> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let serial = self.send(msg)?;
+
+        loop {
+            let remaining = deadline.map(|deadline| {
+                let now = Instant::now();
+                if deadline <= now {
+                    Duration::from_secs(0)
+                } else {
+                    deadline - now
+                }
+            });
+            if remaining == Some(Duration::from_secs(0)) {
+                bail!(ErrorKind::Timeout);
+            }
+
+            let reply = match self.transport.borrow_mut().read_message(remaining)? {
+                Some((reply, fds)) => Message::new_with_fds(reply, fds),
+                None => continue,
+            };
+
+            if reply.reply_serial() != Some(serial) {
+                self.queue.borrow_mut().push_back(reply);
+                continue;
+            }
+
+            return match reply.message_type() {
+                MessageType::MethodReturn => Ok(reply),
+                MessageType::Error => bail!(remote_error(&reply)),
+                _ => bail!(ErrorKind::InvalidReply("unexpected reply type".to_string())),
+            };
+        }
     }
 
     /// Request a name on the bus.
@@ -86,13 +282,10 @@ impl Connection {
     pub fn request_name(&self, name: &str, flags: RequestNameFlags)
                         -> Result<RequestNameReply> {
         // TODO: Use an actual struct with an API for this.
-        let msg = Message::new_method_call("org.freedesktop.DBus",
-                                           "/org/freedesktop/DBus",
-                                           "org.freedesktop.DBus",
-                                           "RequestName")
+        let msg = _daemon_method("RequestName")
             .add_argument(&name)
             .add_argument(&flags.bits);
-        if let Some(mut results) = self.conn.call_sync(msg.message)? {
+        if let Some(mut results) = self.call_sync(msg)? {
             if let Some(Value::BasicValue(BasicValue::Uint32(r))) = results.pop() {
                 match r {
                     1 => Ok(RequestNameReply::PrimaryOwner),
@@ -112,12 +305,9 @@ impl Connection {
     /// Release a name on the bus.
     pub fn release_name(&self, name: &str) -> Result<ReleaseNameReply> {
         // TODO: Use an actual struct with an API for this.
-        let msg = Message::new_method_call("org.freedesktop.DBus",
-                                           "/org/freedesktop/DBus",
-                                           "org.freedesktop.DBus",
-                                           "ReleaseName")
+        let msg = _daemon_method("ReleaseName")
             .add_argument(&name);
-        if let Some(mut results) = self.conn.call_sync(msg.message)? {
+        if let Some(mut results) = self.call_sync(msg)? {
             if let Some(Value::BasicValue(BasicValue::Uint32(r))) = results.pop() {
                 match r {
                     1 => Ok(ReleaseNameReply::Released),
@@ -140,31 +330,265 @@ impl Connection {
     ///
     /// The match syntax is documented in the [D-Bus
     /// specification](https://dbus.freedesktop.org/doc/dbus-specification.html#message-bus-routing).
+    ///
+    /// Matches are reference-counted; the rule is only sent to the bus the first time it is
+    /// added and each call should be balanced by a call to `remove_match`. Peer connections
+    /// receive all messages and rules are only counted.
     pub fn add_match(&self, match_rule: &str) -> Result<()> {
-        let msg = Message::new_method_call("org.freedesktop.DBus",
-                                           "/org/freedesktop/DBus",
-                                           "org.freedesktop.DBus",
-                                           "AddMatch")
+        if let Some(count) = self.matches.borrow_mut().get_mut(match_rule) {
+            *count += 1;
+            return Ok(());
+        }
+
+        if self.peer {
+            self.matches.borrow_mut().insert(match_rule.to_string(), 1);
+            return Ok(());
+        }
+
+        let msg = _daemon_method("AddMatch")
             .add_argument(&match_rule);
-        self.conn.call_sync(msg.message)?;
+        self.call_sync(msg)?;
+
+        self.matches.borrow_mut().insert(match_rule.to_string(), 1);
+
         Ok(())
     }
 
+    /// Requests the server to stop routing messages for a match to this connection.
+    ///
+    /// The rule is only removed from the bus once it has been removed as many times as it was
+    /// added. Rules which were never added are ignored.
+    pub fn remove_match(&self, match_rule: &str) -> Result<()> {
+        {
+            let mut matches = self.matches.borrow_mut();
+            match matches.get_mut(match_rule) {
+                Some(count) => {
+                    *count -= 1;
+                    if *count > 0 {
+                        return Ok(());
+                    }
+                },
+                None => return Ok(()),
+            }
+            matches.remove(match_rule);
+        }
+
+        if self.peer {
+            return Ok(());
+        }
+
+        let msg = _daemon_method("RemoveMatch")
+            .add_argument(&match_rule);
+        self.call_sync(msg)?;
+
+        Ok(())
+    }
+
+    fn _call_daemon<T>(&self, method: &str, msg: Message) -> Result<T>
+        where T: FromDBusArgs,
+    {
+        let results = match self.call_sync(msg)? {
+            Some(results) => results,
+            None if !T::args_signature().is_empty() => {
+                bail!(ErrorKind::InvalidReply(format!("{}: no response", method)))
+            },
+            None => vec![],
+        };
+
+        T::from_dbus_args(&results)
+            .ok_or_else(|| ErrorKind::InvalidReply(format!("{}: invalid response", method)).into())
+    }
+
+    /// Register the connection with the bus.
+    ///
+    /// This is done automatically when connecting; the bus rejects further calls.
+    pub fn hello(&self) -> Result<String> {
+        self._call_daemon("Hello", _daemon_method("Hello"))
+            .map(|(name,)| name)
+    }
+
+    /// List the names currently owned on the bus.
+    pub fn list_names(&self) -> Result<Vec<String>> {
+        self._call_daemon("ListNames", _daemon_method("ListNames"))
+            .map(|(names,)| names)
+    }
+
+    /// List the names which may be started by the bus.
+    pub fn list_activatable_names(&self) -> Result<Vec<String>> {
+        self._call_daemon("ListActivatableNames", _daemon_method("ListActivatableNames"))
+            .map(|(names,)| names)
+    }
+
+    /// Whether the given name currently has an owner.
+    pub fn name_has_owner(&self, name: &str) -> Result<bool> {
+        let msg = _daemon_method("NameHasOwner")
+            .add_argument(&name);
+        self._call_daemon("NameHasOwner", msg)
+            .map(|(has_owner,)| has_owner)
+    }
+
+    /// The unique name of the owner of the given name.
+    pub fn get_name_owner(&self, name: &str) -> Result<String> {
+        let msg = _daemon_method("GetNameOwner")
+            .add_argument(&name);
+        self._call_daemon("GetNameOwner", msg)
+            .map(|(owner,)| owner)
+    }
+
+    /// Ask the bus to start the service for the given name.
+    pub fn start_service_by_name(&self, name: &str) -> Result<StartServiceReply> {
+        // The flags argument is currently unused by the specification.
+        let msg = _daemon_method("StartServiceByName")
+            .add_argument(&name)
+            .add_argument(&0u32);
+        let (reply,): (u32,) = self._call_daemon("StartServiceByName", msg)?;
+
+        match reply {
+            1 => Ok(StartServiceReply::Success),
+            2 => Ok(StartServiceReply::AlreadyRunning),
+            r => bail!(ErrorKind::InvalidReply(format!("StartServiceByName: invalid response {}", r))),
+        }
+    }
+
+    /// Add variables to the environment of services started by the bus.
+    pub fn update_activation_environment(&self, environment: &HashMap<String, String>)
+                                         -> Result<()> {
+        let msg = _daemon_method("UpdateActivationEnvironment")
+            .add_argument(&environment.to_dbus());
+        self._call_daemon("UpdateActivationEnvironment", msg)
+    }
+
+    /// List the unique names of the connections waiting to own the given name.
+    ///
+    /// The current owner is listed first.
+    pub fn list_queued_owners(&self, name: &str) -> Result<Vec<String>> {
+        let msg = _daemon_method("ListQueuedOwners")
+            .add_argument(&name);
+        self._call_daemon("ListQueuedOwners", msg)
+            .map(|(owners,)| owners)
+    }
+
+    /// The user ID of the process owning the connection with the given name.
+    pub fn get_connection_unix_user(&self, name: &str) -> Result<u32> {
+        let msg = _daemon_method("GetConnectionUnixUser")
+            .add_argument(&name);
+        self._call_daemon("GetConnectionUnixUser", msg)
+            .map(|(uid,)| uid)
+    }
+
+    /// The process ID of the process owning the connection with the given name.
+    pub fn get_connection_unix_process_id(&self, name: &str) -> Result<u32> {
+        let msg = _daemon_method("GetConnectionUnixProcessID")
+            .add_argument(&name);
+        self._call_daemon("GetConnectionUnixProcessID", msg)
+            .map(|(pid,)| pid)
+    }
+
+    /// Query the bus for the credentials of the connection with the given name.
+    pub fn get_connection_credentials(&self, name: &str) -> Result<Credentials> {
+        let msg = _daemon_method("GetConnectionCredentials")
+            .add_argument(&name);
+        self._call_daemon::<(HashMap<String, Variant>,)>("GetConnectionCredentials", msg)
+            .map(|(creds,)| Credentials::from_map(&creds))
+    }
+
+    /// The unique ID of the bus.
+    pub fn get_id(&self) -> Result<String> {
+        self._call_daemon("GetId", _daemon_method("GetId"))
+            .map(|(id,)| id)
+    }
+
+    /// Ask the bus to reload its configuration.
+    pub fn reload_config(&self) -> Result<()> {
+        self._call_daemon("ReloadConfig", _daemon_method("ReloadConfig"))
+    }
+
     /// Send a `Message` on the bus.
     ///
     /// On success, returns the serial number of the message.
+    ///
+    /// File descriptors in the message are closed once it has been sent.
     pub fn send(&self, msg: Message) -> Result<u32> {
-        Ok(self.conn.send(msg.message)?)
+        let (mut message, fds) = msg.into_parts();
+        let serial = self._next_serial();
+        message.serial = serial;
+
+        self.transport.borrow_mut().send(&message, &fds)?;
+
+        Ok(serial)
+    }
+
+    /// Whether there are messages which have been received, but not yet read.
+    ///
+    /// Event loops should read messages until this is `false` before waiting for the file
+    /// descriptor to become readable again.
+    pub fn has_pending(&self) -> bool {
+        !self.queue.borrow().is_empty() || self.transport.borrow().has_pending()
+    }
+
+    /// Read a message from the bus.
+    ///
+    /// A timeout of `None` blocks until a message is received. Returns `None` if no message was
+    /// received before the timeout expired. A timeout of zero does not block.
+    pub fn read_message(&self, timeout: Option<Duration>) -> Result<Option<Message>> {
+        if let Some(msg) = self.queue.borrow_mut().pop_front() {
+            return Ok(Some(msg));
+        }
+
+        Ok(self.transport
+            .borrow_mut()
+            .read_message(timeout)?
+            .map(|(msg, fds)| Message::new_with_fds(msg, fds)))
+    }
+
+    /// Wait until a message may be read or a spawned task needs to be polled.
+    ///
+    /// Returns `false` if the timeout expired first.
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<bool> {
+        if self.has_pending() || self.tasks.is_woken() {
+            return Ok(true);
+        }
+
+        let ready = wait_readable(&[self.as_raw_fd(), self.tasks.fd()], timeout)?;
+
+        Ok(ready.into_iter().any(|ready| ready))
+    }
+
+    /// Spawn a future to be run alongside the handling of messages.
+    pub fn spawn<F>(&self, future: F)
+        where F: Future<Output = ()> + 'static,
+    {
+        self.tasks.spawn(Box::pin(future))
+    }
+
+    /// Poll any spawned futures which have been woken.
+    ///
+    /// Returns whether any futures were polled.
+    pub fn poll_tasks(&self) -> bool {
+        self.tasks.poll_tasks()
+    }
+
+    /// A file descriptor which is readable when spawned futures need to be polled.
+    ///
+    /// External event loops should watch this in addition to the connection itself.
+    pub fn task_fd(&self) -> RawFd {
+        self.tasks.fd()
     }
 
     /// An iterator over messages received over the bus.
     pub fn iter(&self) -> Messages {
         Messages {
-            conn: &self.conn,
+            conn: self,
         }
     }
 }
 
+impl AsRawFd for Connection {
+    fn as_raw_fd(&self) -> RawFd {
+        self.transport.borrow().as_raw_fd()
+    }
+}
+
 fn _should_handle(message: &Message) -> bool {
     match message.message_type() {
         MessageType::MethodCall | MessageType::Signal => true,
@@ -177,21 +601,222 @@ impl<'a> Iterator for Messages<'a> {
 
     /// Returns messages received from the bus.
     ///
-    /// Note that this currently blocks. See [this
-    /// issue](https://github.com/srwalter/dbus-bytestream/issues/10) for progress on supporting an
-    /// event loop.
+    /// Note that this blocks until a method call or signal arrives. Use
+    /// `Connection::read_message` to integrate with an event loop.
     fn next(&mut self) -> Option<Self::Item> {
-        let res = self.conn.read_msg();
-        match res {
-            Ok(message) => {
-                let dbus_message = Message::new(message);
-                if _should_handle(&dbus_message) {
-                    Some(dbus_message)
-                } else {
-                    None
+        loop {
+            match self.conn.read_message(None) {
+                Ok(Some(message)) => {
+                    if _should_handle(&message) {
+                        return Some(message);
+                    }
+                },
+                Ok(None) => (),
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+/// Create a connection to a fake bus running in a thread.
+///
+/// Method calls received by the bus are given to the handler and the messages it returns are
+/// sent back to the connection. The thread returns every message the bus received once the
+/// connection has been closed.
+pub(crate) fn test_connection<F>(peer: bool, mut handler: F)
+                                 -> (Connection, ::std::thread::JoinHandle<Vec<Message>>)
+    where F: FnMut(&Message) -> Vec<Message> + Send + 'static,
+{
+    let (client, mut bus) = Transport::pair();
+
+    let thread = ::std::thread::spawn(move || {
+        let mut received = vec![];
+        let mut serial = 0;
+
+        while let Ok(Some((msg, fds))) = bus.read_message(None) {
+            let msg = Message::new_with_fds(msg, fds);
+
+            if let MessageType::MethodCall = msg.message_type() {
+                for reply in handler(&msg) {
+                    let (mut reply, fds) = reply.into_parts();
+                    serial += 1;
+                    reply.serial = serial;
+                    bus.send(&reply, &fds).unwrap();
                 }
+            }
+
+            received.push(msg);
+        }
+
+        received
+    });
+
+    (Connection::_new(client, peer).unwrap(), thread)
+}
+
+#[test]
+fn test_match_counting() {
+    let (conn, bus) = test_connection(false, |msg| vec![msg.return_message()]);
+
+    conn.add_match("type='signal'").unwrap();
+    conn.add_match("type='signal'").unwrap();
+    conn.remove_match("type='signal'").unwrap();
+    conn.remove_match("type='signal'").unwrap();
+    // Rules which are not (or no longer) added are not sent to the bus.
+    conn.remove_match("type='signal'").unwrap();
+    conn.remove_match("type='method_call'").unwrap();
+    drop(conn);
+
+    let calls = bus.join()
+        .unwrap()
+        .into_iter()
+        .map(|msg| (msg.member().unwrap(), msg.read::<(String,)>().unwrap().0))
+        .collect::<Vec<_>>();
+    assert_eq!(calls,
+               vec![("AddMatch".to_string(), "type='signal'".to_string()),
+                    ("RemoveMatch".to_string(), "type='signal'".to_string())]);
+}
+
+#[test]
+fn test_daemon_methods() {
+    let (conn, bus) = test_connection(false, |msg| {
+        let reply = msg.return_message();
+        let reply = match msg.member().unwrap().as_str() {
+            "StartServiceByName" => {
+                let (name, _): (String, u32) = msg.read().unwrap();
+                let result: u32 = match name.as_str() {
+                    "org.example.Stopped" => 1,
+                    "org.example.Running" => 2,
+                    _ => 3,
+                };
+                reply.add_argument(&result)
             },
-            Err(_) => None,
+            "NameHasOwner" => reply.add_argument(&true.to_dbus()),
+            "GetNameOwner" => reply.add_argument(&":1.42".to_string()),
+            "ListNames" => reply.add_argument(&vec![":1.42".to_string()].to_dbus()),
+            "GetConnectionUnixUser" => reply.add_argument(&1000u32),
+            "ReloadConfig" => reply,
+            _ => msg.error_message("org.freedesktop.DBus.Error.UnknownMethod"),
+        };
+
+        vec![reply]
+    });
+
+    assert_eq!(conn.start_service_by_name("org.example.Stopped").unwrap(),
+               StartServiceReply::Success);
+    assert_eq!(conn.start_service_by_name("org.example.Running").unwrap(),
+               StartServiceReply::AlreadyRunning);
+    match *conn.start_service_by_name("org.example.Invalid").unwrap_err().kind() {
+        ErrorKind::InvalidReply(_) => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+    assert!(conn.name_has_owner("org.example.Service").unwrap());
+    assert_eq!(conn.get_name_owner("org.example.Service").unwrap(), ":1.42");
+    assert_eq!(conn.list_names().unwrap(), vec![":1.42".to_string()]);
+    assert_eq!(conn.get_connection_unix_user(":1.42").unwrap(), 1000);
+    conn.reload_config().unwrap();
+    match *conn.get_id().unwrap_err().kind() {
+        ErrorKind::RemoteError(ref name, _) => {
+            assert_eq!(name, "org.freedesktop.DBus.Error.UnknownMethod")
+        },
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+    drop(conn);
+
+    let calls = bus.join().unwrap();
+    for call in &calls {
+        assert_eq!(call.destination(), Some("org.freedesktop.DBus".to_string()));
+        assert_eq!(call.path(), Some("/org/freedesktop/DBus".to_string()));
+        assert_eq!(call.interface(), Some("org.freedesktop.DBus".to_string()));
+    }
+
+    let shapes = calls.iter()
+        .map(|call| {
+            let signature = call.values()
+                .unwrap()
+                .unwrap_or_default()
+                .iter()
+                .map(|value| value.get_signature().to_string())
+                .collect::<String>();
+            (call.member().unwrap(), signature)
+        })
+        .collect::<Vec<_>>();
+    let expected = [("StartServiceByName", "su"),
+                    ("StartServiceByName", "su"),
+                    ("StartServiceByName", "su"),
+                    ("NameHasOwner", "s"),
+                    ("GetNameOwner", "s"),
+                    ("ListNames", ""),
+                    ("GetConnectionUnixUser", "s"),
+                    ("ReloadConfig", ""),
+                    ("GetId", "")];
+    assert_eq!(shapes,
+               expected.iter()
+                   .map(|&(member, sig)| (member.to_string(), sig.to_string()))
+                   .collect::<Vec<_>>());
+    assert_eq!(calls[0].read::<(String, u32)>().unwrap(),
+               ("org.example.Stopped".to_string(), 0));
+}
+
+#[test]
+fn test_remote_error() {
+    let reply = Message::new_error("org.example.Error.Failed", 1)
+        .add_argument(&"something went wrong".to_string());
+    match remote_error(&reply) {
+        ErrorKind::RemoteError(name, message) => {
+            assert_eq!(name, "org.example.Error.Failed");
+            assert_eq!(message, "something went wrong");
+        },
+        kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    match remote_error(&Message::new_error("org.example.Error.Empty", 1)) {
+        ErrorKind::RemoteError(name, message) => {
+            assert_eq!(name, "org.example.Error.Empty");
+            assert_eq!(message, "");
+        },
+        kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+#[test]
+fn test_call_queues_messages() {
+    let (conn, bus) = test_connection(true, |msg| {
+        let signal = |member: &str| {
+            Message::new_signal("/org/example", "org.example.Iface", member)
+        };
+        match msg.member().unwrap().as_str() {
+            // Messages which arrive before the reply are queued in order.
+            "Ping" => vec![signal("First"), signal("Second"), msg.return_message()],
+            _ => vec![],
         }
+    });
+
+    let call = |method: &str| {
+        Message::new_method_call("org.example.Service",
+                                 "/org/example",
+                                 "org.example.Iface",
+                                 method)
+    };
+
+    match *conn.call(call("Slow"), Some(Duration::from_millis(50))).unwrap_err().kind() {
+        ErrorKind::Timeout => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+    assert!(!conn.has_pending());
+
+    let reply = conn.call(call("Ping"), None).unwrap();
+    assert_eq!(reply.message_type(), MessageType::MethodReturn);
+    assert!(conn.has_pending());
+
+    let mut members = vec![];
+    while let Some(msg) = conn.read_message(Some(Duration::from_secs(0))).unwrap() {
+        members.push(msg.member().unwrap());
     }
+    assert_eq!(members, vec!["First", "Second"]);
+    assert!(!conn.has_pending());
+
+    drop(conn);
+    bus.join().unwrap();
 }
@@ -0,0 +1,309 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use connection::Connection;
+use convert::{FromDBus, FromDBusArgs};
+use error::*;
+use fd::UnixFd;
+use message::Message;
+use target::Target;
+use value::{BasicValue, Value, Variant};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// File descriptors attached to the reply to a method call.
+pub(crate) type AttachedFds = Rc<RefCell<Vec<Option<UnixFd>>>>;
+
+/// Attach a file descriptor and return the `h` value which refers to it.
+pub(crate) fn attach_fd(fds: &AttachedFds, fd: UnixFd) -> Value {
+    let mut fds = fds.borrow_mut();
+    fds.push(Some(fd));
+
+    Value::BasicValue(BasicValue::Uint32(fds.len() as u32 - 1))
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// The credentials of a connection to the bus.
+///
+/// Fields are `None` if the bus does not know the value for the connection.
+pub struct Credentials {
+    /// The user ID of the process owning the connection.
+    pub unix_user_id: Option<u32>,
+    /// The group IDs of the process owning the connection.
+    pub unix_group_ids: Option<Vec<u32>>,
+    /// The ID of the process owning the connection.
+    pub process_id: Option<u32>,
+    /// The security label of the connection (e.g., an SELinux context).
+    pub linux_security_label: Option<Vec<u8>>,
+}
+
+impl Credentials {
+    /// Parse credentials from the reply to `GetConnectionCredentials`.
+    ///
+    /// Unknown keys and values of an unexpected type are ignored.
+    pub fn from_map(map: &HashMap<String, Variant>) -> Self {
+        let mut creds = Credentials::default();
+        for (key, value) in map {
+            let value = &*value.object;
+
+            match key.as_str() {
+                "UnixUserID" => creds.unix_user_id = u32::from_dbus(value),
+                "UnixGroupIDs" => creds.unix_group_ids = Vec::from_dbus(value),
+                "ProcessID" => creds.process_id = u32::from_dbus(value),
+                "LinuxSecurityLabel" => creds.linux_security_label = Vec::from_dbus(value),
+                _ => (),
+            }
+        }
+
+        creds
+    }
+}
+
+#[derive(Clone)]
+/// Information about the method call being handled.
+///
+/// Handlers which are shared between objects may use the context to determine which object was
+/// called. The connection may be used to emit signals or make calls of its own.
+pub struct MethodContext {
+    conn: Rc<Connection>,
+    sender: Option<String>,
+    path: String,
+    interface: String,
+    member: String,
+    credentials: Rc<RefCell<Option<Credentials>>>,
+    fds: AttachedFds,
+}
+
+impl MethodContext {
+    /// Create a context for a method call message.
+    pub fn new<I, M>(conn: &Rc<Connection>, msg: &Message, interface: I, member: M) -> Self
+        where I: ToString,
+              M: ToString,
+    {
+        MethodContext {
+            conn: conn.clone(),
+            sender: msg.sender(),
+            path: msg.path().unwrap_or_default(),
+            interface: interface.to_string(),
+            member: member.to_string(),
+            credentials: Rc::new(RefCell::new(None)),
+            fds: AttachedFds::default(),
+        }
+    }
+
+    /// The connection the method call was received on.
+    pub fn connection(&self) -> &Rc<Connection> {
+        &self.conn
+    }
+
+    /// The unique name of the connection which made the call.
+    pub fn sender(&self) -> Option<&str> {
+        self.sender.as_ref().map(String::as_str)
+    }
+
+    /// The object path the method was called on.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The interface of the method.
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    /// The name of the method.
+    pub fn member(&self) -> &str {
+        &self.member
+    }
+
+    /// Attach a file descriptor to the reply to the method call.
+    ///
+    /// The returned value stands in for the file descriptor and should be returned from the
+    /// handler as an `h` result. Only results which are `h` themselves (rather than containing
+    /// one) may carry file descriptors. Descriptors which are not returned are closed once the
+    /// reply is sent.
+    pub fn attach_fd(&self, fd: UnixFd) -> Value {
+        attach_fd(&self.fds, fd)
+    }
+
+    pub(crate) fn attached_fds(&self) -> AttachedFds {
+        self.fds.clone()
+    }
+
+    /// The credentials of the caller.
+    ///
+    /// The first time this is called for a method call, it makes a blocking call to the bus (see
+    /// `Connection::call`) which queues other incoming messages until the reply arrives. The
+    /// result is kept for the rest of the method call, including by copies of the context. On
+    /// peer connections, the credentials of the peer's socket are returned instead.
+    pub fn credentials(&self) -> Result<Credentials> {
+        if let Some(ref credentials) = *self.credentials.borrow() {
+            return Ok(credentials.clone());
+        }
+
+        let credentials = self._fetch_credentials()?;
+        *self.credentials.borrow_mut() = Some(credentials.clone());
+
+        Ok(credentials)
+    }
+
+    fn _fetch_credentials(&self) -> Result<Credentials> {
+        if self.conn.is_peer() {
+            return self.conn.peer_credentials().ok_or_else(|| {
+                ErrorKind::InvalidMessage("peer credentials are unknown".to_string()).into()
+            });
+        }
+
+        match self.sender {
+            Some(ref sender) => self.conn.get_connection_credentials(sender),
+            None => bail!(ErrorKind::InvalidMessage("method call without a sender".to_string())),
+        }
+    }
+}
+
+/// Information about a signal being handled.
+pub struct SignalContext<'a> {
+    conn: &'a Rc<Connection>,
+    target: &'a Target,
+    message: &'a Message,
+    sender: Option<String>,
+}
+
+impl<'a> SignalContext<'a> {
+    /// Create a context for a signal message.
+    pub fn new(conn: &'a Rc<Connection>, target: &'a Target, message: &'a Message) -> Self {
+        SignalContext {
+            conn: conn,
+            target: target,
+            message: message,
+            sender: message.sender(),
+        }
+    }
+
+    /// The connection the signal was received on.
+    pub fn connection(&self) -> &Rc<Connection> {
+        self.conn
+    }
+
+    /// The signal which was received.
+    pub fn target(&self) -> &Target {
+        self.target
+    }
+
+    /// The unique name of the connection which emitted the signal.
+    pub fn sender(&self) -> Option<&str> {
+        self.sender.as_ref().map(String::as_str)
+    }
+
+    /// The object path which emitted the signal.
+    pub fn path(&self) -> &str {
+        &self.target.object
+    }
+
+    /// The signal message itself.
+    pub fn message(&self) -> &Message {
+        self.message
+    }
+
+    /// The arguments of the signal.
+    pub fn arguments(&self) -> Result<Vec<Value>> {
+        self.message.values().map(Option::unwrap_or_default)
+    }
+
+    /// The arguments of the signal as Rust types.
+    pub fn read<T>(&self) -> Result<T>
+        where T: FromDBusArgs,
+    {
+        self.message.read()
+    }
+}
+
+#[test]
+fn test_method_context() {
+    use crates::dbus_bytestream::message::HEADER_FIELD_SENDER;
+
+    use connection::test_connection;
+    use interface::{Interface, Interfaces, Method};
+    use value::{Dictionary, Signature, Struct};
+
+    let (conn, bus) = test_connection(false, |msg| {
+        assert_eq!(msg.member(), Some("GetConnectionCredentials".to_string()));
+        let pid = match msg.values().unwrap().unwrap()[0] {
+            Value::BasicValue(BasicValue::String(ref name)) if name == ":1.5" => 5,
+            _ => 6,
+        };
+
+        let pid = Value::Variant(Variant::new(Value::BasicValue(BasicValue::Uint32(pid)), "u"));
+        let credentials = vec![(BasicValue::String("ProcessID".to_string()), pid)];
+        let credentials = Dictionary::new_with_sig(credentials.into_iter().collect(),
+                                                   "a{sv}".to_string());
+        vec![msg.return_message().add_argument(&Value::Dictionary(credentials))]
+    });
+    let conn = Rc::new(conn);
+    let seen = Rc::new(RefCell::new(vec![]));
+    {
+        // The same handler is used for both objects.
+        let interfaces = || {
+            let seen = seen.clone();
+            let name = Method::new(move |ctx, _| {
+                let credentials = ctx.credentials().unwrap();
+                // The credentials are only requested once per call.
+                assert_eq!(ctx.clone().credentials().unwrap(), credentials);
+                seen.borrow_mut().push((ctx.sender().map(ToString::to_string),
+                                        ctx.path().to_string(),
+                                        ctx.interface().to_string(),
+                                        ctx.member().to_string(),
+                                        credentials.process_id));
+                Ok(vec![])
+            });
+            let children = Rc::new(RefCell::new(vec![]));
+            Interfaces::new()
+                .add_interface("org.example.Iface", Interface::new().add_method("Name", name))
+                .unwrap()
+                .finalize(&children)
+                .unwrap()
+        };
+        let a = interfaces();
+        let b = interfaces();
+
+        let call = |path: &str, sender: &str| {
+            let mut msg = Message::new_method_call("org.example.Test",
+                                                   path,
+                                                   "org.example.Iface",
+                                                   "Name");
+            let sender = Value::BasicValue(BasicValue::String(sender.to_string()));
+            msg.message.headers.push(Struct {
+                objects: vec![Value::BasicValue(BasicValue::Byte(HEADER_FIELD_SENDER)),
+                              Value::Variant(Variant::new(sender, "s"))],
+                signature: Signature("(yv)".to_string()),
+            });
+            msg
+        };
+        a.handle(&conn, &mut call("/org/example/a", ":1.5"));
+        b.handle(&conn, &mut call("/org/example/b", ":1.6"));
+    }
+    drop(conn);
+
+    let member = "Name".to_string();
+    let iface = "org.example.Iface".to_string();
+    assert_eq!(*seen.borrow(),
+               vec![(Some(":1.5".to_string()),
+                     "/org/example/a".to_string(),
+                     iface.clone(),
+                     member.clone(),
+                     Some(5)),
+                    (Some(":1.6".to_string()),
+                     "/org/example/b".to_string(),
+                     iface,
+                     member,
+                     Some(6))]);
+
+    let requests = bus.join()
+        .unwrap()
+        .into_iter()
+        .filter(|msg| msg.member() == Some("GetConnectionCredentials".to_string()))
+        .count();
+    assert_eq!(requests, 2);
+}
@@ -0,0 +1,429 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use interface::MethodResult;
+use names::ObjectPath;
+use value::{Array, BasicValue, Dictionary, Path, Signature, Struct, Value, Variant};
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// A trait for Rust types which have a D-Bus type.
+pub trait DBusType {
+    /// The D-Bus signature of the type.
+    fn signature() -> String;
+}
+
+/// A trait for converting Rust values into D-Bus values.
+pub trait ToDBus: DBusType {
+    /// Convert the value into a D-Bus value.
+    fn to_dbus(&self) -> Value;
+}
+
+/// A trait for converting D-Bus values into Rust values.
+pub trait FromDBus: DBusType + Sized {
+    /// Convert a D-Bus value into a Rust value.
+    ///
+    /// Returns `None` if the value does not have the expected type.
+    fn from_dbus(value: &Value) -> Option<Self>;
+}
+
+/// A trait for types which are basic D-Bus types.
+///
+/// Only basic types may be used as dictionary keys.
+pub trait BasicType: ToDBus + FromDBus {
+    /// Convert the value into a basic D-Bus value.
+    fn to_basic(&self) -> BasicValue;
+    /// Convert a basic D-Bus value into a Rust value.
+    fn from_basic(value: &BasicValue) -> Option<Self>;
+}
+
+/// A trait for sequences of values used as the arguments of a message.
+pub trait DBusArgs {
+    /// The D-Bus signature of the arguments.
+    fn args_signature() -> String;
+}
+
+/// A trait for converting Rust values into the arguments of a message.
+pub trait ToDBusArgs: DBusArgs {
+    /// Convert the values into D-Bus values.
+    fn to_dbus_args(&self) -> Vec<Value>;
+
+    /// Use the values as the successful result of a method call.
+    fn into_result(self) -> MethodResult
+        where Self: Sized,
+    {
+        Ok(self.to_dbus_args())
+    }
+}
+
+/// A trait for converting the arguments of a message into Rust values.
+pub trait FromDBusArgs: DBusArgs + Sized {
+    /// Convert D-Bus values into Rust values.
+    ///
+    /// Returns `None` if the number or types of the values are not as expected.
+    fn from_dbus_args(values: &[Value]) -> Option<Self>;
+}
+
+macro_rules! basic_type {
+    ($type:ty, $variant:ident, $sig:expr) => {
+        impl DBusType for $type {
+            fn signature() -> String {
+                $sig.to_string()
+            }
+        }
+
+        impl ToDBus for $type {
+            fn to_dbus(&self) -> Value {
+                Value::BasicValue(self.to_basic())
+            }
+        }
+
+        impl FromDBus for $type {
+            fn from_dbus(value: &Value) -> Option<Self> {
+                match *value {
+                    Value::BasicValue(ref value) => Self::from_basic(value),
+                    _ => None,
+                }
+            }
+        }
+
+        impl BasicType for $type {
+            fn to_basic(&self) -> BasicValue {
+                BasicValue::$variant(Clone::clone(self))
+            }
+
+            fn from_basic(value: &BasicValue) -> Option<Self> {
+                match *value {
+                    BasicValue::$variant(ref value) => Some(Clone::clone(value)),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+basic_type!(u8, Byte, "y");
+basic_type!(bool, Boolean, "b");
+basic_type!(i16, Int16, "n");
+basic_type!(u16, Uint16, "q");
+basic_type!(i32, Int32, "i");
+basic_type!(u32, Uint32, "u");
+basic_type!(i64, Int64, "x");
+basic_type!(u64, Uint64, "t");
+basic_type!(String, String, "s");
+basic_type!(Signature, Signature, "g");
+
+impl DBusType for ObjectPath {
+    fn signature() -> String {
+        "o".to_string()
+    }
+}
+
+impl ToDBus for ObjectPath {
+    fn to_dbus(&self) -> Value {
+        Value::BasicValue(self.to_basic())
+    }
+}
+
+impl FromDBus for ObjectPath {
+    fn from_dbus(value: &Value) -> Option<Self> {
+        match *value {
+            Value::BasicValue(ref value) => Self::from_basic(value),
+            _ => None,
+        }
+    }
+}
+
+impl BasicType for ObjectPath {
+    fn to_basic(&self) -> BasicValue {
+        BasicValue::ObjectPath(Path(self.as_str().to_string()))
+    }
+
+    fn from_basic(value: &BasicValue) -> Option<Self> {
+        match *value {
+            BasicValue::ObjectPath(Path(ref path)) => ObjectPath::new(path).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> DBusType for &'a str {
+    fn signature() -> String {
+        "s".to_string()
+    }
+}
+
+impl<'a> ToDBus for &'a str {
+    fn to_dbus(&self) -> Value {
+        Value::BasicValue(BasicValue::String(self.to_string()))
+    }
+}
+
+impl DBusType for f64 {
+    fn signature() -> String {
+        "d".to_string()
+    }
+}
+
+impl ToDBus for f64 {
+    fn to_dbus(&self) -> Value {
+        Value::Double(*self)
+    }
+}
+
+impl FromDBus for f64 {
+    fn from_dbus(value: &Value) -> Option<Self> {
+        match *value {
+            Value::Double(d) => Some(d),
+            _ => None,
+        }
+    }
+}
+
+impl DBusType for Variant {
+    fn signature() -> String {
+        "v".to_string()
+    }
+}
+
+impl ToDBus for Variant {
+    fn to_dbus(&self) -> Value {
+        Value::Variant(self.clone())
+    }
+}
+
+impl FromDBus for Variant {
+    fn from_dbus(value: &Value) -> Option<Self> {
+        match *value {
+            Value::Variant(ref v) => Some(v.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl<T> DBusType for Vec<T>
+    where T: DBusType,
+{
+    fn signature() -> String {
+        format!("a{}", T::signature())
+    }
+}
+
+impl<T> ToDBus for Vec<T>
+    where T: ToDBus,
+{
+    fn to_dbus(&self) -> Value {
+        Value::Array(Array::new_with_sig(self.iter().map(ToDBus::to_dbus).collect(),
+                                         Self::signature()))
+    }
+}
+
+impl<T> FromDBus for Vec<T>
+    where T: FromDBus,
+{
+    fn from_dbus(value: &Value) -> Option<Self> {
+        match *value {
+            Value::Array(ref array) => array.objects.iter().map(T::from_dbus).collect(),
+            _ => None,
+        }
+    }
+}
+
+fn _dict_signature<K, V>() -> String
+    where K: DBusType,
+          V: DBusType,
+{
+    format!("a{{{}{}}}", K::signature(), V::signature())
+}
+
+impl<K, V> DBusType for HashMap<K, V>
+    where K: DBusType + Eq + Hash,
+          V: DBusType,
+{
+    fn signature() -> String {
+        _dict_signature::<K, V>()
+    }
+}
+
+impl<K, V> ToDBus for HashMap<K, V>
+    where K: BasicType + Eq + Hash,
+          V: ToDBus,
+{
+    fn to_dbus(&self) -> Value {
+        Value::Dictionary(Dictionary::new_with_sig(self.iter()
+                                                       .map(|(k, v)| (k.to_basic(), v.to_dbus()))
+                                                       .collect(),
+                                                   Self::signature()))
+    }
+}
+
+impl<K, V> FromDBus for HashMap<K, V>
+    where K: BasicType + Eq + Hash,
+          V: FromDBus,
+{
+    fn from_dbus(value: &Value) -> Option<Self> {
+        match *value {
+            Value::Dictionary(ref dict) => {
+                dict.map
+                    .iter()
+                    .map(|(k, v)| K::from_basic(k).and_then(|k| V::from_dbus(v).map(|v| (k, v))))
+                    .collect()
+            },
+            _ => None,
+        }
+    }
+}
+
+impl<K, V> DBusType for BTreeMap<K, V>
+    where K: DBusType + Ord,
+          V: DBusType,
+{
+    fn signature() -> String {
+        _dict_signature::<K, V>()
+    }
+}
+
+impl<K, V> ToDBus for BTreeMap<K, V>
+    where K: BasicType + Ord,
+          V: ToDBus,
+{
+    fn to_dbus(&self) -> Value {
+        Value::Dictionary(Dictionary::new_with_sig(self.iter()
+                                                       .map(|(k, v)| (k.to_basic(), v.to_dbus()))
+                                                       .collect(),
+                                                   Self::signature()))
+    }
+}
+
+impl<K, V> FromDBus for BTreeMap<K, V>
+    where K: BasicType + Ord,
+          V: FromDBus,
+{
+    fn from_dbus(value: &Value) -> Option<Self> {
+        match *value {
+            Value::Dictionary(ref dict) => {
+                dict.map
+                    .iter()
+                    .map(|(k, v)| K::from_basic(k).and_then(|k| V::from_dbus(v).map(|v| (k, v))))
+                    .collect()
+            },
+            _ => None,
+        }
+    }
+}
+
+impl DBusArgs for () {
+    fn args_signature() -> String {
+        String::new()
+    }
+}
+
+impl ToDBusArgs for () {
+    fn to_dbus_args(&self) -> Vec<Value> {
+        vec![]
+    }
+}
+
+impl FromDBusArgs for () {
+    fn from_dbus_args(values: &[Value]) -> Option<Self> {
+        if values.is_empty() { Some(()) } else { None }
+    }
+}
+
+macro_rules! tuple_type {
+    ($len:expr; $($index:tt $name:ident),+) => {
+        impl<$($name),+> DBusType for ($($name,)+)
+            where $($name: DBusType),+
+        {
+            fn signature() -> String {
+                format!("({})", Self::args_signature())
+            }
+        }
+
+        impl<$($name),+> ToDBus for ($($name,)+)
+            where $($name: ToDBus),+
+        {
+            fn to_dbus(&self) -> Value {
+                Value::Struct(Struct {
+                    objects: self.to_dbus_args(),
+                    signature: Signature(Self::signature()),
+                })
+            }
+        }
+
+        impl<$($name),+> FromDBus for ($($name,)+)
+            where $($name: FromDBus),+
+        {
+            fn from_dbus(value: &Value) -> Option<Self> {
+                match *value {
+                    Value::Struct(ref st) => Self::from_dbus_args(&st.objects),
+                    _ => None,
+                }
+            }
+        }
+
+        impl<$($name),+> DBusArgs for ($($name,)+)
+            where $($name: DBusType),+
+        {
+            fn args_signature() -> String {
+                let sigs: Vec<String> = vec![$($name::signature()),+];
+                sigs.concat()
+            }
+        }
+
+        impl<$($name),+> ToDBusArgs for ($($name,)+)
+            where $($name: ToDBus),+
+        {
+            fn to_dbus_args(&self) -> Vec<Value> {
+                vec![$(self.$index.to_dbus()),+]
+            }
+        }
+
+        impl<$($name),+> FromDBusArgs for ($($name,)+)
+            where $($name: FromDBus),+
+        {
+            fn from_dbus_args(values: &[Value]) -> Option<Self> {
+                if values.len() != $len {
+                    return None;
+                }
+
+                Some(($($name::from_dbus(&values[$index])?,)+))
+            }
+        }
+    }
+}
+
+tuple_type!(1; 0 A);
+tuple_type!(2; 0 A, 1 B);
+tuple_type!(3; 0 A, 1 B, 2 C);
+tuple_type!(4; 0 A, 1 B, 2 C, 3 D);
+tuple_type!(5; 0 A, 1 B, 2 C, 3 D, 4 E);
+tuple_type!(6; 0 A, 1 B, 2 C, 3 D, 4 E, 5 F);
+tuple_type!(7; 0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G);
+tuple_type!(8; 0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G, 7 H);
+
+#[test]
+fn test_signatures() {
+    assert_eq!(u32::signature(), "u");
+    assert_eq!(Vec::<String>::signature(), "as");
+    assert_eq!(HashMap::<String, Variant>::signature(), "a{sv}");
+    assert_eq!(<(u8, Vec<ObjectPath>)>::signature(), "(yao)");
+    assert_eq!(<(u8, Vec<ObjectPath>)>::args_signature(), "yao");
+}
+
+#[test]
+fn test_round_trip() {
+    let mut map = BTreeMap::new();
+    map.insert("answer".to_string(), 42u32);
+
+    let value = map.to_dbus();
+    assert_eq!(value.get_signature(), "a{su}");
+    assert_eq!(BTreeMap::<String, u32>::from_dbus(&value), Some(map));
+
+    let args = (true, -1i64, vec![1u8, 2]).to_dbus_args();
+    assert_eq!(<(bool, i64, Vec<u8>)>::from_dbus_args(&args),
+               Some((true, -1, vec![1, 2])));
+    assert_eq!(<(bool, i64)>::from_dbus_args(&args), None);
+    assert_eq!(u32::from_dbus(&args[0]), None);
+}
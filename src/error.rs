@@ -1,16 +1,60 @@
 // Distributed under the OSI-approved BSD 3-Clause License.
 // See accompanying LICENSE file for details.
 
-use crates::dbus_bytestream::connection;
 use crates::dbus_bytestream::demarshal;
 
 error_chain! {
     foreign_links {
-        DBusMessage(connection::Error)
-            #[doc = "An error message from the underlying D-Bus communication."];
+        Io(::std::io::Error)
+            #[doc = "An error from the underlying socket."];
     }
 
     errors {
+        /// The connection to the bus was closed.
+        Disconnected {
+            description("disconnected")
+        }
+
+        /// Authenticating with the bus failed.
+        AuthenticationFailed(reason: String) {
+            description("authentication failed")
+            display("authentication failed: {}", reason)
+        }
+
+        /// File descriptors were sent over a connection which cannot pass them.
+        UnixFdsNotSupported {
+            description("file descriptor passing is not supported on the connection")
+        }
+
+        /// A malformed message was received.
+        InvalidMessage(reason: String) {
+            description("invalid message")
+            display("invalid message: {}", reason)
+        }
+
+        /// The address of a bus was not available.
+        MissingAddress(variable: String) {
+            description("missing bus address")
+            display("missing bus address: {} is not set", variable)
+        }
+
+        /// An address of a bus could not be used.
+        InvalidAddress(address: String, reason: String) {
+            description("invalid bus address")
+            display("invalid bus address '{}': {}", address, reason)
+        }
+
+        /// No reply to a method call was received in time.
+        Timeout {
+            description("timed out waiting for a reply")
+        }
+
+        /// A method call failed with an error reply.
+        RemoteError(name: String, message: String) {
+            description("remote error")
+            display("remote error {}: {}", name, message)
+        }
+
         /// An invalid reply was received from a method call.
         InvalidReply(desc: String) {
             description("invalid reply")
@@ -22,6 +66,17 @@ error_chain! {
             description("listening server cannot handle methods")
         }
 
+        /// A peer server was created for a connection to a bus.
+        NotPeer {
+            description("connection is not to a peer")
+        }
+
+        /// A name requested on the bus is already owned by another connection.
+        NameExists(name: String) {
+            description("name already exists")
+            display("name already exists on the bus: {}", name)
+        }
+
         /// A server with the given name was already registered.
         ServerAlreadyRegistered(name: String) {
             description("server already registered")
@@ -52,10 +107,52 @@ error_chain! {
             display("failed to extract arguments: {}", err)
         }
 
+        /// The values in a message body did not have the requested types.
+        ArgumentMismatch(expected: String, actual: String) {
+            description("argument type mismatch")
+            display("argument type mismatch: expected '{}', found '{}'", expected, actual)
+        }
+
+        /// An invalid signature was given.
+        InvalidSignature(signature: String, reason: String) {
+            description("invalid signature")
+            display("invalid signature '{}': {}", signature, reason)
+        }
+
         /// An attempt to redefine an interface for an object was made.
         InterfaceAlreadyRegistered(name: String) {
             description("interface already registered")
             display("interface already registered: {}", name)
         }
+
+        /// An invalid object path was given.
+        InvalidObjectPath(path: String, reason: String) {
+            description("invalid object path")
+            display("invalid object path '{}': {}", path, reason)
+        }
+
+        /// An invalid bus name was given.
+        InvalidBusName(name: String, reason: String) {
+            description("invalid bus name")
+            display("invalid bus name '{}': {}", name, reason)
+        }
+
+        /// An invalid interface name was given.
+        InvalidInterfaceName(name: String, reason: String) {
+            description("invalid interface name")
+            display("invalid interface name '{}': {}", name, reason)
+        }
+
+        /// An invalid member (method, signal, or property) name was given.
+        InvalidMemberName(name: String, reason: String) {
+            description("invalid member name")
+            display("invalid member name '{}': {}", name, reason)
+        }
+
+        /// An invalid error name was given.
+        InvalidErrorName(name: String, reason: String) {
+            description("invalid error name")
+            display("invalid error name '{}': {}", name, reason)
+        }
     }
 }
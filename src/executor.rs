@@ -0,0 +1,197 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use crates::libc;
+
+use error::*;
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+
+/// Wait for any of a set of file descriptors to become readable.
+///
+/// Returns whether each file descriptor is readable (or closed). All are `false` if the timeout
+/// expired.
+pub fn wait_readable(fds: &[RawFd], timeout: Option<Duration>) -> Result<Vec<bool>> {
+    let mut pollfds = fds.iter()
+        .map(|&fd| {
+            libc::pollfd {
+                fd: fd,
+                events: libc::POLLIN,
+                revents: 0,
+            }
+        })
+        .collect::<Vec<_>>();
+    let timeout_ms = timeout.map_or(-1, |timeout| {
+        let ms = timeout.as_secs()
+            .saturating_mul(1000)
+            .saturating_add((timeout.subsec_nanos() / 1_000_000) as u64);
+        if (libc::c_int::max_value() as u64) < ms {
+            libc::c_int::max_value()
+        } else {
+            ms as libc::c_int
+        }
+    });
+
+    let ret = unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout_ms) };
+    if ret < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::Interrupted {
+            return Ok(vec![false; fds.len()]);
+        }
+
+        return Err(err.into());
+    }
+
+    Ok(pollfds.iter().map(|pollfd| pollfd.revents != 0).collect())
+}
+
+/// A pipe used to wake a thread waiting for file descriptors to become readable.
+pub struct WakePipe {
+    read: RawFd,
+    write: RawFd,
+}
+
+impl WakePipe {
+    /// Create a new pipe.
+    pub fn new() -> Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        for &fd in &fds {
+            unsafe {
+                let flags = libc::fcntl(fd, libc::F_GETFL);
+                libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+                libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            }
+        }
+
+        Ok(WakePipe {
+            read: fds[0],
+            write: fds[1],
+        })
+    }
+
+    /// The file descriptor which becomes readable when woken.
+    pub fn fd(&self) -> RawFd {
+        self.read
+    }
+
+    /// Make the pipe readable.
+    pub fn wake(&self) {
+        let byte = [1u8];
+        // A full pipe is already readable, so failures may be ignored.
+        unsafe { libc::write(self.write, byte.as_ptr() as *const libc::c_void, 1) };
+    }
+
+    /// Clear any pending wakeups.
+    pub fn drain(&self) {
+        let mut buf = [0u8; 64];
+        while 0 < unsafe { libc::read(self.read, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) } {}
+    }
+}
+
+impl Drop for WakePipe {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read);
+            libc::close(self.write);
+        }
+    }
+}
+
+struct ExecutorWaker {
+    woken: AtomicBool,
+    pipe: WakePipe,
+}
+
+impl Wake for ExecutorWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::SeqCst);
+        self.pipe.wake();
+    }
+}
+
+type Task = Pin<Box<Future<Output = ()>>>;
+
+/// A single-threaded executor for futures spawned on a connection.
+///
+/// All tasks are polled whenever any of them is woken.
+pub struct Executor {
+    tasks: RefCell<Vec<Task>>,
+    spawned: RefCell<Vec<Task>>,
+    waker: Arc<ExecutorWaker>,
+}
+
+impl Executor {
+    /// Create a new executor.
+    pub fn new() -> Result<Self> {
+        Ok(Executor {
+            tasks: RefCell::new(vec![]),
+            spawned: RefCell::new(vec![]),
+            waker: Arc::new(ExecutorWaker {
+                woken: AtomicBool::new(false),
+                pipe: WakePipe::new()?,
+            }),
+        })
+    }
+
+    /// Add a task to the executor.
+    pub fn spawn(&self, task: Task) {
+        self.spawned.borrow_mut().push(task);
+        self.waker.wake_by_ref();
+    }
+
+    /// A file descriptor which is readable when tasks need to be polled.
+    pub fn fd(&self) -> RawFd {
+        self.waker.pipe.fd()
+    }
+
+    /// Whether tasks need to be polled.
+    pub fn is_woken(&self) -> bool {
+        self.waker.woken.load(Ordering::SeqCst)
+    }
+
+    /// Poll the tasks if any of them have been woken.
+    ///
+    /// Returns whether any tasks were polled.
+    pub fn poll_tasks(&self) -> bool {
+        if !self.waker.woken.swap(false, Ordering::SeqCst) {
+            return false;
+        }
+        self.waker.pipe.drain();
+
+        let waker = Waker::from(self.waker.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        // Tasks spawned while polling are queued in `spawned` until the next round.
+        let mut tasks = mem::replace(&mut *self.tasks.borrow_mut(), vec![]);
+        tasks.extend(self.spawned.borrow_mut().drain(..));
+
+        let pending = tasks.into_iter()
+            .filter_map(|mut task| {
+                match task.as_mut().poll(&mut cx) {
+                    Poll::Ready(()) => None,
+                    Poll::Pending => Some(task),
+                }
+            })
+            .collect();
+        *self.tasks.borrow_mut() = pending;
+
+        true
+    }
+}
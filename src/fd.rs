@@ -0,0 +1,297 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use crates::libc;
+
+use convert::DBusType;
+use error::*;
+use value::Marshal;
+
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+#[cfg(target_os = "linux")]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::ptr;
+
+/// The most file descriptors which may be received at once (Linux's `SCM_MAX_FD`).
+const MAX_FDS: usize = 253;
+
+#[derive(Debug, PartialEq, Eq)]
+/// An owned Unix file descriptor which may be passed in a message.
+///
+/// The file descriptor is closed when the value is dropped.
+pub struct UnixFd {
+    fd: RawFd,
+}
+
+impl UnixFd {
+    /// Take ownership of a file descriptor.
+    pub fn new<F>(fd: F) -> Self
+        where F: IntoRawFd,
+    {
+        UnixFd {
+            fd: fd.into_raw_fd(),
+        }
+    }
+
+    /// Duplicate the file descriptor.
+    pub fn try_clone(&self) -> Result<Self> {
+        let fd = unsafe { libc::fcntl(self.fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(UnixFd {
+            fd: fd,
+        })
+    }
+}
+
+impl AsRawFd for UnixFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl IntoRawFd for UnixFd {
+    fn into_raw_fd(self) -> RawFd {
+        let fd = self.fd;
+        mem::forget(self);
+        fd
+    }
+}
+
+impl FromRawFd for UnixFd {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        UnixFd {
+            fd: fd,
+        }
+    }
+}
+
+impl Drop for UnixFd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+impl DBusType for UnixFd {
+    fn signature() -> String {
+        "h".to_string()
+    }
+}
+
+/// The index of a file descriptor within a message.
+///
+/// On the wire, `h` values are indices into the message's file descriptors.
+pub struct UnixFdIndex(pub u32);
+
+impl Marshal for UnixFdIndex {
+    fn dbus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        self.0.dbus_encode(buf)
+    }
+
+    fn get_type(&self) -> String {
+        UnixFd::signature()
+    }
+}
+
+/// Send bytes over a socket along with file descriptors.
+pub fn send_with_fds(sock: RawFd, buf: &[u8], fds: &[RawFd]) -> io::Result<usize> {
+    let fds_len = fds.len() * mem::size_of::<RawFd>();
+    let space = unsafe { libc::CMSG_SPACE(fds_len as u32) } as usize;
+    // Use `u64` to align the buffer for `cmsghdr`.
+    let mut control = vec![0u64; (space + 7) / 8];
+
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+
+    if !fds.is_empty() {
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = space as _;
+
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len as u32) as _;
+            ptr::copy_nonoverlapping(fds.as_ptr(),
+                                     libc::CMSG_DATA(cmsg) as *mut RawFd,
+                                     fds.len());
+        }
+    }
+
+    let ret = unsafe { libc::sendmsg(sock, &msg, 0) };
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+#[cfg(target_os = "linux")]
+const RECV_FLAGS: libc::c_int = libc::MSG_CMSG_CLOEXEC;
+#[cfg(not(target_os = "linux"))]
+const RECV_FLAGS: libc::c_int = 0;
+
+/// Receive bytes from a socket along with any file descriptors sent with them.
+///
+/// Returns the number of bytes received and whether file descriptors were discarded because
+/// there was not enough space for them.
+pub fn recv_with_fds(sock: RawFd, buf: &mut [u8], fds: &mut Vec<UnixFd>)
+                     -> io::Result<(usize, bool)> {
+    let space = unsafe { libc::CMSG_SPACE((MAX_FDS * mem::size_of::<RawFd>()) as u32) } as usize;
+    let mut control = vec![0u64; (space + 7) / 8];
+
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = space as _;
+
+    let ret = unsafe { libc::recvmsg(sock, &mut msg, RECV_FLAGS) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data_len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                for i in 0..data_len / mem::size_of::<RawFd>() {
+                    fds.push(UnixFd::from_raw_fd(ptr::read_unaligned(data.add(i))));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    Ok((ret as usize, msg.msg_flags & libc::MSG_CTRUNC != 0))
+}
+
+#[cfg(target_os = "linux")]
+fn _abstract_addr(name: &str) -> io::Result<(libc::sockaddr_un, libc::socklen_t)> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+
+    // Abstract names start with a nul byte and are not nul-terminated.
+    if addr.sun_path.len() <= name.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "abstract socket name too long"));
+    }
+    for (dest, byte) in addr.sun_path[1..].iter_mut().zip(name.bytes()) {
+        *dest = byte as libc::c_char;
+    }
+
+    let len = mem::size_of::<libc::sa_family_t>() + 1 + name.len();
+    Ok((addr, len as libc::socklen_t))
+}
+
+#[cfg(target_os = "linux")]
+fn _unix_socket() -> io::Result<RawFd> {
+    let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(fd)
+    }
+}
+
+#[cfg(target_os = "linux")]
+/// Connect to a Unix socket in the abstract namespace.
+pub fn connect_abstract(name: &str) -> io::Result<UnixStream> {
+    let (addr, len) = _abstract_addr(name)?;
+    // The stream owns the socket so that it is closed on failure.
+    let stream = unsafe { UnixStream::from_raw_fd(_unix_socket()?) };
+
+    let ret = unsafe {
+        libc::connect(stream.as_raw_fd(),
+                      &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+                      len)
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(stream)
+}
+
+#[cfg(target_os = "linux")]
+/// Listen on a Unix socket in the abstract namespace.
+pub fn bind_abstract(name: &str) -> io::Result<UnixListener> {
+    let (addr, len) = _abstract_addr(name)?;
+    let listener = unsafe { UnixListener::from_raw_fd(_unix_socket()?) };
+
+    let ret = unsafe {
+        libc::bind(listener.as_raw_fd(),
+                   &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+                   len)
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::listen(listener.as_raw_fd(), 128) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(listener)
+}
+
+#[test]
+fn test_pass_fds() {
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    let (sender, receiver) = UnixStream::pair().unwrap();
+    let (mut write_end, read_end) = UnixStream::pair().unwrap();
+
+    let read_fd = UnixFd::new(read_end);
+    assert_eq!(send_with_fds(sender.as_raw_fd(), b"x", &[read_fd.as_raw_fd()]).unwrap(), 1);
+    drop(read_fd);
+
+    let mut buf = [0; 4];
+    let mut fds = vec![];
+    assert_eq!(recv_with_fds(receiver.as_raw_fd(), &mut buf, &mut fds).unwrap(), (1, false));
+    assert_eq!(fds.len(), 1);
+
+    write_end.write_all(b"data").unwrap();
+    let mut file = unsafe { File::from_raw_fd(fds.pop().unwrap().into_raw_fd()) };
+    let mut data = [0; 4];
+    file.read_exact(&mut data).unwrap();
+    assert_eq!(&data, b"data");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_abstract_sockets() {
+    use auth::random_hex;
+
+    use std::io::{Read, Write};
+
+    let name = format!("rust-bus-test-{}", random_hex(8).unwrap());
+    let listener = bind_abstract(&name).unwrap();
+    assert!(bind_abstract(&name).is_err());
+
+    let mut client = connect_abstract(&name).unwrap();
+    let (mut server, _) = listener.accept().unwrap();
+
+    client.write_all(b"ping").unwrap();
+    let mut data = [0; 4];
+    server.read_exact(&mut data).unwrap();
+    assert_eq!(&data, b"ping");
+
+    let too_long = "x".repeat(108);
+    assert!(connect_abstract(&too_long).is_err());
+}
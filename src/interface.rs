@@ -6,13 +6,22 @@ use crates::machine_id::MachineId;
 
 use arguments::Arguments;
 use connection::Connection;
+use context::{self, AttachedFds, MethodContext};
 use error::*;
-use message::{Message, MessageType};
-use value::{BasicValue, Dictionary, Signature, Value};
+use fd::UnixFd;
+use message::{Message, NO_REPLY_EXPECTED};
+use names::{ErrorName, IntoName, InterfaceName, MemberName};
+use signature::IntoSig;
+use value::{Array, BasicValue, Dictionary, Value, Variant};
 
 use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
 use std::collections::btree_map::{BTreeMap, Entry};
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
 use std::rc::{Rc, Weak};
+use std::task::{Context, Poll};
 
 type Map<T> = BTreeMap<String, T>;
 
@@ -27,15 +36,15 @@ impl Argument {
     ///
     /// The signature string specification is documented in the [D-Bus
     /// specification](https://dbus.freedesktop.org/doc/dbus-specification.html#basic-types).
-    pub fn new<N, S>(name: N, sig: S) -> Self
+    /// The signature may also be built using `Sig`. It must be a single complete type.
+    pub fn new<N, S>(name: N, sig: S) -> Result<Self>
         where N: ToString,
-              S: ToString,
+              S: IntoSig,
     {
-        // TODO: make a builder for the signature type.
-        Argument {
+        Ok(Argument {
             name: name.to_string(),
-            signature: sig.to_string(),
-        }
+            signature: sig.into_sig()?.to_string(),
+        })
     }
 }
 
@@ -86,38 +95,113 @@ impl ErrorMessage {
         }
     }
 
-    fn into_message(self, msg: &Message) -> Message {
-        msg.error_message(&self.name)
-            .add_argument(&self.message)
+    fn into_message(self, reply_serial: u32) -> Message {
+        match ErrorName::new(&self.name) {
+            Ok(name) => {
+                Message::new_error(&name, reply_serial)
+                    .add_argument(&self.message)
+            },
+            // Sending an invalid error name would get the connection dropped by the bus.
+            Err(err) => {
+                Message::new_error("org.freedesktop.DBus.Error.Failed", reply_serial)
+                    .add_argument(&format!("{} ({})", self.message, err))
+            },
+        }
     }
 }
 
 /// The result of a method call.
 pub type MethodResult = ::std::result::Result<Vec<Value>, ErrorMessage>;
 /// A holder for method closures.
-pub type MethodHandler = Box<RefCell<FnMut(&mut Message) -> MethodResult>>;
+pub type MethodHandler = Box<RefCell<FnMut(&MethodContext, &mut Message) -> MethodResult>>;
+/// The future returned by an asynchronous method.
+pub type MethodFuture = Pin<Box<Future<Output = MethodResult>>>;
+/// A holder for asynchronous method closures.
+pub type AsyncMethodHandler = Box<RefCell<FnMut(&MethodContext, &mut Message) -> MethodFuture>>;
+/// A holder for method closures which reply at a later time.
+pub type DeferredMethodHandler = Box<RefCell<FnMut(&MethodContext, &mut Message, PendingReply)>>;
+
+/// The annotation for methods which never reply.
+const NO_REPLY_ANNOTATION: &'static str = "org.freedesktop.DBus.Method.NoReply";
+
+enum MethodCallback {
+    Sync(MethodHandler),
+    Async(AsyncMethodHandler),
+    Deferred(DeferredMethodHandler),
+}
 
 /// A representation of a method call.
 pub struct Method {
     in_args: Vec<Argument>,
     out_args: Vec<Argument>,
-    cb: MethodHandler,
+    cb: MethodCallback,
     anns: Annotations,
 }
 
 impl Method {
-    /// Create a new `Method` with the given function.
-    pub fn new<F>(cb: F) -> Self
-        where F: FnMut(&mut Message) -> MethodResult + 'static
-    {
+    fn with_callback(cb: MethodCallback) -> Self {
         Method {
             in_args: vec![],
             out_args: vec![],
-            cb: Box::new(RefCell::new(cb)),
+            cb: cb,
             anns: vec![],
         }
     }
 
+    /// Create a new `Method` with the given function.
+    ///
+    /// The function is given the context of the call along with the message itself.
+    pub fn new<F>(cb: F) -> Self
+        where F: FnMut(&MethodContext, &mut Message) -> MethodResult + 'static
+    {
+        Self::with_callback(MethodCallback::Sync(Box::new(RefCell::new(cb))))
+    }
+
+    /// Create a new `Method` with the given asynchronous function.
+    ///
+    /// The returned future is spawned onto the connection and the reply is sent once it
+    /// completes. Other messages are handled while it is pending.
+    pub fn new_async<F, R>(mut cb: F) -> Self
+        where F: FnMut(&MethodContext, &mut Message) -> R + 'static,
+              R: Future<Output = MethodResult> + 'static,
+    {
+        let handler = move |ctx: &MethodContext, m: &mut Message| -> MethodFuture {
+            Box::pin(cb(ctx, m))
+        };
+
+        Self::with_callback(MethodCallback::Async(Box::new(RefCell::new(handler))))
+    }
+
+    /// Create a new `Method` which replies at a later time.
+    ///
+    /// The function is given a `PendingReply` which must be used to send the reply to the call.
+    /// It may be stored and finished once the operation completes (e.g., when a child process
+    /// exits) while other messages are handled.
+    pub fn new_deferred<F>(cb: F) -> Self
+        where F: FnMut(&MethodContext, &mut Message, PendingReply) + 'static,
+    {
+        Self::with_callback(MethodCallback::Deferred(Box::new(RefCell::new(cb))))
+    }
+
+    /// Create a new `Method` which never replies.
+    ///
+    /// The `org.freedesktop.DBus.Method.NoReply` annotation is added so that callers know not
+    /// to wait for a reply. The function may still fail, but the error is not sent. Returning
+    /// values is a bug and panics in debug builds.
+    pub fn no_reply<F>(cb: F) -> Self
+        where F: FnMut(&MethodContext, &mut Message) -> MethodResult + 'static
+    {
+        Self::new(cb).annotate(Annotation::new(NO_REPLY_ANNOTATION, "true"))
+    }
+
+    fn _is_no_reply(&self) -> bool {
+        self.anns
+            .iter()
+            .rev()
+            .find(|ann| ann.name == NO_REPLY_ANNOTATION)
+            .map_or(false, |ann| ann.value == "true")
+    }
+
     /// Add an input argument to the method.
     pub fn add_argument(mut self, arg: Argument) -> Self {
         self.in_args.push(arg);
@@ -165,6 +249,74 @@ pub trait PropertyReadWriteHandler {
     fn set(&self, &Value) -> PropertySetResult;
 }
 
+enum PropertyChange {
+    Changed(String, Value),
+    Invalidated(String),
+}
+
+#[derive(Default, Clone)]
+/// A handle to notify clients that the properties of an interface have changed.
+///
+/// Notifications are queued and sent as an `org.freedesktop.DBus.Properties.PropertiesChanged`
+/// signal the next time the properties of the object are flushed (see
+/// `Server::flush_properties_changed`). Whether the signal contains the new value, only
+/// invalidates the property, or is not sent at all depends on the
+/// `org.freedesktop.DBus.Property.EmitsChangedSignal` annotation on the property or its
+/// interface.
+///
+/// Property handlers may hold on to a copy of the notifier to report changes from within their
+/// implementation.
+pub struct PropertyNotifier {
+    changes: Rc<RefCell<Vec<PropertyChange>>>,
+}
+
+impl PropertyNotifier {
+    /// Indicate that a property has a new value.
+    ///
+    /// The change is checked when it is flushed; changes to unknown properties or with a value
+    /// which does not match the signature of the property are dropped.
+    pub fn changed<N>(&self, name: N, value: Value)
+        where N: ToString,
+    {
+        self.changes.borrow_mut().push(PropertyChange::Changed(name.to_string(), value));
+    }
+
+    /// Indicate that a property has changed without providing the new value.
+    pub fn invalidated<N>(&self, name: N)
+        where N: ToString,
+    {
+        self.changes.borrow_mut().push(PropertyChange::Invalidated(name.to_string()));
+    }
+
+    fn take(&self) -> Vec<PropertyChange> {
+        mem::replace(&mut *self.changes.borrow_mut(), vec![])
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum EmitsChangedSignal {
+    True,
+    Invalidates,
+    Const,
+    False,
+}
+
+impl EmitsChangedSignal {
+    fn from_annotations(anns: &[Annotation]) -> Option<Self> {
+        anns.iter()
+            .rev()
+            .find(|ann| ann.name == "org.freedesktop.DBus.Property.EmitsChangedSignal")
+            .map(|ann| {
+                match ann.value.as_str() {
+                    "invalidates" => EmitsChangedSignal::Invalidates,
+                    "const" => EmitsChangedSignal::Const,
+                    "false" => EmitsChangedSignal::False,
+                    _ => EmitsChangedSignal::True,
+                }
+            })
+    }
+}
+
 enum PropertyAccess {
     RO(Box<PropertyReadHandler>),
     RW(Box<PropertyReadWriteHandler>),
@@ -172,33 +324,43 @@ enum PropertyAccess {
 }
 
 /// A property which is exposed over the bus.
+///
+/// The signature of a property must be a single complete type.
 pub struct Property {
-    signature: Signature,
+    signature: String,
     access: PropertyAccess,
     anns: Annotations,
 }
 
 impl Property {
-    fn new(sig: Signature, access: PropertyAccess) -> Self {
-        Property {
-            signature: sig,
+    fn new<S>(sig: S, access: PropertyAccess) -> Result<Self>
+        where S: IntoSig,
+    {
+        Ok(Property {
+            signature: sig.into_sig()?.to_string(),
             access: access,
             anns: vec![],
-        }
+        })
     }
 
     /// Create a new read-only property.
-    pub fn new_ro(sig: Signature, access: Box<PropertyReadHandler>) -> Self {
+    pub fn new_ro<S>(sig: S, access: Box<PropertyReadHandler>) -> Result<Self>
+        where S: IntoSig,
+    {
         Property::new(sig, PropertyAccess::RO(access))
     }
 
     /// Create a new read-write property.
-    pub fn new_rw(sig: Signature, access: Box<PropertyReadWriteHandler>) -> Self {
+    pub fn new_rw<S>(sig: S, access: Box<PropertyReadWriteHandler>) -> Result<Self>
+        where S: IntoSig,
+    {
         Property::new(sig, PropertyAccess::RW(access))
     }
 
     /// Create a new write-only property.
-    pub fn new_wo(sig: Signature, access: Box<PropertyWriteHandler>) -> Self {
+    pub fn new_wo<S>(sig: S, access: Box<PropertyWriteHandler>) -> Result<Self>
+        where S: IntoSig,
+    {
         Property::new(sig, PropertyAccess::WO(access))
     }
 
@@ -210,7 +372,7 @@ impl Property {
     }
 
     fn _check_signature(&self, value: &Value) -> bool {
-        self.signature.0 == value.get_signature()
+        self.signature == value.get_signature()
     }
 }
 
@@ -252,6 +414,8 @@ pub struct Interface {
     properties: Map<Property>,
     signals: Map<Signal>,
     anns: Annotations,
+    notifier: PropertyNotifier,
+    errors: Vec<Error>,
 }
 
 impl Interface {
@@ -262,23 +426,40 @@ impl Interface {
             properties: Map::new(),
             signals: Map::new(),
             anns: vec![],
+            notifier: PropertyNotifier::default(),
+            errors: vec![],
+        }
+    }
+
+    fn _add_member<N, T>(map: &mut Map<T>, errors: &mut Vec<Error>, name: N, member: T)
+        where N: IntoName<MemberName>,
+    {
+        match name.into_name() {
+            Ok(name) => {
+                map.insert(name.into_string(), member);
+            },
+            Err(err) => errors.push(err),
         }
     }
 
     /// Add a method to the interface.
+    ///
+    /// An invalid name is reported when the interface is added to an `InterfacesBuilder`.
     pub fn add_method<N>(mut self, name: N, method: Method) -> Self
-        where N: ToString,
+        where N: IntoName<MemberName>,
     {
-        self.methods.insert(name.to_string(), method);
+        Self::_add_member(&mut self.methods, &mut self.errors, name, method);
 
         self
     }
 
     /// Add a property to the interface.
+    ///
+    /// An invalid name is reported when the interface is added to an `InterfacesBuilder`.
     pub fn add_property<N>(mut self, name: N, property: Property) -> Self
-        where N: ToString,
+        where N: IntoName<MemberName>,
     {
-        self.properties.insert(name.to_string(), property);
+        Self::_add_member(&mut self.properties, &mut self.errors, name, property);
 
         self
     }
@@ -291,10 +472,12 @@ impl Interface {
     }
 
     /// Add a signal to the interface.
-    pub fn add_signal<N>(mut self, name: &str, signal: Signal) -> Self
-        where N: ToString,
+    ///
+    /// An invalid name is reported when the interface is added to an `InterfacesBuilder`.
+    pub fn add_signal<N>(mut self, name: N, signal: Signal) -> Self
+        where N: IntoName<MemberName>,
     {
-        self.signals.insert(name.to_string(), signal);
+        Self::_add_member(&mut self.signals, &mut self.errors, name, signal);
 
         self
     }
@@ -306,6 +489,79 @@ impl Interface {
         self
     }
 
+    /// A handle which may be used to notify clients of changes to the interface's properties.
+    pub fn property_notifier(&self) -> PropertyNotifier {
+        self.notifier.clone()
+    }
+
+    fn _emits_changed_signal(&self, prop: &Property) -> EmitsChangedSignal {
+        EmitsChangedSignal::from_annotations(&prop.anns)
+            .or_else(|| EmitsChangedSignal::from_annotations(&self.anns))
+            .unwrap_or(EmitsChangedSignal::True)
+    }
+
+    fn _properties_changed(&self, path: &str, name: &str) -> Option<Message> {
+        let mut changed = HashMap::new();
+        let mut invalidated: Vec<String> = vec![];
+
+        for change in self.notifier.take() {
+            let (prop_name, value) = match change {
+                PropertyChange::Changed(prop_name, value) => (prop_name, Some(value)),
+                PropertyChange::Invalidated(prop_name) => (prop_name, None),
+            };
+            let prop = match self.properties.get(&prop_name) {
+                Some(prop) => prop,
+                None => {
+                    println!("dropping a change to an unknown property: {}.{}",
+                             name,
+                             prop_name);
+                    continue;
+                },
+            };
+            if let Some(ref value) = value {
+                if !prop._check_signature(value) {
+                    println!("dropping a change to property {}.{}: expected '{}' actual '{}'",
+                             name,
+                             prop_name,
+                             prop.signature,
+                             value.get_signature());
+                    continue;
+                }
+            }
+            let emits = self._emits_changed_signal(prop);
+
+            let key = BasicValue::String(prop_name.clone());
+            match (emits, value) {
+                (EmitsChangedSignal::True, Some(value)) => {
+                    invalidated.retain(|n| n != &prop_name);
+                    changed.insert(key, variant(value));
+                },
+                (EmitsChangedSignal::True, None) |
+                (EmitsChangedSignal::Invalidates, _) => {
+                    changed.remove(&key);
+                    if !invalidated.contains(&prop_name) {
+                        invalidated.push(prop_name);
+                    }
+                },
+                (EmitsChangedSignal::Const, _) |
+                (EmitsChangedSignal::False, _) => (),
+            }
+        }
+
+        if changed.is_empty() && invalidated.is_empty() {
+            return None;
+        }
+
+        let invalidated = invalidated.into_iter()
+            .map(|n| Value::BasicValue(BasicValue::String(n)))
+            .collect();
+
+        Some(Message::new_signal(path, "org.freedesktop.DBus.Properties", "PropertiesChanged")
+            .add_argument(&name)
+            .add_argument(&Value::Dictionary(Dictionary::new_with_sig(changed, "a{sv}".to_string())))
+            .add_argument(&Value::Array(Array::new_with_sig(invalidated, "as".to_string()))))
+    }
+
     fn _require_property(&self, name: &str) -> ::std::result::Result<&Property, ErrorMessage> {
         self.properties.get(name).ok_or_else(|| {
             ErrorMessage::new("org.freedesktop.DBus.Error.UnknownProperty",
@@ -328,12 +584,12 @@ impl Interface {
             };
 
             if let Ok(value) = res.as_ref() {
-                if prop._check_signature(value) {
+                if !prop._check_signature(value) {
                     panic!("invalid property return type for: \
                             property: '{}' expected: '{}' actual: '{}'",
                            name,
-                           value.get_signature(),
-                           prop.signature.0)
+                           prop.signature,
+                           value.get_signature())
                 }
             }
 
@@ -342,40 +598,61 @@ impl Interface {
     }
 
     /// Set a property value.
+    ///
+    /// On success, the change is queued to be sent in a `PropertiesChanged` signal. The value is
+    /// read back from the property since the handler may have adjusted it; write-only properties
+    /// are invalidated instead.
     pub fn set_property_value(&self, name: &str, value: &Value) -> MethodResult {
         self._require_property(name).and_then(|prop| {
-            if prop._check_signature(value) {
+            if !prop._check_signature(value) {
                 return Err(Arguments::invalid_arguments());
             }
 
             match prop.access {
-                PropertyAccess::WO(ref wo) => wo.set(value).map(|_| vec![]),
-                PropertyAccess::RW(ref rw) => rw.set(value).map(|_| vec![]),
+                PropertyAccess::WO(ref wo) => {
+                    wo.set(value)?;
+                    self.notifier.invalidated(name);
+                },
+                PropertyAccess::RW(ref rw) => {
+                    rw.set(value)?;
+                    match rw.get() {
+                        Ok(value) => self.notifier.changed(name, value),
+                        Err(_) => self.notifier.invalidated(name),
+                    }
+                },
                 PropertyAccess::RO(_) => {
-                    Err(ErrorMessage::new("org.freedesktop.DBus.Error.Failed",
-                                          &format!("property is read-only: {}", name)))
+                    return Err(ErrorMessage::new("org.freedesktop.DBus.Error.Failed",
+                                                 &format!("property is read-only: {}", name)));
                 },
             }
+
+            Ok(vec![])
         })
     }
 
     /// Get a map of all (readable) property values.
     pub fn get_property_map(&self) -> Dictionary {
-        Dictionary::new(self.properties
-            .iter()
-            .map(|(k, v)| {
-                match v.access {
-                        PropertyAccess::RO(ref ro) => ro.get().ok(),
-                        PropertyAccess::RW(ref rw) => rw.get().ok(),
-                        PropertyAccess::WO(_) => None,
-                    }
-                    .map(|v| (BasicValue::String(k.clone()), v))
-            })
-            .filter_map(|a| a)
-            .collect())
+        Dictionary::new_with_sig(self.properties
+                                     .iter()
+                                     .map(|(k, v)| {
+                                         match v.access {
+                                                 PropertyAccess::RO(ref ro) => ro.get().ok(),
+                                                 PropertyAccess::RW(ref rw) => rw.get().ok(),
+                                                 PropertyAccess::WO(_) => None,
+                                             }
+                                             .map(|v| (BasicValue::String(k.clone()), variant(v)))
+                                     })
+                                     .filter_map(|a| a)
+                                     .collect(),
+                                 "a{sv}".to_string())
     }
 }
 
+fn variant(value: Value) -> Value {
+    let sig = value.get_signature().to_string();
+    Value::Variant(Variant::new(value, &sig))
+}
+
 type InterfaceMap = Rc<RefCell<Map<Interface>>>;
 type InterfaceMapRef = Weak<RefCell<Map<Interface>>>;
 /// A list of child objects for an object.
@@ -412,12 +689,12 @@ impl PeerInterface {
         Ok(vec![Value::BasicValue(BasicValue::String(mid))])
     }
 
-    pub fn new() -> Interface {
-        Interface::new()
-            .add_method("Ping", Method::new(|_| Self::ping()))
+    pub fn new() -> Result<Interface> {
+        Ok(Interface::new()
+            .add_method("Ping", Method::new(|_, _| Self::ping()))
             .add_method("GetMachineId",
-                        Method::new(|_| Self::get_machine_id())
-                            .add_result(Argument::new("machine_uuid", "s")))
+                        Method::new(|_, _| Self::get_machine_id())
+                            .add_result(Argument::new("machine_uuid", "s")?)))
     }
 }
 
@@ -432,14 +709,19 @@ impl PropertyInterface {
         let smap = map.upgrade().expect("get_property: interface map no longer exists?");
         let smap_ref = &smap.borrow();
 
-        require_interface(smap_ref, iface).and_then(|iface| iface.get_property_value(property))
+        require_interface(smap_ref, iface)
+            .and_then(|iface| iface.get_property_value(property))
+            .map(|values| values.into_iter().map(variant).collect())
     }
 
     fn set_property(map: InterfaceMapRef, m: &mut Message) -> MethodResult {
         let values = Arguments::new(m)?;
         let iface = values.extract_string(0)?;
         let property = values.extract_string(1)?;
-        let value = values.extract(2)?;
+        let value = match *values.extract(2)? {
+            Value::Variant(ref v) => &*v.object,
+            _ => return Err(Arguments::invalid_arguments()),
+        };
 
         let smap = map.upgrade().expect("get_property: interface map no longer exists?");
         let smap_ref = &smap.borrow();
@@ -459,26 +741,33 @@ impl PropertyInterface {
             .map(|iface| vec![Value::Dictionary(iface.get_property_map())])
     }
 
-    pub fn new(map: InterfaceMapRef) -> Interface {
+    pub fn new(map: InterfaceMapRef) -> Result<Interface> {
         let get_map = map.clone();
         let set_map = map.clone();
         let get_all_map = map.clone();
 
-        Interface::new()
+        Ok(Interface::new()
             .add_method("Get",
-                        Method::new(move |m| Self::get_property(get_map.clone(), m))
-                            .add_argument(Argument::new("interface_name", "s"))
-                            .add_argument(Argument::new("property_name", "s"))
-                            .add_result(Argument::new("value", "v")))
+                        Method::new(move |_, m| Self::get_property(get_map.clone(), m))
+                            .add_argument(Argument::new("interface_name", "s")?)
+                            .add_argument(Argument::new("property_name", "s")?)
+                            .add_result(Argument::new("value", "v")?))
             .add_method("Set",
-                        Method::new(move |m| Self::set_property(set_map.clone(), m))
-                            .add_argument(Argument::new("interface_name", "s"))
-                            .add_argument(Argument::new("property_name", "s"))
-                            .add_result(Argument::new("value", "v")))
+                        Method::new(move |_, m| Self::set_property(set_map.clone(), m))
+                            .add_argument(Argument::new("interface_name", "s")?)
+                            .add_argument(Argument::new("property_name", "s")?)
+                            .add_argument(Argument::new("value", "v")?))
             .add_method("GetAll",
-                        Method::new(move |m| Self::get_all_properties(get_all_map.clone(), m))
-                            .add_argument(Argument::new("interface_name", "s"))
-                            .add_result(Argument::new("props", "{sv}")))
+                        Method::new(move |_, m| {
+                            Self::get_all_properties(get_all_map.clone(), m)
+                        })
+                            .add_argument(Argument::new("interface_name", "s")?)
+                            .add_result(Argument::new("props", "a{sv}")?))
+            .add_signal("PropertiesChanged",
+                        Signal::new()
+                            .add_argument(Argument::new("interface_name", "s")?)
+                            .add_argument(Argument::new("changed_properties", "a{sv}")?)
+                            .add_argument(Argument::new("invalidated_properties", "as")?)))
     }
 }
 
@@ -540,9 +829,7 @@ impl IntrospectableInterface {
             PropertyAccess::RW(_) => "readwrite",
             PropertyAccess::WO(_) => "write",
         };
-        let sig = match prop.signature {
-            Signature(ref s) => s,
-        };
+        let sig = &prop.signature;
         format!(r#"{}<property name="" type="{}" access="{}">\n{}{}</property>\n"#,
                 name,
                 sig,
@@ -591,29 +878,240 @@ impl IntrospectableInterface {
                 indent)
     }
 
-    pub fn new(map: InterfaceMapRef, children: ChildrenListRef) -> Interface {
-        Interface::new().add_method("Introspect",
-                                    Method::new(move |m| {
-                                            Self::introspect(map.clone(), children.clone(), m)
-                                        })
-                                        .add_result(Argument::new("xml_data", "s")))
+    pub fn new(map: InterfaceMapRef, children: ChildrenListRef) -> Result<Interface> {
+        Ok(Interface::new().add_method("Introspect",
+                                       Method::new(move |_, m| {
+                                               Self::introspect(map.clone(), children.clone(), m)
+                                           })
+                                           .add_result(Argument::new("xml_data", "s")?)))
     }
 }
 
-struct CallHeaders {
+/// The information needed to reply to a method call once its handler has finished.
+struct ReplyInfo {
+    serial: u32,
+    destination: Option<String>,
+    no_reply: bool,
+    declared_no_reply: bool,
+    path: Option<String>,
     interface: String,
     method: String,
+    signatures: Vec<String>,
+    fds: AttachedFds,
+}
+
+impl ReplyInfo {
+    /// Check the result of a method which is not replied to.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, methods declared to never reply panic if they return values.
+    fn check_no_reply(&self, res: &MethodResult) {
+        if self.declared_no_reply {
+            debug_assert!(res.as_ref().map_or(true, Vec::is_empty),
+                          "no-reply method returned values: \
+                           path: '{:?}' interface: '{}' method: '{}'",
+                          self.path,
+                          self.interface,
+                          self.method);
+        }
+    }
+
+    /// Create the reply message for the result of a method.
+    ///
+    /// # Panics
+    ///
+    /// If the method returns values which do not match its signature, a panic will occur since
+    /// this is a bug in the implementation.
+    fn reply(&self, res: MethodResult) -> Message {
+        let mut reply = match res {
+            Ok(vals) => {
+                let actual = vals.iter()
+                    .map(|v| v.get_signature().to_string())
+                    .collect::<Vec<_>>();
+                // File descriptors are returned as the index of the attached descriptor.
+                let matches = self.signatures.len() == actual.len() &&
+                              self.signatures.iter().zip(&actual).all(|(expect, actual)| {
+                                  expect == actual || (expect == "h" && actual == "u")
+                              });
+
+                if !matches {
+                    panic!("invalid return signature for: \
+                            path: '{:?}' interface: '{}' method: '{}' \
+                            expected: '{}' actual: '{}'",
+                           self.path,
+                           self.interface,
+                           self.method,
+                           self.signatures.join(""),
+                           actual.join(""))
+                };
+
+                self.signatures
+                    .iter()
+                    .zip(&vals)
+                    .fold(Message::new_method_return(self.serial), |msg, (sig, val)| {
+                        match (sig.as_str(), val) {
+                            ("h", &Value::BasicValue(BasicValue::Uint32(index))) => {
+                                msg.add_argument(self._take_fd(index))
+                            },
+                            _ => msg.add_argument(val),
+                        }
+                    })
+            },
+            Err(err) => err.into_message(self.serial),
+        };
+
+        if let Some(ref destination) = self.destination {
+            reply.set_destination(destination);
+        }
+
+        reply
+    }
+
+    fn _take_fd(&self, index: u32) -> UnixFd {
+        self.fds
+            .borrow_mut()
+            .get_mut(index as usize)
+            .and_then(Option::take)
+            .unwrap_or_else(|| {
+                panic!("no file descriptor attached for: \
+                        path: '{:?}' interface: '{}' method: '{}' index: {}",
+                       self.path,
+                       self.interface,
+                       self.method,
+                       index)
+            })
+    }
+}
+
+/// A token used to reply to a method call after its handler has returned.
+///
+/// If the token is dropped without a reply being sent, an `org.freedesktop.DBus.Error.Failed`
+/// error is sent so that the caller does not wait forever.
+pub struct PendingReply {
+    conn: Weak<Connection>,
+    info: ReplyInfo,
+    sender: Option<String>,
+    finished: bool,
+}
+
+impl PendingReply {
+    fn new(conn: &Rc<Connection>, info: ReplyInfo, sender: Option<String>) -> Self {
+        PendingReply {
+            conn: Rc::downgrade(conn),
+            info: info,
+            sender: sender,
+            finished: false,
+        }
+    }
+
+    /// The serial of the method call being replied to.
+    pub fn serial(&self) -> u32 {
+        self.info.serial
+    }
+
+    /// The unique name of the connection which made the method call.
+    pub fn sender(&self) -> Option<&str> {
+        self.sender.as_ref().map(String::as_str)
+    }
+
+    /// Attach a file descriptor to the reply.
+    ///
+    /// This is the same as `MethodContext::attach_fd`.
+    pub fn attach_fd(&self, fd: UnixFd) -> Value {
+        context::attach_fd(&self.info.fds, fd)
+    }
+
+    /// Send the result of the method call.
+    ///
+    /// # Panics
+    ///
+    /// If the method returns values which do not match its signature or `h` results which do not
+    /// refer to an attached file descriptor, a panic will occur since this is a bug in the
+    /// implementation.
+    pub fn finish(mut self, res: MethodResult) -> Result<()> {
+        self._send(res)
+    }
+
+    /// Reply to the method call with the given values.
+    pub fn reply(self, values: Vec<Value>) -> Result<()> {
+        self.finish(Ok(values))
+    }
+
+    /// Reply to the method call with an error.
+    pub fn error(self, err: ErrorMessage) -> Result<()> {
+        self.finish(Err(err))
+    }
+
+    fn _send(&mut self, res: MethodResult) -> Result<()> {
+        self.finished = true;
+
+        if self.info.no_reply {
+            self.info.check_no_reply(&res);
+            return Ok(());
+        }
+
+        let reply = self.info.reply(res);
+        match self.conn.upgrade() {
+            Some(conn) => conn.send(reply).map(|_| ()),
+            None => bail!(ErrorKind::Disconnected),
+        }
+    }
+}
+
+impl Drop for PendingReply {
+    fn drop(&mut self) {
+        if !self.finished {
+            let err = ErrorMessage::new("org.freedesktop.DBus.Error.Failed",
+                                        "the method did not send a reply");
+            if let Err(err) = self._send(Err(err)) {
+                println!("failed to send a reply for {}: {:?}", self.info.method, err);
+            }
+        }
+    }
+}
+
+/// A task which replies to a method call once its asynchronous handler has finished.
+struct MethodTask {
+    reply: Option<PendingReply>,
+    future: MethodFuture,
+}
+
+impl Future for MethodTask {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+
+        match this.future.as_mut().poll(cx) {
+            Poll::Ready(res) => {
+                if let Some(reply) = this.reply.take() {
+                    let method = reply.info.method.clone();
+
+                    if let Err(err) = reply.finish(res) {
+                        println!("failed to send a reply for {}: {:?}", method, err);
+                    }
+                }
+
+                Poll::Ready(())
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+struct CallHeaders {
+    interface: Option<String>,
+    method: String,
 }
 
 impl CallHeaders {
     pub fn new(msg: &Message) -> Option<Self> {
-        msg.interface().and_then(|interface| {
-            msg.member().map(|method| {
-                CallHeaders {
-                    interface: interface,
-                    method: method,
-                }
-            })
+        msg.member().map(|method| {
+            CallHeaders {
+                interface: msg.interface(),
+                method: method,
+            }
         })
     }
 }
@@ -622,19 +1120,26 @@ impl InterfacesBuilder {
     // Marked as mut for intent; Rc<> doesn't require it though.
     #[allow(unused_mut)]
     /// Add an interface to the set.
-    pub fn add_interface<N>(mut self, name: N, iface: Interface) -> Result<Self>
-        where N: ToString,
+    ///
+    /// Fails if the name of the interface or any of its members is invalid.
+    pub fn add_interface<N>(mut self, name: N, mut iface: Interface) -> Result<Self>
+        where N: IntoName<InterfaceName>,
     {
+        let name = name.into_name()?;
+        if !iface.errors.is_empty() {
+            return Err(iface.errors.remove(0));
+        }
+
         {
             let mut map = self.map.borrow_mut();
 
-            match map.entry(name.to_string()) {
+            match map.entry(name.into_string()) {
                 Entry::Vacant(v) => {
                     v.insert(iface);
 
                     Ok(())
                 },
-                Entry::Occupied(_) => bail!(ErrorKind::InterfaceAlreadyRegistered(name.to_string())),
+                Entry::Occupied(o) => bail!(ErrorKind::InterfaceAlreadyRegistered(o.key().clone())),
             }
         }
         .map(|_| self)
@@ -650,17 +1155,17 @@ impl InterfacesBuilder {
     pub fn finalize(mut self, children: &ChildrenList) -> Result<Interfaces> {
         self = Ok(self)
             .and_then(|this| {
-                this.add_interface("org.freedesktop.DBus.Peer", PeerInterface::new())
+                this.add_interface("org.freedesktop.DBus.Peer", PeerInterface::new()?)
             })
             .and_then(|this| {
                 let map_ref = Rc::downgrade(&this.map);
                 this.add_interface("org.freedesktop.DBus.Properties",
-                                   PropertyInterface::new(map_ref))
+                                   PropertyInterface::new(map_ref)?)
             })
             .and_then(|this| {
                 let map_ref = Rc::downgrade(&this.map);
                 this.add_interface("org.freedesktop.DBus.Introspectable",
-                                   IntrospectableInterface::new(map_ref, Rc::downgrade(children)))
+                                   IntrospectableInterface::new(map_ref, Rc::downgrade(children))?)
             })?;
 
         Ok(Interfaces {
@@ -684,94 +1189,143 @@ impl Interfaces {
             .join("")
     }
 
-    fn _msg_signature(msg: &Message) -> String {
-        msg.values()
-            .unwrap()
-            .map_or_else(String::new, |vs| {
-                vs.iter()
-                    .map(|v| v.get_signature().to_string())
-                    .collect::<Vec<_>>()
-                    .join("")
-            })
-    }
-
     fn _check_signature(args: &[Argument], msg: &Message) -> bool {
-        let expect_sig = Self::_signature(args);
-        let actual_sig = Self::_msg_signature(msg);
-
-        expect_sig == actual_sig
+        // The header is used rather than the values since `h` arguments unpack as `u`.
+        Self::_signature(args) == msg.signature()
     }
 
     /// Return a dictionary of interfaces and properties on the interface.
     ///
     /// This is meant to be used by an ObjectManager interface.
     pub fn get_interfaces_and_properties(&self) -> Dictionary {
-        Dictionary::new(self.map
+        Dictionary::new_with_sig(self.map
+                                     .borrow()
+                                     .iter()
+                                     .map(|(k, v)| {
+                                         (BasicValue::String(k.clone()),
+                                          Value::Dictionary(v.get_property_map()))
+                                     })
+                                     .collect(),
+                                 "a{sa{sv}}".to_string())
+    }
+
+    /// Send `PropertiesChanged` signals for any queued property changes.
+    pub fn flush_properties_changed(&self, conn: &Connection, path: &str) -> Result<()> {
+        let signals = self.map
             .borrow()
             .iter()
-            .map(|(k, v)| (BasicValue::String(k.clone()), Value::Dictionary(v.get_property_map())))
-            .collect())
+            .filter_map(|(name, iface)| iface._properties_changed(path, name))
+            .collect::<Vec<_>>();
+
+        for signal in signals {
+            conn.send(signal)?;
+        }
+
+        Ok(())
+    }
+
+    /// The names of the interfaces in the set.
+    pub fn interface_names(&self) -> Vec<String> {
+        self.map
+            .borrow()
+            .keys()
+            .cloned()
+            .collect()
     }
 
     /// Parse a `Message` and call the appropriate method (if applicable).
     ///
-    /// Returns `None` if the method doesn't match, otherwise a a `Result` indicating whether the
-    /// method call succeeded or not.
+    /// Returns `None` if the message is not a method call, otherwise a `Result` indicating whether
+    /// the reply (which may be an error for unknown methods) was sent or not.
+    ///
+    /// Asynchronous methods are spawned onto the connection and reply once they complete.
+    /// Deferred methods reply whenever their `PendingReply` is finished.
+    ///
+    /// Methods are still called, but no reply is sent if the caller set `NO_REPLY_EXPECTED` or
+    /// the method is annotated with `org.freedesktop.DBus.Method.NoReply`.
     ///
     /// # Panics
     ///
     /// If the method returns values which do not match its signature, a panic will occur since
     /// this is a bug in the implementation.
-    pub fn handle(&self, conn: &Connection, msg: &mut Message) -> Option<::std::result::Result<(), ()>> {
+    pub fn handle(&self, conn: &Rc<Connection>, msg: &mut Message)
+                  -> Option<::std::result::Result<(), ()>> {
         CallHeaders::new(msg).map(|hdrs| {
-            let iface_name = hdrs.interface;
             let method_name = hdrs.method;
             let map_ref = &self.map.borrow();
-            let opt_iface = map_ref.get(&iface_name);
+            let (iface_name, opt_iface) = match hdrs.interface {
+                Some(iface_name) => {
+                    let opt_iface = map_ref.get(&iface_name);
+                    (iface_name, opt_iface)
+                },
+                // Calls without an interface go to the first interface with a matching method.
+                None => {
+                    map_ref.iter()
+                        .find(|&(_, iface)| iface.methods.contains_key(&method_name))
+                        .map_or_else(|| (String::new(), None),
+                                     |(name, iface)| (name.clone(), Some(iface)))
+                },
+            };
             let opt_method = opt_iface.and_then(|iface| iface.methods.get(&method_name));
+            let no_reply_expected = msg.flags().contains(NO_REPLY_EXPECTED);
 
             let res = if let Some(method) = opt_method {
-                let res = if Self::_check_signature(&method.in_args, msg) {
-                    let mut cb = method.cb.borrow_mut();
+                let declared_no_reply = method._is_no_reply();
+                let ctx = MethodContext::new(conn, msg, &iface_name, &method_name);
+                let reply = ReplyInfo {
+                    serial: msg.serial(),
+                    destination: msg.sender(),
+                    no_reply: no_reply_expected || declared_no_reply,
+                    declared_no_reply: declared_no_reply,
+                    path: msg.path(),
+                    interface: iface_name.clone(),
+                    method: method_name.clone(),
+                    signatures: method.out_args
+                        .iter()
+                        .map(|arg| arg.signature.clone())
+                        .collect(),
+                    fds: ctx.attached_fds(),
+                };
 
-                    match cb.deref_mut()(msg) {
-                        Ok(vals) => {
-                            vals.iter().fold(msg.return_message(), |msg, val| msg.add_argument(val))
-                        },
-                        Err(err) => err.into_message(msg),
+                if !Self::_check_signature(&method.in_args, msg) {
+                    if reply.no_reply {
+                        return Ok(());
                     }
+
+                    reply.reply(Err(Arguments::invalid_arguments()))
                 } else {
-                    Arguments::invalid_arguments().into_message(msg)
-                };
+                    match method.cb {
+                        MethodCallback::Sync(ref cb) => {
+                            let mut cb = cb.borrow_mut();
+                            let res = cb.deref_mut()(&ctx, msg);
 
-                match res.message_type() {
-                    MessageType::Error => (),
-                    MessageType::MethodReturn => {
-                        let expect = Self::_signature(&method.out_args);
-                        let actual = Self::_msg_signature(&res);
-
-                        if expect != actual {
-                            panic!("invalid return signature for: \
-                                    path: '{:?}' interface: '{}' method: '{}' \
-                                    expected: '{}' actual: '{}'",
-                                   msg.path(),
-                                   iface_name,
-                                   method_name,
-                                   expect,
-                                   actual)
-                        };
-                    },
-                    _ => {
-                        panic!("invalid return value for: \
-                                path: '{:?}' interface: '{}' method: '{}'",
-                               msg.path(),
-                               iface_name,
-                               method_name)
-                    },
-                };
+                            if reply.no_reply {
+                                reply.check_no_reply(&res);
+
+                                return Ok(());
+                            }
+
+                            reply.reply(res)
+                        },
+                        MethodCallback::Async(ref cb) => {
+                            let future = cb.borrow_mut().deref_mut()(&ctx, msg);
 
-                res
-            } else if opt_iface.is_none() {
+                            conn.spawn(MethodTask {
+                                reply: Some(PendingReply::new(conn, reply, msg.sender())),
+                                future: future,
+                            });
+
+                            return Ok(());
+                        },
+                        MethodCallback::Deferred(ref cb) => {
+                            let pending = PendingReply::new(conn, reply, msg.sender());
+                            cb.borrow_mut().deref_mut()(&ctx, msg, pending);
+
+                            return Ok(());
+                        },
+                    }
+                }
+            } else if opt_iface.is_none() && !iface_name.is_empty() {
                 msg.error_message("org.freedesktop.DBus.Error.UnknownMethod")
                     .add_argument(&format!("unknown interface: {}", iface_name))
             } else {
@@ -779,6 +1333,11 @@ impl Interfaces {
                     .add_argument(&format!("unknown method: {}", method_name))
             };
 
+            // Errors are not sent either when the caller does not want a reply.
+            if no_reply_expected {
+                return Ok(());
+            }
+
             conn.send(res)
                 .map(|_| ())
                 .map_err(|_| ())
@@ -805,7 +1364,7 @@ fn empty_interface() {
                    true);
     }
 
-    let conn = Connection::session_new().unwrap();
+    let conn = Rc::new(Connection::session_new().unwrap());
     let name = "net.benboeckel.test.rustbus";
 
     assert_eq!(conn.request_name(name, RequestNameFlags::empty()).unwrap(),
@@ -818,3 +1377,379 @@ fn empty_interface() {
 
     ifaces.handle(&conn, &mut msg);
 }
+
+#[test]
+fn no_reply_methods() {
+    assert!(Method::no_reply(|_, _| Ok(vec![]))._is_no_reply());
+    assert!(!Method::new(|_, _| Ok(vec![]))._is_no_reply());
+    assert!(!Method::no_reply(|_, _| Ok(vec![]))
+        .annotate(Annotation::new(NO_REPLY_ANNOTATION, "false"))
+        ._is_no_reply());
+}
+
+#[test]
+fn test_no_reply_handling() {
+    use connection::test_connection;
+    use message::{MessageFlags, MessageType};
+
+    use std::cell::Cell;
+
+    let (conn, bus) = test_connection(true, |_| vec![]);
+    let conn = Rc::new(conn);
+    let calls = Rc::new(Cell::new(0));
+    {
+        let get_calls = calls.clone();
+        let get = Method::new(move |_, _| {
+                get_calls.set(get_calls.get() + 1);
+                Ok(vec![Value::BasicValue(BasicValue::Uint32(0))])
+            })
+            .add_result(Argument::new("value", "u").unwrap());
+        let notify_calls = calls.clone();
+        let notify = Method::no_reply(move |_, _| {
+            notify_calls.set(notify_calls.get() + 1);
+            Ok(vec![])
+        });
+        let iface = Interface::new()
+            .add_method("Get", get)
+            .add_method("Notify", notify);
+        let children = Rc::new(RefCell::new(vec![]));
+        let ifaces = Interfaces::new()
+            .add_interface("org.example.Iface", iface)
+            .unwrap()
+            .finalize(&children)
+            .unwrap();
+
+        let call = |serial: u32, method: &str, flags: MessageFlags| {
+            let mut msg = Message::new_method_call("org.example.Test",
+                                                   "/org/example",
+                                                   "org.example.Iface",
+                                                   method);
+            msg.set_serial(serial).set_flags(flags);
+            msg
+        };
+
+        ifaces.handle(&conn, &mut call(1, "Get", NO_REPLY_EXPECTED));
+        ifaces.handle(&conn, &mut call(2, "Notify", MessageFlags::empty()));
+        ifaces.handle(&conn, &mut call(3, "Missing", NO_REPLY_EXPECTED));
+        ifaces.handle(&conn, &mut call(4, "Get", MessageFlags::empty()));
+    }
+    drop(conn);
+
+    // Methods are still called even though no reply is sent.
+    assert_eq!(calls.get(), 3);
+
+    let replies = bus.join().unwrap();
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].message_type(), MessageType::MethodReturn);
+    assert_eq!(replies[0].reply_serial(), Some(4));
+}
+
+#[cfg(test)]
+fn _read_properties_changed(msg: Message) -> (String, Vec<(String, Value)>, Vec<String>) {
+    let string = |value: &Value| {
+        match *value {
+            Value::BasicValue(BasicValue::String(ref s)) => s.clone(),
+            ref value => panic!("unexpected value: {:?}", value),
+        }
+    };
+
+    let values = msg.values().unwrap().unwrap();
+    assert_eq!(values.len(), 3);
+    let mut changed = match values[1] {
+        Value::Dictionary(ref dict) => {
+            dict.map
+                .iter()
+                .map(|(name, value)| {
+                    let value = match *value {
+                        Value::Variant(ref v) => *v.object.clone(),
+                        ref value => panic!("unexpected value: {:?}", value),
+                    };
+                    (string(&Value::BasicValue(name.clone())), value)
+                })
+                .collect::<Vec<_>>()
+        },
+        ref value => panic!("unexpected changed properties: {:?}", value),
+    };
+    changed.sort_by(|a, b| a.0.cmp(&b.0));
+    let invalidated = match values[2] {
+        Value::Array(ref array) => array.objects.iter().map(string).collect(),
+        ref value => panic!("unexpected invalidated properties: {:?}", value),
+    };
+
+    (string(&values[0]), changed, invalidated)
+}
+
+#[test]
+fn test_emits_changed_signal() {
+    struct Constant;
+
+    impl PropertyReadHandler for Constant {
+        fn get(&self) -> PropertyGetResult {
+            Ok(Value::BasicValue(BasicValue::Uint32(0)))
+        }
+    }
+
+    let emits = |value: &str| {
+        Annotation::new("org.freedesktop.DBus.Property.EmitsChangedSignal", value)
+    };
+    let property = |value: Option<&str>| {
+        let prop = Property::new_ro("u", Box::new(Constant)).unwrap();
+        match value {
+            Some(value) => prop.annotate(emits(value)),
+            None => prop,
+        }
+    };
+    let changes = |iface: &Interface, names: &[&str]| {
+        let notifier = iface.property_notifier();
+        for name in names {
+            notifier.changed(*name, Value::BasicValue(BasicValue::Uint32(1)));
+        }
+
+        iface._properties_changed("/org/example", "org.example.Iface").map(|msg| {
+            let (iface_name, changed, invalidated) = _read_properties_changed(msg);
+            assert_eq!(iface_name, "org.example.Iface");
+
+            let changed = changed.into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+            (changed, invalidated)
+        })
+    };
+
+    let iface = Interface::new()
+        .add_property("Default", property(None))
+        .add_property("True", property(Some("true")))
+        .add_property("Invalidates", property(Some("invalidates")))
+        .add_property("Const", property(Some("const")))
+        .add_property("False", property(Some("false")));
+    assert_eq!(changes(&iface, &["Default", "True", "Invalidates", "Const", "False"]),
+               Some((vec!["Default".to_string(), "True".to_string()],
+                     vec!["Invalidates".to_string()])));
+    assert_eq!(changes(&iface, &["Const", "False"]), None);
+
+    // The annotation on the interface applies to properties without their own annotation.
+    let iface = Interface::new()
+        .annotate(emits("invalidates"))
+        .add_property("Default", property(None))
+        .add_property("True", property(Some("true")));
+    assert_eq!(changes(&iface, &["Default", "True"]),
+               Some((vec!["True".to_string()], vec!["Default".to_string()])));
+}
+
+#[test]
+fn test_property_changes() {
+    use std::cell::Cell;
+
+    // Values are clamped to a maximum of 10.
+    struct Limited(Rc<Cell<u32>>);
+
+    impl PropertyReadWriteHandler for Limited {
+        fn get(&self) -> PropertyGetResult {
+            Ok(Value::BasicValue(BasicValue::Uint32(self.0.get())))
+        }
+
+        fn set(&self, value: &Value) -> PropertySetResult {
+            match *value {
+                Value::BasicValue(BasicValue::Uint32(value)) => self.0.set(value.min(10)),
+                _ => unreachable!(),
+            }
+            Ok(())
+        }
+    }
+
+    struct Secret;
+
+    impl PropertyWriteHandler for Secret {
+        fn set(&self, _: &Value) -> PropertySetResult {
+            Ok(())
+        }
+    }
+
+    let limit = Rc::new(Cell::new(0));
+    let iface = Interface::new()
+        .add_property("Limit",
+                      Property::new_rw("u", Box::new(Limited(limit.clone()))).unwrap())
+        .add_property("Secret", Property::new_wo("s", Box::new(Secret)).unwrap());
+    let changes = |iface: &Interface| {
+        iface._properties_changed("/org/example", "org.example.Iface").map(|msg| {
+            let (_, changed, invalidated) = _read_properties_changed(msg);
+            let changed = changed.into_iter()
+                .map(|(name, value)| {
+                    match value {
+                        Value::BasicValue(BasicValue::Uint32(value)) => (name, value),
+                        value => panic!("unexpected value: {:?}", value),
+                    }
+                })
+                .collect::<Vec<_>>();
+            (changed, invalidated)
+        })
+    };
+
+    // The value read back from the property is announced rather than the requested one.
+    assert!(iface.set_property_value("Limit", &Value::BasicValue(BasicValue::Uint32(20))).is_ok());
+    assert_eq!(limit.get(), 10);
+    assert_eq!(changes(&iface), Some((vec![("Limit".to_string(), 10)], vec![])));
+
+    let secret = Value::BasicValue(BasicValue::String("a".to_string()));
+    assert!(iface.set_property_value("Secret", &secret).is_ok());
+    assert_eq!(changes(&iface), Some((vec![], vec!["Secret".to_string()])));
+
+    // Changes to unknown properties or with the wrong type are dropped.
+    let notifier = iface.property_notifier();
+    notifier.changed("Missing", Value::BasicValue(BasicValue::Uint32(1)));
+    notifier.changed("Limit", Value::BasicValue(BasicValue::String("a".to_string())));
+    assert_eq!(changes(&iface), None);
+}
+
+#[test]
+fn test_pending_reply() {
+    use connection::test_connection;
+    use message::MessageType;
+
+    let (conn, bus) = test_connection(false, |_| vec![]);
+    let conn = Rc::new(conn);
+    let pending = Rc::new(RefCell::new(vec![]));
+    let children = Rc::new(RefCell::new(vec![]));
+    let stored = pending.clone();
+    let defer = Method::new_deferred(move |_, _, reply| stored.borrow_mut().push(reply))
+        .add_result(Argument::new("value", "u").unwrap());
+    let ifaces = Interfaces::new()
+        .add_interface("org.example.Iface", Interface::new().add_method("Defer", defer))
+        .unwrap()
+        .finalize(&children)
+        .unwrap();
+
+    for serial in 1..5 {
+        let mut msg = Message::new_method_call("org.example.Test",
+                                               "/org/example",
+                                               "org.example.Iface",
+                                               "Defer");
+        msg.message.serial = serial;
+        assert_eq!(ifaces.handle(&conn, &mut msg), Some(Ok(())));
+    }
+
+    // Nothing is sent until the replies are finished.
+    let mut replies = pending.borrow_mut().drain(..).collect::<Vec<_>>();
+    assert_eq!(replies.iter().map(PendingReply::serial).collect::<Vec<_>>(),
+               vec![1, 2, 3, 4]);
+    let last = replies.pop().unwrap();
+    let mut replies = replies.into_iter();
+
+    replies.next().unwrap().reply(vec![Value::BasicValue(BasicValue::Uint32(5))]).unwrap();
+    replies.next()
+        .unwrap()
+        .error(ErrorMessage::new("org.example.Error.Busy", "busy"))
+        .unwrap();
+    // Dropping the reply sends an error instead.
+    drop(replies.next().unwrap());
+
+    drop(ifaces);
+    drop(conn);
+    match *last.reply(vec![Value::BasicValue(BasicValue::Uint32(0))]).unwrap_err().kind() {
+        ErrorKind::Disconnected => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    let messages = bus.join().unwrap();
+    assert_eq!(messages.len(), 3);
+    let check = |msg: &Message, serial: u32, error: bool, value: Value| {
+        match (msg.message_type(), error) {
+            (MessageType::MethodReturn, false) |
+            (MessageType::Error, true) => (),
+            _ => panic!("unexpected reply: {:?}", msg),
+        }
+        assert_eq!(msg.reply_serial(), Some(serial));
+
+        let values = msg.values().unwrap().unwrap();
+        assert_eq!(values.len(), 1);
+        match (&values[0], &value) {
+            (&Value::BasicValue(BasicValue::Uint32(a)),
+             &Value::BasicValue(BasicValue::Uint32(b))) => assert_eq!(a, b),
+            (&Value::BasicValue(BasicValue::String(ref a)),
+             &Value::BasicValue(BasicValue::String(ref b))) => assert_eq!(a, b),
+            (actual, _) => panic!("unexpected value: {:?}", actual),
+        }
+    };
+
+    check(&messages[0], 1, false, Value::BasicValue(BasicValue::Uint32(5)));
+    check(&messages[1], 2, true, Value::BasicValue(BasicValue::String("busy".to_string())));
+    check(&messages[2],
+          3,
+          true,
+          Value::BasicValue(BasicValue::String("the method did not send a reply".to_string())));
+}
+
+#[test]
+fn test_invalid_signatures() {
+    struct Constant;
+
+    impl PropertyReadHandler for Constant {
+        fn get(&self) -> PropertyGetResult {
+            Ok(Value::BasicValue(BasicValue::Uint32(0)))
+        }
+    }
+
+    assert!(Argument::new("valid", "a{sv}").is_ok());
+    assert!(Argument::new("multiple", "ss").is_err());
+    assert!(Argument::new("unterminated", "a{sv").is_err());
+    assert!(Argument::new("empty", "").is_err());
+
+    assert!(Property::new_ro("u", Box::new(Constant)).is_ok());
+    assert!(Property::new_ro("uu", Box::new(Constant)).is_err());
+}
+
+#[test]
+fn test_reply_fds() {
+    use connection::test_connection;
+    use message::MessageType;
+
+    use std::io::{Read, Write};
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+    use std::os::unix::net::UnixStream;
+
+    let (conn, bus) = test_connection(true, |_| vec![]);
+    let conn = Rc::new(conn);
+    let (local, remote) = UnixStream::pair().unwrap();
+    {
+        let remote = RefCell::new(Some(UnixFd::new(remote)));
+        let take_device = Method::new(move |ctx, _| {
+                let fd = remote.borrow_mut().take().unwrap();
+                Ok(vec![ctx.attach_fd(fd), Value::BasicValue(BasicValue::Boolean(false))])
+            })
+            .add_argument(Argument::new("major", "u").unwrap())
+            .add_argument(Argument::new("minor", "u").unwrap())
+            .add_result(Argument::new("fd", "h").unwrap())
+            .add_result(Argument::new("inactive", "b").unwrap());
+        let iface = Interface::new().add_method("TakeDevice", take_device);
+        let children = Rc::new(RefCell::new(vec![]));
+        let ifaces = Interfaces::new()
+            .add_interface("org.example.Session", iface)
+            .unwrap()
+            .finalize(&children)
+            .unwrap();
+
+        let mut msg = Message::new_method_call("org.example.Test",
+                                               "/org/example",
+                                               "org.example.Session",
+                                               "TakeDevice")
+            .add_argument(&13u32)
+            .add_argument(&64u32);
+        msg.message.serial = 1;
+        assert_eq!(ifaces.handle(&conn, &mut msg), Some(Ok(())));
+    }
+    drop(conn);
+
+    let replies = bus.join().unwrap();
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].message_type(), MessageType::MethodReturn);
+    assert_eq!(replies[0].signature(), "hb");
+    assert_eq!(replies[0].unix_fds(), 1);
+    assert_eq!(replies[0].read::<(u32, bool)>().unwrap(), (0, false));
+
+    let fd = replies[0].take_fd(0).unwrap();
+    let mut received = unsafe { UnixStream::from_raw_fd(fd.into_raw_fd()) };
+    received.write_all(b"ping").unwrap();
+    drop(received);
+
+    let mut data = String::new();
+    (&local).read_to_string(&mut data).unwrap();
+    assert_eq!(data, "ping");
+}
@@ -21,47 +21,109 @@ mod crates {
     pub extern crate core;
     pub extern crate dbus_bytestream;
     pub extern crate dbus_serialize;
+    pub extern crate futures_core;
+    pub extern crate libc;
     pub extern crate machine_id;
+    pub extern crate sha1;
 }
 
+mod address;
 mod arguments;
+mod async_connection;
+mod auth;
 mod connection;
+mod context;
+mod convert;
 mod error;
+mod executor;
+mod fd;
 mod interface;
+mod listener;
+mod match_rule;
 mod message;
+mod names;
 mod object;
+mod object_manager;
+mod property_cache;
+mod proxy;
 mod runner;
 mod server;
+mod signature;
 mod target;
+mod transport;
 mod value;
 
+pub use address::Address;
+pub use arguments::Arguments;
+pub use async_connection::AsyncConnection;
+pub use async_connection::MessageStream;
+pub use async_connection::ReplyFuture;
+pub use auth::Anonymous;
+pub use auth::AuthMechanism;
+pub use auth::Authenticator;
+pub use auth::CookieSha1;
+pub use auth::External;
 pub use connection::Connection;
 pub use connection::ReleaseNameReply;
 pub use connection::RequestNameFlags;
 pub use connection::{ALLOW_REPLACEMENT, REPLACE_EXISTING, DO_NOT_QUEUE};
 pub use connection::RequestNameReply;
+pub use connection::StartServiceReply;
+pub use context::Credentials;
+pub use context::MethodContext;
+pub use context::SignalContext;
+pub use convert::BasicType;
+pub use convert::DBusArgs;
+pub use convert::DBusType;
+pub use convert::FromDBus;
+pub use convert::FromDBusArgs;
+pub use convert::ToDBus;
+pub use convert::ToDBusArgs;
 pub use error::Error;
+pub use fd::UnixFd;
 pub use interface::Annotation;
 pub use interface::Argument;
+pub use interface::AsyncMethodHandler;
 pub use interface::ChildrenList;
+pub use interface::DeferredMethodHandler;
 pub use interface::ErrorMessage;
 pub use interface::Interface;
 pub use interface::Interfaces;
 pub use interface::InterfacesBuilder;
 pub use interface::Method;
+pub use interface::MethodFuture;
 pub use interface::MethodHandler;
 pub use interface::MethodResult;
+pub use interface::PendingReply;
 pub use interface::Property;
 pub use interface::PropertyGetResult;
+pub use interface::PropertyNotifier;
 pub use interface::PropertyReadHandler;
 pub use interface::PropertyReadWriteHandler;
 pub use interface::PropertySetResult;
 pub use interface::PropertyWriteHandler;
 pub use interface::Signal;
+pub use listener::Listener;
+pub use match_rule::MatchRule;
 pub use message::Message;
+pub use message::MessageArgument;
+pub use message::MessageFlags;
+pub use message::{ALLOW_INTERACTIVE_AUTHORIZATION, NO_AUTO_START, NO_REPLY_EXPECTED};
 pub use message::MessageType;
+pub use names::BusName;
+pub use names::ErrorName;
+pub use names::InterfaceName;
+pub use names::IntoName;
+pub use names::MemberName;
+pub use names::ObjectPath;
 pub use object::Object;
+pub use object_manager::ObjectManagerClient;
+pub use property_cache::PropertyCache;
+pub use proxy::Proxy;
 pub use runner::Runner;
 pub use server::Server;
+pub use server::SubscriptionHandle;
+pub use signature::IntoSig;
+pub use signature::Sig;
 pub use target::Target;
 pub use value::*;
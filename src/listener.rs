@@ -0,0 +1,198 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use crates::libc;
+
+use address::Address;
+use auth::{AuthPolicy, random_hex};
+use connection::Connection;
+use error::*;
+#[cfg(target_os = "linux")]
+use fd;
+use transport::Transport;
+
+use std::fs;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long a client may take to authenticate, in seconds.
+const AUTH_TIMEOUT: u64 = 30;
+
+#[cfg(target_os = "linux")]
+fn _bind_abstract(name: &str) -> Result<UnixListener> {
+    Ok(fd::bind_abstract(name)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn _bind_abstract(name: &str) -> Result<UnixListener> {
+    bail!(ErrorKind::InvalidAddress(format!("unix:abstract={}", name),
+                                    "abstract sockets are not supported".to_string()))
+}
+
+/// A socket which accepts connections directly from peers without a bus.
+///
+/// Each accepted connection is authenticated using the `EXTERNAL` mechanism. By default, only
+/// processes running as the same user as the listener are accepted. Anonymous clients may be
+/// allowed as well.
+///
+/// Peers have no bus names and there is no bus to route signals, so an accepted connection is
+/// handled by its own `Runner` using a server created with `Runner::add_peer_server`:
+///
+/// ```rust,no_run
+/// # use rust_bus::{Listener, Runner};
+/// let listener = Listener::bind("unix:tmpdir=/tmp").unwrap();
+/// println!("listening on {}", listener.address());
+///
+/// let conn = listener.accept().unwrap();
+/// let mut runner = Runner::new(conn).unwrap();
+/// runner.add_peer_server().unwrap();
+/// runner.run();
+/// ```
+pub struct Listener {
+    listener: UnixListener,
+    address: Address,
+    guid: String,
+    path: Option<PathBuf>,
+    policy: AuthPolicy,
+}
+
+impl Listener {
+    /// Listen on the first usable address in a `;`-separated list of addresses.
+    ///
+    /// Only `unix` addresses are supported. A `tmpdir` or `dir` address listens on a new socket
+    /// within the directory.
+    pub fn bind(addresses: &str) -> Result<Self> {
+        let mut last_err = None;
+
+        for address in Address::parse_list(addresses)? {
+            match Self::_bind(&address) {
+                Ok(listener) => return Ok(listener),
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        // Parsing guarantees at least one address was tried.
+        Err(last_err.unwrap())
+    }
+
+    fn _bind(address: &Address) -> Result<Self> {
+        if address.transport() != "unix" {
+            bail!(ErrorKind::InvalidAddress(address.to_string(),
+                                            format!("unsupported transport '{}'",
+                                                    address.transport())));
+        }
+
+        let (listener, connect_address, path) = if let Some(path) = address.get("path") {
+            (UnixListener::bind(path)?,
+             Address::new("unix").param("path", path),
+             Some(PathBuf::from(path)))
+        } else if let Some(name) = address.get("abstract") {
+            (_bind_abstract(name)?, Address::new("unix").param("abstract", name), None)
+        } else if let Some(dir) = address.get("tmpdir").or_else(|| address.get("dir")) {
+            let path = Path::new(dir).join(format!("dbus-{}", random_hex(8)?));
+            let path_str = path.to_str()
+                .ok_or_else(|| {
+                    ErrorKind::InvalidAddress(address.to_string(), "non-UTF-8 path".to_string())
+                })?
+                .to_string();
+
+            (UnixListener::bind(&path)?, Address::new("unix").param("path", path_str), Some(path))
+        } else {
+            bail!(ErrorKind::InvalidAddress(address.to_string(),
+                                            "missing 'path', 'abstract', 'tmpdir', or 'dir' \
+                                             parameter"
+                                                .to_string()))
+        };
+
+        let guid = match address.get("guid") {
+            Some(guid) => guid.to_string(),
+            None => random_hex(16)?,
+        };
+
+        Ok(Listener {
+            listener: listener,
+            address: connect_address.param("guid", &guid),
+            guid: guid,
+            path: path,
+            policy: AuthPolicy {
+                allowed_users: vec![unsafe { libc::getuid() }],
+                allow_anonymous: false,
+                unix_fds: true,
+            },
+        })
+    }
+
+    /// The address clients may use to connect to the listener.
+    pub fn address(&self) -> String {
+        self.address.to_string()
+    }
+
+    /// The GUID of the server, sent to clients when they authenticate.
+    pub fn guid(&self) -> &str {
+        &self.guid
+    }
+
+    /// Accept connections from processes running as the given user.
+    pub fn allow_user(&mut self, uid: u32) -> &mut Self {
+        if !self.policy.allowed_users.contains(&uid) {
+            self.policy.allowed_users.push(uid);
+        }
+
+        self
+    }
+
+    /// Accept connections from clients using the `ANONYMOUS` mechanism.
+    pub fn allow_anonymous(&mut self, allow: bool) -> &mut Self {
+        self.policy.allow_anonymous = allow;
+
+        self
+    }
+
+    /// Wait for a peer to connect and authenticate.
+    ///
+    /// The returned connection is a peer connection; see `Connection::is_peer`.
+    pub fn accept(&self) -> Result<Connection> {
+        let (stream, _) = self.listener.accept()?;
+        // Keep a client which stalls while authenticating from blocking the listener forever.
+        stream.set_read_timeout(Some(Duration::from_secs(AUTH_TIMEOUT)))?;
+        let transport = Transport::accept(stream, &self.guid, &self.policy)?;
+
+        Connection::new_peer(transport)
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.path {
+            if let Err(err) = fs::remove_file(path) {
+                println!("failed to remove the socket {}: {:?}", path.display(), err);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_listener_address() {
+    let listener = Listener::bind("unix:tmpdir=/tmp,guid=0123456789abcdef0123456789abcdef")
+        .unwrap();
+    let address = Address::parse(&listener.address()).unwrap();
+
+    assert_eq!(listener.guid(), "0123456789abcdef0123456789abcdef");
+    assert_eq!(address.get("guid"), Some(listener.guid()));
+    assert!(address.get("path").unwrap().starts_with("/tmp/dbus-"));
+
+    let path = address.get("path").unwrap().to_string();
+    assert!(Path::new(&path).exists());
+    drop(listener);
+    assert!(!Path::new(&path).exists());
+
+    assert!(Listener::bind("tcp:host=localhost,port=0").is_err());
+}
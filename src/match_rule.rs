@@ -0,0 +1,317 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use message::{Message, MessageType};
+use value::{BasicValue, Path, Value};
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The number of arguments which may be matched against.
+const MAX_MATCH_ARGS: u8 = 64;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// A rule for selecting messages routed by the bus.
+///
+/// Rules are sent to the bus using `Connection::add_match` and may also be tested against
+/// messages locally. The rule syntax is documented in the [D-Bus
+/// specification](https://dbus.freedesktop.org/doc/dbus-specification.html#message-bus-routing-match-rules).
+pub struct MatchRule {
+    message_type: Option<MessageType>,
+    sender: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    path: Option<String>,
+    path_namespace: Option<String>,
+    destination: Option<String>,
+    args: BTreeMap<u8, String>,
+    arg_paths: BTreeMap<u8, String>,
+    arg0_namespace: Option<String>,
+    eavesdrop: bool,
+}
+
+impl MatchRule {
+    /// Create a rule which matches all messages.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a rule which matches signals.
+    pub fn new_signal() -> Self {
+        Self::new().message_type(MessageType::Signal)
+    }
+
+    /// Match messages of the given type.
+    pub fn message_type(mut self, message_type: MessageType) -> Self {
+        self.message_type = Some(message_type);
+
+        self
+    }
+
+    /// Match messages sent by the given bus name.
+    ///
+    /// Note that when testing messages locally, the sender of the message is compared directly.
+    /// The bus also matches messages sent by the owner of a well-known name.
+    pub fn sender<N>(mut self, sender: N) -> Self
+        where N: ToString,
+    {
+        self.sender = Some(sender.to_string());
+
+        self
+    }
+
+    /// Match messages for the given interface.
+    pub fn interface<N>(mut self, interface: N) -> Self
+        where N: ToString,
+    {
+        self.interface = Some(interface.to_string());
+
+        self
+    }
+
+    /// Match messages for the given method or signal name.
+    pub fn member<N>(mut self, member: N) -> Self
+        where N: ToString,
+    {
+        self.member = Some(member.to_string());
+
+        self
+    }
+
+    /// Match messages for the given object path.
+    pub fn path<P>(mut self, path: P) -> Self
+        where P: ToString,
+    {
+        self.path = Some(path.to_string());
+
+        self
+    }
+
+    /// Match messages for the given object path or any path underneath it.
+    pub fn path_namespace<P>(mut self, path: P) -> Self
+        where P: ToString,
+    {
+        self.path_namespace = Some(path.to_string());
+
+        self
+    }
+
+    /// Match messages addressed to the given unique name.
+    pub fn destination<N>(mut self, destination: N) -> Self
+        where N: ToString,
+    {
+        self.destination = Some(destination.to_string());
+
+        self
+    }
+
+    /// Match messages where the given argument is a string with the given value.
+    ///
+    /// # Panics
+    ///
+    /// Only the first 64 arguments may be matched against.
+    pub fn arg<V>(mut self, index: u8, value: V) -> Self
+        where V: ToString,
+    {
+        assert!(index < MAX_MATCH_ARGS, "argument index too large: {}", index);
+        self.args.insert(index, value.to_string());
+
+        self
+    }
+
+    /// Match messages where the given argument is a path related to the given path.
+    ///
+    /// The argument matches if it is equal to the path or if either ends with `/` and is a prefix
+    /// of the other.
+    ///
+    /// # Panics
+    ///
+    /// Only the first 64 arguments may be matched against.
+    pub fn arg_path<P>(mut self, index: u8, path: P) -> Self
+        where P: ToString,
+    {
+        assert!(index < MAX_MATCH_ARGS, "argument index too large: {}", index);
+        self.arg_paths.insert(index, path.to_string());
+
+        self
+    }
+
+    /// Match messages where the first argument is a bus name in the given namespace.
+    pub fn arg0_namespace<N>(mut self, namespace: N) -> Self
+        where N: ToString,
+    {
+        self.arg0_namespace = Some(namespace.to_string());
+
+        self
+    }
+
+    /// Request messages which are not addressed to the connection as well.
+    pub fn eavesdrop(mut self, eavesdrop: bool) -> Self {
+        self.eavesdrop = eavesdrop;
+
+        self
+    }
+
+    /// Test whether a message matches the rule.
+    pub fn matches(&self, msg: &Message) -> bool {
+        if let Some(ref message_type) = self.message_type {
+            if *message_type != msg.message_type() {
+                return false;
+            }
+        }
+
+        if !(_matches_header(&self.sender, msg.sender()) &&
+             _matches_header(&self.interface, msg.interface()) &&
+             _matches_header(&self.member, msg.member()) &&
+             _matches_header(&self.path, msg.path()) &&
+             _matches_header(&self.destination, msg.destination())) {
+            return false;
+        }
+
+        if let Some(ref namespace) = self.path_namespace {
+            let matched = msg.path().map_or(false, |path| {
+                namespace == "/" || path == *namespace ||
+                path.starts_with(&format!("{}/", namespace))
+            });
+            if !matched {
+                return false;
+            }
+        }
+
+        if self.args.is_empty() && self.arg_paths.is_empty() && self.arg0_namespace.is_none() {
+            return true;
+        }
+
+        let values = match msg.values() {
+            Ok(Some(values)) => values,
+            _ => vec![],
+        };
+        let arg = |index: u8| values.get(index as usize);
+
+        let args_match = self.args.iter().all(|(&index, expected)| {
+            match arg(index) {
+                Some(&Value::BasicValue(BasicValue::String(ref actual))) => actual == expected,
+                _ => false,
+            }
+        });
+        let arg_paths_match = self.arg_paths.iter().all(|(&index, expected)| {
+            match arg(index) {
+                Some(&Value::BasicValue(BasicValue::String(ref actual))) |
+                Some(&Value::BasicValue(BasicValue::ObjectPath(Path(ref actual)))) => {
+                    _path_related(actual, expected)
+                },
+                _ => false,
+            }
+        });
+        let arg0_namespace_match = self.arg0_namespace.as_ref().map_or(true, |namespace| {
+            match arg(0) {
+                Some(&Value::BasicValue(BasicValue::String(ref actual))) => {
+                    actual == namespace || actual.starts_with(&format!("{}.", namespace))
+                },
+                _ => false,
+            }
+        });
+
+        args_match && arg_paths_match && arg0_namespace_match
+    }
+}
+
+fn _matches_header(expected: &Option<String>, actual: Option<String>) -> bool {
+    expected.as_ref().map_or(true, |expected| actual.as_ref() == Some(expected))
+}
+
+fn _path_related(actual: &str, expected: &str) -> bool {
+    actual == expected || (actual.ends_with('/') && expected.starts_with(actual)) ||
+    (expected.ends_with('/') && actual.starts_with(expected))
+}
+
+fn _write_pair(f: &mut fmt::Formatter, first: &mut bool, key: &str, value: &str) -> fmt::Result {
+    if !*first {
+        write!(f, ",")?;
+    }
+    *first = false;
+
+    // Apostrophes cannot appear within quotes and must be escaped outside of them instead.
+    write!(f, "{}='{}'", key, value.replace('\'', r"'\''"))
+}
+
+impl fmt::Display for MatchRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+
+        if let Some(ref message_type) = self.message_type {
+            let type_name = match *message_type {
+                MessageType::MethodCall => "method_call",
+                MessageType::MethodReturn => "method_return",
+                MessageType::Error => "error",
+                MessageType::Signal => "signal",
+                MessageType::Invalid => "invalid",
+            };
+            _write_pair(f, &mut first, "type", type_name)?;
+        }
+
+        let headers = [("sender", &self.sender),
+                       ("interface", &self.interface),
+                       ("member", &self.member),
+                       ("path", &self.path),
+                       ("path_namespace", &self.path_namespace),
+                       ("destination", &self.destination)];
+        for &(key, value) in &headers {
+            if let Some(ref value) = *value {
+                _write_pair(f, &mut first, key, value)?;
+            }
+        }
+
+        for (index, value) in &self.args {
+            _write_pair(f, &mut first, &format!("arg{}", index), value)?;
+        }
+        for (index, value) in &self.arg_paths {
+            _write_pair(f, &mut first, &format!("arg{}path", index), value)?;
+        }
+        if let Some(ref namespace) = self.arg0_namespace {
+            _write_pair(f, &mut first, "arg0namespace", namespace)?;
+        }
+        if self.eavesdrop {
+            _write_pair(f, &mut first, "eavesdrop", "true")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_match_rule_string() {
+    let rule = MatchRule::new_signal()
+        .sender("org.freedesktop.DBus")
+        .interface("org.freedesktop.DBus")
+        .member("NameOwnerChanged")
+        .arg(0, "it's")
+        .arg_path(1, "/org/");
+
+    assert_eq!(rule.to_string(),
+               "type='signal',sender='org.freedesktop.DBus',interface='org.freedesktop.DBus',\
+                member='NameOwnerChanged',arg0='it'\\''s',arg1path='/org/'");
+    assert_eq!(MatchRule::new().to_string(), "");
+}
+
+#[test]
+fn test_match_rule_matches() {
+    let msg = Message::new_signal("/org/example/Object", "org.example.Interface", "Changed")
+        .add_argument(&"org.example.Name")
+        .add_argument(&"/org/example/");
+
+    assert!(MatchRule::new().matches(&msg));
+    assert!(MatchRule::new_signal().interface("org.example.Interface").matches(&msg));
+    assert!(!MatchRule::new().message_type(MessageType::MethodCall).matches(&msg));
+    assert!(MatchRule::new().path_namespace("/org/example").matches(&msg));
+    assert!(MatchRule::new().path_namespace("/org/example/Object").matches(&msg));
+    assert!(!MatchRule::new().path_namespace("/org/ex").matches(&msg));
+    assert!(MatchRule::new().arg(0, "org.example.Name").matches(&msg));
+    assert!(!MatchRule::new().arg(0, "org.example").matches(&msg));
+    assert!(MatchRule::new().arg0_namespace("org.example").matches(&msg));
+    assert!(!MatchRule::new().arg0_namespace("org.ex").matches(&msg));
+    assert!(MatchRule::new().arg_path(1, "/org/example/Object").matches(&msg));
+    assert!(MatchRule::new().arg_path(1, "/").matches(&msg));
+    assert!(!MatchRule::new().arg_path(1, "/org/other").matches(&msg));
+    assert!(!MatchRule::new().arg(2, "missing").matches(&msg));
+}
@@ -1,20 +1,100 @@
 // Distributed under the OSI-approved BSD 3-Clause License.
 // See accompanying LICENSE file for details.
 
+use crates::dbus_bytestream::demarshal::demarshal;
 use crates::dbus_bytestream::message;
 use crates::dbus_serialize::types::Variant;
 
+use convert::FromDBusArgs;
 use error::*;
-use value::{BasicValue, Marshal, Value};
+use fd::{UnixFd, UnixFdIndex};
+use value::{BasicValue, Marshal, Signature, Struct, Value};
+
+use std::cell::RefCell;
+
+/// The header field holding the number of file descriptors sent with a message.
+const HEADER_FIELD_UNIX_FDS: u8 = 9;
+
+bitflags! {
+    /// Flags which may be set on a message.
+    pub flags MessageFlags: u8 {
+        /// The method call does not expect a reply and none should be sent.
+        const NO_REPLY_EXPECTED               = 0x1,
+        /// The bus should not start a service to handle the message if the destination does not
+        /// exist.
+        const NO_AUTO_START                   = 0x2,
+        /// The caller is prepared to wait for interactive authorization (e.g., a password
+        /// prompt) before the method call is handled.
+        const ALLOW_INTERACTIVE_AUTHORIZATION = 0x4,
+    }
+}
 
 #[derive(Debug)]
 /// A message to communicate on the D-Bus.
+///
+/// File descriptors sent with the message are owned by it and closed when it is dropped unless
+/// they are taken out of it first.
 pub struct Message {
     #[doc(hidden)]
     // This is used inside of the implementation, but should not be fully public.
     pub message: message::Message,
+    fds: RefCell<Vec<Option<UnixFd>>>,
+}
+
+/// A value which may be added to a message as an argument.
+///
+/// This is implemented for references to values which may be marshalled, including `&Marshal`
+/// trait objects, and for `UnixFd`, which is moved into the message.
+pub trait MessageArgument {
+    /// Add the value to the message.
+    fn add_to(self, msg: Message) -> Message;
+}
+
+impl<'a, T> MessageArgument for &'a T
+    where T: Marshal + ?Sized,
+{
+    fn add_to(self, msg: Message) -> Message {
+        Message {
+            message: msg.message.add_arg(&BorrowedArgument(self)),
+            fds: msg.fds,
+        }
+    }
+}
+
+/// Marshals a borrowed argument, which may be a `Marshal` trait object.
+struct BorrowedArgument<'a, T: ?Sized + 'a>(&'a T);
+
+impl<'a, T> Marshal for BorrowedArgument<'a, T>
+    where T: Marshal + ?Sized,
+{
+    fn dbus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        self.0.dbus_encode(buf)
+    }
+
+    fn get_type(&self) -> String {
+        self.0.get_type()
+    }
+}
+
+impl MessageArgument for UnixFd {
+    fn add_to(self, msg: Message) -> Message {
+        let index = {
+            let mut fds = msg.fds.borrow_mut();
+            fds.push(Some(self));
+            fds.len()
+        };
+
+        let mut msg = Message {
+            message: msg.message.add_arg(&UnixFdIndex(index as u32 - 1)),
+            fds: msg.fds,
+        };
+        msg.set_unix_fds(index as u32);
+
+        msg
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 /// The type of a message.
 pub enum MessageType {
     /// An error message.
@@ -32,46 +112,92 @@ pub enum MessageType {
 impl Message {
     /// Create a new message from the underlying data type.
     pub fn new(message: message::Message) -> Self {
+        Self::new_with_fds(message, vec![])
+    }
+
+    /// Create a new message from the underlying data type and the file descriptors sent with it.
+    pub fn new_with_fds(message: message::Message, fds: Vec<UnixFd>) -> Self {
         Message {
             message: message,
+            fds: RefCell::new(fds.into_iter().map(Some).collect()),
         }
     }
 
     /// Create a call to a method.
     pub fn new_method_call(dest: &str, path: &str, iface: &str, method: &str) -> Self {
-        Message {
-            message: message::create_method_call(dest, path, iface, method),
-        }
+        Self::new(message::create_method_call(dest, path, iface, method))
     }
 
     /// Create a signal message.
     pub fn new_signal(path: &str, iface: &str, method: &str) -> Self {
-        Message {
-            message: message::create_signal(path, iface, method),
-        }
+        Self::new(message::create_signal(path, iface, method))
+    }
+
+    /// Create a return message for the method call with the given serial.
+    pub fn new_method_return(reply_serial: u32) -> Self {
+        Self::new(message::create_method_return(reply_serial))
+    }
+
+    /// Create an error message for the method call with the given serial.
+    pub fn new_error(name: &str, reply_serial: u32) -> Self {
+        Self::new(message::create_error(name, reply_serial))
     }
 
     /// Create an error message.
+    ///
+    /// The error is addressed to the sender of the current message.
     pub fn error_message(&self, name: &str) -> Self {
-        Message {
-            message: message::create_error(name, self.message.serial),
-        }
+        self._reply_to(Self::new(message::create_error(name, self.message.serial)))
     }
 
     /// Create a message which is a return value for the current message.
     ///
-    /// This is used so that the return value is associated with the method call message.
+    /// This is used so that the return value is associated with the method call message. The
+    /// return value is addressed to the sender of the current message.
     pub fn return_message(&self) -> Self {
-        Message {
-            message: message::create_method_return(self.message.serial),
+        self._reply_to(Self::new(message::create_method_return(self.message.serial)))
+    }
+
+    fn _reply_to(&self, mut reply: Self) -> Self {
+        if let Some(sender) = self.sender() {
+            reply.set_destination(&sender);
         }
+
+        reply
     }
 
     /// Add an argument to the message.
-    pub fn add_argument(self, arg: &Marshal) -> Self {
-        Message {
-            message: self.message.add_arg(arg),
-        }
+    ///
+    /// A `UnixFd` argument is moved into the message and sent along with it.
+    pub fn add_argument<A>(self, arg: A) -> Self
+        where A: MessageArgument,
+    {
+        arg.add_to(self)
+    }
+
+    /// Take a file descriptor out of the message.
+    ///
+    /// The index is the value of an `h` argument. Returns `None` if there is no such file
+    /// descriptor or it has already been taken.
+    pub fn take_fd(&self, index: u32) -> Option<UnixFd> {
+        self.fds
+            .borrow_mut()
+            .get_mut(index as usize)
+            .and_then(Option::take)
+    }
+
+    /// Take all of the file descriptors out of the message.
+    ///
+    /// Descriptors which have already been taken are `None`.
+    pub fn take_fds(&self) -> Vec<Option<UnixFd>> {
+        self.fds.borrow_mut().drain(..).collect()
+    }
+
+    #[doc(hidden)]
+    /// Split the message into the underlying data type and its file descriptors.
+    pub fn into_parts(self) -> (message::Message, Vec<UnixFd>) {
+        let fds = self.fds.into_inner().into_iter().filter_map(|fd| fd).collect();
+        (self.message, fds)
     }
 
     /// The type of the message.
@@ -85,6 +211,32 @@ impl Message {
         }
     }
 
+    /// The flags set on the message.
+    pub fn flags(&self) -> MessageFlags {
+        MessageFlags::from_bits_truncate(self.message.flags)
+    }
+
+    /// Set the flags of the message.
+    pub fn set_flags(&mut self, flags: MessageFlags) -> &mut Self {
+        self.message.flags = flags.bits;
+
+        self
+    }
+
+    /// The serial of the message.
+    ///
+    /// Serials are assigned by the connection when the message is sent.
+    pub fn serial(&self) -> u32 {
+        self.message.serial
+    }
+
+    /// Set the serial of the message.
+    pub fn set_serial(&mut self, serial: u32) -> &mut Self {
+        self.message.serial = serial;
+
+        self
+    }
+
     fn _extract_string(v: &Variant) -> Option<String> {
         if let Value::BasicValue(BasicValue::String(ref s)) = *v.object {
             Some(s.clone())
@@ -98,6 +250,30 @@ impl Message {
             .and_then(Self::_extract_string)
     }
 
+    fn _get_header_u32(message: &message::Message, header: u8) -> Option<u32> {
+        message.get_header(header)
+            .and_then(|v| {
+                match *v.object {
+                    Value::BasicValue(BasicValue::Uint32(u)) => Some(u),
+                    _ => None,
+                }
+            })
+    }
+
+    fn _set_header(&mut self, header: u8, value: BasicValue, signature: &str) {
+        self.message.headers.retain(|field| {
+            match field.objects.first() {
+                Some(&Value::BasicValue(BasicValue::Byte(code))) => code != header,
+                _ => true,
+            }
+        });
+        self.message.headers.push(Struct {
+            objects: vec![Value::BasicValue(BasicValue::Byte(header)),
+                          Value::Variant(Variant::new(Value::BasicValue(value), signature))],
+            signature: Signature("(yv)".to_string()),
+        });
+    }
+
     /// The interface the message is destined for.
     pub fn interface(&self) -> Option<String> {
         Self::_get_header_string(&self.message, message::HEADER_FIELD_INTERFACE)
@@ -113,9 +289,218 @@ impl Message {
         Self::_get_header_string(&self.message, message::HEADER_FIELD_MEMBER)
     }
 
+    /// The name of the error for error messages.
+    pub fn error_name(&self) -> Option<String> {
+        Self::_get_header_string(&self.message, message::HEADER_FIELD_ERROR_NAME)
+    }
+
+    /// Set the name of the error.
+    pub fn set_error_name(&mut self, name: &str) -> &mut Self {
+        self._set_header(message::HEADER_FIELD_ERROR_NAME,
+                         BasicValue::String(name.to_string()),
+                         "s");
+
+        self
+    }
+
+    /// The name of the connection the message is addressed to.
+    pub fn destination(&self) -> Option<String> {
+        Self::_get_header_string(&self.message, message::HEADER_FIELD_DESTINATION)
+    }
+
+    /// Set the name of the connection the message is addressed to.
+    pub fn set_destination(&mut self, destination: &str) -> &mut Self {
+        self._set_header(message::HEADER_FIELD_DESTINATION,
+                         BasicValue::String(destination.to_string()),
+                         "s");
+
+        self
+    }
+
+    /// The unique name of the connection which sent the message.
+    pub fn sender(&self) -> Option<String> {
+        Self::_get_header_string(&self.message, message::HEADER_FIELD_SENDER)
+    }
+
+    /// Set the unique name of the connection which sent the message.
+    ///
+    /// The bus overwrites the sender of messages sent through it; this is only meaningful on
+    /// peer connections.
+    pub fn set_sender(&mut self, sender: &str) -> &mut Self {
+        self._set_header(message::HEADER_FIELD_SENDER,
+                         BasicValue::String(sender.to_string()),
+                         "s");
+
+        self
+    }
+
+    /// The serial of the method call this message is a reply to.
+    pub fn reply_serial(&self) -> Option<u32> {
+        Self::_get_header_u32(&self.message, message::HEADER_FIELD_REPLY_SERIAL)
+    }
+
+    /// Set the serial of the method call this message is a reply to.
+    pub fn set_reply_serial(&mut self, serial: u32) -> &mut Self {
+        self._set_header(message::HEADER_FIELD_REPLY_SERIAL, BasicValue::Uint32(serial), "u");
+
+        self
+    }
+
+    /// The signature of the message body.
+    pub fn signature(&self) -> String {
+        self.message
+            .get_header(message::HEADER_FIELD_SIGNATURE)
+            .and_then(|v| {
+                match *v.object {
+                    Value::BasicValue(BasicValue::Signature(Signature(ref sig))) => {
+                        Some(sig.clone())
+                    },
+                    _ => None,
+                }
+            })
+            .unwrap_or_default()
+    }
+
+    /// Set the signature of the message body.
+    ///
+    /// The signature is kept up to date by `add_argument`; this should only be needed when
+    /// constructing a body manually.
+    pub fn set_signature(&mut self, signature: &str) -> &mut Self {
+        self._set_header(message::HEADER_FIELD_SIGNATURE,
+                         BasicValue::Signature(Signature(signature.to_string())),
+                         "g");
+
+        self
+    }
+
+    /// The number of file descriptors sent with the message.
+    pub fn unix_fds(&self) -> u32 {
+        Self::_get_header_u32(&self.message, HEADER_FIELD_UNIX_FDS).unwrap_or(0)
+    }
+
+    /// Set the number of file descriptors sent with the message.
+    ///
+    /// The count is kept up to date by `add_argument`; this should only be needed when
+    /// constructing a body manually.
+    pub fn set_unix_fds(&mut self, count: u32) -> &mut Self {
+        self._set_header(HEADER_FIELD_UNIX_FDS, BasicValue::Uint32(count), "u");
+
+        self
+    }
+
     /// Unpack the argument values stored within the message.
+    ///
+    /// File descriptor (`h`) arguments are unpacked as the `u32` index of the file descriptor
+    /// which may be passed to `take_fd`.
     pub fn values(&self) -> Result<Option<Vec<Value>>> {
-        self.message.get_body()
-            .map_err(|err| ErrorKind::ExtractArguments(err).into())
+        let signature = self.signature();
+        if !signature.contains('h') {
+            return self.message.get_body()
+                .map_err(|err| ErrorKind::ExtractArguments(err).into());
+        }
+
+        // File descriptor indices have the same representation as `u` values.
+        let mut sig = signature.replace('h', "u");
+        let mut body = self.message.body.clone();
+        let mut offset = 0;
+        let mut values = vec![];
+        while !sig.is_empty() {
+            values.push(demarshal(&mut body, &mut offset, &mut sig)
+                .map_err(ErrorKind::ExtractArguments)?);
+        }
+
+        Ok(Some(values))
+    }
+
+    /// Unpack the argument values stored within the message as Rust types.
+    ///
+    /// The arguments are read as a tuple (e.g., `msg.read::<(String, u32)>()`).
+    pub fn read<T>(&self) -> Result<T>
+        where T: FromDBusArgs,
+    {
+        let values = self.values()?.unwrap_or_default();
+
+        T::from_dbus_args(&values).ok_or_else(|| {
+            let actual = values.iter()
+                .map(|v| v.get_signature().to_string())
+                .collect::<Vec<_>>()
+                .join("");
+
+            ErrorKind::ArgumentMismatch(T::args_signature(), actual).into()
+        })
     }
 }
+
+#[test]
+fn test_headers() {
+    let mut msg = Message::new_method_call("org.example.Service",
+                                           "/org/example/Object",
+                                           "org.example.Interface",
+                                           "Method")
+        .add_argument(&"arg");
+    msg.set_sender(":1.42")
+        .set_serial(7)
+        .set_flags(NO_REPLY_EXPECTED | NO_AUTO_START);
+
+    assert_eq!(msg.destination(), Some("org.example.Service".to_string()));
+    assert_eq!(msg.sender(), Some(":1.42".to_string()));
+    assert_eq!(msg.signature(), "s");
+    assert_eq!(msg.serial(), 7);
+    assert_eq!(msg.unix_fds(), 0);
+    assert!(msg.flags().contains(NO_REPLY_EXPECTED));
+    assert!(!msg.flags().contains(ALLOW_INTERACTIVE_AUTHORIZATION));
+
+    let reply = msg.error_message("org.example.Error.Failed");
+    assert_eq!(reply.destination(), Some(":1.42".to_string()));
+    assert_eq!(reply.reply_serial(), Some(7));
+    assert_eq!(reply.error_name(), Some("org.example.Error.Failed".to_string()));
+
+    msg.set_destination("org.example.Other");
+    assert_eq!(msg.destination(), Some("org.example.Other".to_string()));
+}
+
+#[test]
+fn test_unix_fd_argument() {
+    use arguments::Arguments;
+    use transport::Transport;
+
+    use std::io::{Read, Write};
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+    use std::os::unix::net::UnixStream;
+
+    let (mut client, mut server) = Transport::pair();
+    let (local, remote) = UnixStream::pair().unwrap();
+
+    // Arguments may also be given as trait objects.
+    let name: &Marshal = &"pipe";
+    let mut msg = Message::new_method_call("org.example.Service",
+                                           "/org/example/Object",
+                                           "org.example.Interface",
+                                           "Method")
+        .add_argument(name)
+        .add_argument(UnixFd::new(remote));
+    msg.set_serial(1);
+    assert_eq!(msg.signature(), "sh");
+    assert_eq!(msg.unix_fds(), 1);
+
+    let (msg, fds) = msg.into_parts();
+    client.send(&msg, &fds).unwrap();
+    drop(fds);
+
+    let (msg, fds) = server.read_message(None).unwrap().unwrap();
+    let msg = Message::new_with_fds(msg, fds);
+    let args = Arguments::new(&msg).unwrap();
+    assert_eq!(args.extract_string(0).unwrap(), "pipe");
+    assert!(args.take_fd(0).is_err());
+
+    let fd = args.take_fd(1).unwrap();
+    assert!(args.take_fd(1).is_err());
+
+    let mut received = unsafe { UnixStream::from_raw_fd(fd.into_raw_fd()) };
+    received.write_all(b"ping").unwrap();
+    drop(received);
+
+    let mut data = String::new();
+    (&local).read_to_string(&mut data).unwrap();
+    assert_eq!(data, "ping");
+}
@@ -0,0 +1,268 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use crates::core::ops::Deref;
+
+use error::*;
+
+use std::fmt;
+
+/// The maximum length of a name on the bus.
+const MAX_NAME_LENGTH: usize = 255;
+
+type ValidateResult = ::std::result::Result<(), &'static str>;
+
+/// A trait for values which may be converted into a validated name.
+pub trait IntoName<T> {
+    /// Validate the value as a name.
+    fn into_name(self) -> Result<T>;
+}
+
+macro_rules! name_type {
+    ($(#[$attr:meta])* pub struct $name:ident; $validate:ident, $kind:ident) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(String);
+
+        impl $name {
+            /// Validate a name.
+            pub fn new<N>(name: N) -> Result<Self>
+                where N: ToString,
+            {
+                let name = name.to_string();
+                match $validate(&name) {
+                    Ok(()) => Ok($name(name)),
+                    Err(reason) => bail!(ErrorKind::$kind(name, reason.to_string())),
+                }
+            }
+
+            /// The name as a string.
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+
+            /// Extract the name as a string.
+            pub fn into_string(self) -> String {
+                self.0
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+
+        impl IntoName<$name> for $name {
+            fn into_name(self) -> Result<$name> {
+                Ok(self)
+            }
+        }
+
+        impl<'a> IntoName<$name> for &'a $name {
+            fn into_name(self) -> Result<$name> {
+                Ok(self.clone())
+            }
+        }
+
+        impl<'a> IntoName<$name> for &'a str {
+            fn into_name(self) -> Result<$name> {
+                $name::new(self)
+            }
+        }
+
+        impl<'a> IntoName<$name> for &'a String {
+            fn into_name(self) -> Result<$name> {
+                $name::new(self)
+            }
+        }
+
+        impl IntoName<$name> for String {
+            fn into_name(self) -> Result<$name> {
+                $name::new(self)
+            }
+        }
+    }
+}
+
+name_type! {
+    /// An object path on the bus (e.g., `/org/freedesktop/DBus`).
+    pub struct ObjectPath; validate_object_path, InvalidObjectPath
+}
+
+name_type! {
+    /// A bus name; either a unique name (e.g., `:1.42`) or a well-known name (e.g.,
+    /// `org.freedesktop.DBus`).
+    pub struct BusName; validate_bus_name, InvalidBusName
+}
+
+name_type! {
+    /// The name of an interface (e.g., `org.freedesktop.DBus.Peer`).
+    pub struct InterfaceName; validate_interface_name, InvalidInterfaceName
+}
+
+name_type! {
+    /// The name of a method, signal, or property (e.g., `GetMachineId`).
+    pub struct MemberName; validate_member_name, InvalidMemberName
+}
+
+name_type! {
+    /// The name of an error (e.g., `org.freedesktop.DBus.Error.Failed`).
+    pub struct ErrorName; validate_error_name, InvalidErrorName
+}
+
+impl ObjectPath {
+    /// Whether the path is the given path or underneath it.
+    pub fn starts_with_path(&self, path: &ObjectPath) -> bool {
+        path.0 == "/" || self.0 == path.0 || self.0.starts_with(&format!("{}/", path.0))
+    }
+}
+
+impl BusName {
+    /// Whether the name is a unique connection name assigned by the bus.
+    pub fn is_unique(&self) -> bool {
+        self.0.starts_with(':')
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii() && (c.is_alphanumeric() || c == '_')
+}
+
+fn validate_length(name: &str) -> ValidateResult {
+    if name.is_empty() {
+        Err("empty")
+    } else if name.len() > MAX_NAME_LENGTH {
+        Err("longer than 255 characters")
+    } else {
+        Ok(())
+    }
+}
+
+fn validate_object_path(path: &str) -> ValidateResult {
+    if !path.starts_with('/') {
+        return Err("must begin with '/'");
+    }
+    if path == "/" {
+        return Ok(());
+    }
+    if path.ends_with('/') {
+        return Err("must not end with '/'");
+    }
+
+    path[1..].split('/').fold(Ok(()), |res, element| {
+        res.and_then(|_| {
+            if element.is_empty() {
+                Err("empty path element")
+            } else if !element.chars().all(is_name_char) {
+                Err("path elements may only contain [A-Za-z0-9_]")
+            } else {
+                Ok(())
+            }
+        })
+    })
+}
+
+fn validate_dotted_name(name: &str, extra: &[char], allow_leading_digit: bool) -> ValidateResult {
+    validate_length(name)?;
+
+    let elements = name.split('.').collect::<Vec<_>>();
+    if elements.len() < 2 {
+        return Err("must contain at least two elements");
+    }
+
+    elements.iter().fold(Ok(()), |res, element| {
+        res.and_then(|_| {
+            if element.is_empty() {
+                Err("empty element")
+            } else if !allow_leading_digit && element.starts_with(|c: char| c.is_digit(10)) {
+                Err("elements must not begin with a digit")
+            } else if !element.chars().all(|c| is_name_char(c) || extra.contains(&c)) {
+                Err("invalid character")
+            } else {
+                Ok(())
+            }
+        })
+    })
+}
+
+fn validate_bus_name(name: &str) -> ValidateResult {
+    if name.starts_with(':') {
+        validate_length(name)?;
+        validate_dotted_name(&name[1..], &['-'], true)
+    } else {
+        validate_dotted_name(name, &['-'], false)
+    }
+}
+
+fn validate_interface_name(name: &str) -> ValidateResult {
+    validate_dotted_name(name, &[], false)
+}
+
+fn validate_error_name(name: &str) -> ValidateResult {
+    validate_interface_name(name)
+}
+
+fn validate_member_name(name: &str) -> ValidateResult {
+    validate_length(name)?;
+
+    if name.starts_with(|c: char| c.is_digit(10)) {
+        Err("must not begin with a digit")
+    } else if !name.chars().all(is_name_char) {
+        Err("may only contain [A-Za-z0-9_]")
+    } else {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_object_paths() {
+    assert!(ObjectPath::new("/").is_ok());
+    assert!(ObjectPath::new("/org/freedesktop/DBus").is_ok());
+    assert!(ObjectPath::new("/with_underscore/a1").is_ok());
+
+    assert!(ObjectPath::new("").is_err());
+    assert!(ObjectPath::new("relative").is_err());
+    assert!(ObjectPath::new("/trailing/").is_err());
+    assert!(ObjectPath::new("/double//slash").is_err());
+    assert!(ObjectPath::new("/with-dash").is_err());
+    assert!(ObjectPath::new("/with.dot").is_err());
+}
+
+#[test]
+fn test_names() {
+    assert!(BusName::new("org.freedesktop.DBus").is_ok());
+    assert!(BusName::new("com.example.with-dash").is_ok());
+    assert!(BusName::new(":1.42").is_ok());
+    assert!(BusName::new("org").is_err());
+    assert!(BusName::new("org.1digit").is_err());
+    assert!(BusName::new(".org.example").is_err());
+
+    assert!(InterfaceName::new("org.freedesktop.DBus.Peer").is_ok());
+    assert!(InterfaceName::new("org.example.with-dash").is_err());
+    assert!(InterfaceName::new("org..example").is_err());
+    assert!(InterfaceName::new(&"a.b".repeat(128)).is_err());
+
+    assert!(MemberName::new("GetMachineId").is_ok());
+    assert!(MemberName::new("_private").is_ok());
+    assert!(MemberName::new("").is_err());
+    assert!(MemberName::new("1st").is_err());
+    assert!(MemberName::new("Dotted.Name").is_err());
+
+    assert!(ErrorName::new("org.freedesktop.DBus.Error.Failed").is_ok());
+    assert!(ErrorName::new("Failed").is_err());
+}
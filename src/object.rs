@@ -2,26 +2,31 @@
 // See accompanying LICENSE file for details.
 
 use connection::Connection;
-use interface::Interfaces;
+use error::*;
+use interface::{ChildrenList, Interfaces};
 use message::Message;
 
+use std::rc::Rc;
+
 /// An object which may receive messages.
 pub struct Object {
     path: String,
 
     interfaces: Interfaces,
+    children: ChildrenList,
 }
 
 impl Object {
     /// Create a new object with the given path, interfaces, and children.
     ///
     /// The list of children is managed by the object owning the object.
-    pub fn new<P>(path: P, interfaces: Interfaces) -> Self
+    pub fn new<P>(path: P, interfaces: Interfaces, children: ChildrenList) -> Self
         where P: ToString,
     {
         Object {
             path: path.to_string(),
             interfaces: interfaces,
+            children: children,
         }
     }
 
@@ -30,8 +35,35 @@ impl Object {
         &self.path
     }
 
+    /// The interfaces implemented by the object.
+    pub fn interfaces(&self) -> &Interfaces {
+        &self.interfaces
+    }
+
+    /// The names of the child nodes listed when introspecting the object.
+    pub fn children(&self) -> Vec<String> {
+        self.children.borrow().clone()
+    }
+
+    /// Set the names of the child nodes listed when introspecting the object.
+    pub fn set_children(&self, children: Vec<String>) {
+        *self.children.borrow_mut() = children;
+    }
+
     /// Give a message to the object to handle.
-    pub fn handle_message(&self, conn: &Connection, msg: &mut Message) -> Option<Result<(), ()>> {
+    ///
+    /// Returns `None` if the message is not addressed to this object.
+    pub fn handle_message(&self, conn: &Rc<Connection>, msg: &mut Message)
+                          -> Option<::std::result::Result<(), ()>> {
+        if msg.path().map_or(true, |path| path != self.path) {
+            return None;
+        }
+
         self.interfaces.handle(conn, msg)
     }
+
+    /// Send `PropertiesChanged` signals for any queued property changes on the object.
+    pub fn flush_properties_changed(&self, conn: &Connection) -> Result<()> {
+        self.interfaces.flush_properties_changed(conn, &self.path)
+    }
 }
@@ -0,0 +1,426 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use crates::core::ops::DerefMut;
+
+use error::*;
+use names::{BusName, IntoName, ObjectPath};
+use proxy::{PROPERTIES_INTERFACE, Proxy, SenderFilter};
+use server::{OBJECT_MANAGER_INTERFACE, Server, SubscriptionHandle};
+use target::Target;
+use value::{Value, Variant};
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+/// The interfaces of an object along with the values of their properties.
+type ObjectInterfaces = HashMap<String, HashMap<String, Value>>;
+
+type AddedHandler = Rc<RefCell<FnMut(&ObjectPath, &ObjectInterfaces) -> ()>>;
+type RemovedHandler = Rc<RefCell<FnMut(&ObjectPath) -> ()>>;
+
+fn _unwrap_interfaces(interfaces: HashMap<String, HashMap<String, Variant>>) -> ObjectInterfaces {
+    interfaces.into_iter()
+        .map(|(name, properties)| {
+            (name,
+             properties.into_iter()
+                 .map(|(name, value)| (name, *value.object))
+                 .collect())
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct TreeState {
+    objects: BTreeMap<ObjectPath, ObjectInterfaces>,
+    added_handlers: Vec<AddedHandler>,
+    removed_handlers: Vec<RemovedHandler>,
+}
+
+impl TreeState {
+    fn _interfaces_added(state: &RefCell<Self>, path: ObjectPath, interfaces: ObjectInterfaces) {
+        let notification = {
+            let mut state = state.borrow_mut();
+
+            let is_new = !state.objects.contains_key(&path);
+            state.objects
+                .entry(path.clone())
+                .or_insert_with(HashMap::new)
+                .extend(interfaces);
+
+            if is_new {
+                Some((state.objects[&path].clone(), state.added_handlers.clone()))
+            } else {
+                None
+            }
+        };
+
+        // Handlers are called without the state borrowed so that they may query the tree.
+        if let Some((object, handlers)) = notification {
+            for handler in handlers {
+                let mut cb = handler.borrow_mut();
+
+                cb.deref_mut()(&path, &object);
+            }
+        }
+    }
+
+    fn _interfaces_removed(state: &RefCell<Self>, path: ObjectPath, interfaces: Vec<String>) {
+        let handlers = {
+            let mut state = state.borrow_mut();
+
+            let is_empty = match state.objects.get_mut(&path) {
+                Some(object) => {
+                    for interface in &interfaces {
+                        object.remove(interface);
+                    }
+
+                    object.is_empty()
+                },
+                None => false,
+            };
+
+            if is_empty {
+                state.objects.remove(&path);
+                state.removed_handlers.clone()
+            } else {
+                vec![]
+            }
+        };
+
+        for handler in handlers {
+            let mut cb = handler.borrow_mut();
+
+            cb.deref_mut()(&path);
+        }
+    }
+
+    fn _properties_changed(state: &RefCell<Self>, path: &ObjectPath, interface: &str,
+                           changed: HashMap<String, Variant>, invalidated: Vec<String>) {
+        let mut state = state.borrow_mut();
+        let properties = state.objects
+            .get_mut(path)
+            .and_then(|object| object.get_mut(interface));
+
+        if let Some(properties) = properties {
+            properties.extend(changed.into_iter().map(|(name, value)| (name, *value.object)));
+            for name in &invalidated {
+                properties.remove(name);
+            }
+        }
+    }
+}
+
+/// A local mirror of the objects exposed through an `org.freedesktop.DBus.ObjectManager`.
+///
+/// The objects are fetched when the client is created and kept up to date using the
+/// `InterfacesAdded` and `InterfacesRemoved` signals. Properties are updated using the
+/// `org.freedesktop.DBus.Properties.PropertiesChanged` signal; invalidated properties are
+/// removed from the mirror and may be fetched using a `Proxy` for the object.
+///
+/// Updates are applied by the `Runner` which owns the server used to create the client.
+pub struct ObjectManagerClient {
+    proxy: Proxy,
+    state: Rc<RefCell<TreeState>>,
+    // Keeps the signal handlers connected for the lifetime of the client.
+    _subscriptions: Vec<SubscriptionHandle>,
+}
+
+impl ObjectManagerClient {
+    /// Mirror the objects managed by the object at the given path.
+    ///
+    /// The signal handlers are connected to the given server.
+    pub fn new<D, P>(server: &mut Server, destination: D, path: P) -> Result<Self>
+        where D: IntoName<BusName>,
+              P: IntoName<ObjectPath>,
+    {
+        let proxy = Proxy::new(server.connection().clone(),
+                               destination,
+                               path,
+                               OBJECT_MANAGER_INTERFACE)?;
+        let state = Rc::new(RefCell::new(TreeState::default()));
+
+        let sender = SenderFilter::new(server, proxy.destination())?;
+
+        // Subscribe before fetching the objects so that no changes are missed.
+        let added_state = state.clone();
+        let added_sender = sender.clone();
+        let added_signal = Target::new(OBJECT_MANAGER_INTERFACE, proxy.path(), "InterfacesAdded")?;
+        let added = server.connect_namespace(added_signal, move |ctx| {
+            if !added_sender.accepts(ctx) {
+                return;
+            }
+
+            match ctx.read::<(ObjectPath, HashMap<String, HashMap<String, Variant>>)>() {
+                Ok((path, interfaces)) => {
+                    TreeState::_interfaces_added(&added_state, path, _unwrap_interfaces(interfaces))
+                },
+                Err(err) => println!("invalid InterfacesAdded signal: {:?}", err),
+            }
+        })?;
+
+        let removed_state = state.clone();
+        let removed_sender = sender.clone();
+        let removed_signal = Target::new(OBJECT_MANAGER_INTERFACE,
+                                         proxy.path(),
+                                         "InterfacesRemoved")?;
+        let removed = server.connect_namespace(removed_signal, move |ctx| {
+            if !removed_sender.accepts(ctx) {
+                return;
+            }
+
+            match ctx.read::<(ObjectPath, Vec<String>)>() {
+                Ok((path, interfaces)) => {
+                    TreeState::_interfaces_removed(&removed_state, path, interfaces)
+                },
+                Err(err) => println!("invalid InterfacesRemoved signal: {:?}", err),
+            }
+        })?;
+
+        let changed_state = state.clone();
+        let changed_signal = Target::new(PROPERTIES_INTERFACE, proxy.path(), "PropertiesChanged")?;
+        let changed = server.connect_namespace(changed_signal, move |ctx| {
+            if !sender.accepts(ctx) {
+                return;
+            }
+
+            match ctx.read::<(String, HashMap<String, Variant>, Vec<String>)>() {
+                Ok((interface, changed, invalidated)) => {
+                    TreeState::_properties_changed(&changed_state,
+                                                   &ctx.target().object,
+                                                   &interface,
+                                                   changed,
+                                                   invalidated)
+                },
+                Err(err) => println!("invalid PropertiesChanged signal: {:?}", err),
+            }
+        })?;
+
+        let (objects,): (HashMap<ObjectPath, HashMap<String, HashMap<String, Variant>>>,) =
+            proxy.call("GetManagedObjects", ())?;
+        state.borrow_mut()
+            .objects
+            .extend(objects.into_iter()
+                .map(|(path, interfaces)| (path, _unwrap_interfaces(interfaces))));
+
+        Ok(ObjectManagerClient {
+            proxy: proxy,
+            state: state,
+            _subscriptions: vec![added, removed, changed],
+        })
+    }
+
+    /// The proxy for the object manager itself.
+    pub fn proxy(&self) -> &Proxy {
+        &self.proxy
+    }
+
+    /// The paths of the managed objects.
+    pub fn paths(&self) -> Vec<ObjectPath> {
+        self.state.borrow().objects.keys().cloned().collect()
+    }
+
+    /// The interfaces and properties of a managed object.
+    pub fn object(&self, path: &ObjectPath) -> Option<ObjectInterfaces> {
+        self.state.borrow().objects.get(path).cloned()
+    }
+
+    /// The interfaces and properties of all of the managed objects.
+    pub fn objects(&self) -> BTreeMap<ObjectPath, ObjectInterfaces> {
+        self.state.borrow().objects.clone()
+    }
+
+    /// The value of a property of a managed object.
+    pub fn get(&self, path: &ObjectPath, interface: &str, property: &str) -> Option<Value> {
+        self.state
+            .borrow()
+            .objects
+            .get(path)
+            .and_then(|object| object.get(interface))
+            .and_then(|properties| properties.get(property))
+            .cloned()
+    }
+
+    /// Call a function when an object is added.
+    ///
+    /// The callback is given the path of the object and its interfaces. It is not called for the
+    /// objects which existed when the client was created; see `objects`.
+    pub fn on_object_added<F>(&mut self, callback: F) -> &mut Self
+        where F: FnMut(&ObjectPath, &ObjectInterfaces) -> () + 'static
+    {
+        self.state.borrow_mut().added_handlers.push(Rc::new(RefCell::new(callback)));
+
+        self
+    }
+
+    /// Call a function when an object is removed.
+    ///
+    /// An object is removed once all of its interfaces have been removed.
+    pub fn on_object_removed<F>(&mut self, callback: F) -> &mut Self
+        where F: FnMut(&ObjectPath) -> () + 'static
+    {
+        self.state.borrow_mut().removed_handlers.push(Rc::new(RefCell::new(callback)));
+
+        self
+    }
+}
+
+#[test]
+fn test_object_manager_tree() {
+    let state = RefCell::new(TreeState::default());
+
+    let events = Rc::new(RefCell::new(vec![]));
+    let added_events = events.clone();
+    let added: AddedHandler = Rc::new(RefCell::new(move |path: &ObjectPath,
+                                                         interfaces: &ObjectInterfaces| {
+        added_events.borrow_mut().push(format!("added {} {}", path, interfaces.len()));
+    }));
+    let removed_events = events.clone();
+    let removed: RemovedHandler = Rc::new(RefCell::new(move |path: &ObjectPath| {
+        removed_events.borrow_mut().push(format!("removed {}", path));
+    }));
+    state.borrow_mut().added_handlers.push(added);
+    state.borrow_mut().removed_handlers.push(removed);
+
+    let path = ObjectPath::new("/org/example/obj").unwrap();
+    let interfaces = |names: &[&str]| {
+        names.iter()
+            .map(|name| (name.to_string(), HashMap::new()))
+            .collect::<ObjectInterfaces>()
+    };
+
+    TreeState::_interfaces_added(&state, path.clone(), interfaces(&["org.example.A"]));
+    TreeState::_interfaces_added(&state, path.clone(), interfaces(&["org.example.B"]));
+    assert_eq!(state.borrow().objects[&path].len(), 2);
+
+    TreeState::_interfaces_removed(&state, path.clone(), vec!["org.example.A".to_string()]);
+    assert!(state.borrow().objects.contains_key(&path));
+    TreeState::_interfaces_removed(&state, path.clone(), vec!["org.example.B".to_string()]);
+    assert!(!state.borrow().objects.contains_key(&path));
+
+    assert_eq!(*events.borrow(),
+               vec!["added /org/example/obj 1".to_string(),
+                    "removed /org/example/obj".to_string()]);
+}
+
+#[test]
+fn test_object_manager_client() {
+    use connection::test_connection;
+    use convert::{FromDBus, ToDBus};
+    use message::Message;
+    use value::BasicValue;
+
+    let interfaces = |count: u32| {
+        let mut properties = HashMap::new();
+        properties.insert("Count".to_string(),
+                          Variant::new(Value::BasicValue(BasicValue::Uint32(count)), "u"));
+        let mut interfaces = HashMap::new();
+        interfaces.insert("org.example.Iface".to_string(), properties);
+        interfaces
+    };
+    let (conn, bus) = test_connection(false, move |msg| {
+        let reply = msg.return_message();
+        let reply = match msg.member().unwrap().as_str() {
+            "GetNameOwner" => reply.add_argument(&":1.7".to_string()),
+            "GetManagedObjects" => {
+                let mut objects = HashMap::new();
+                objects.insert(ObjectPath::new("/org/example/a").unwrap(), interfaces(1));
+                reply.add_argument(&objects.to_dbus())
+            },
+            _ => reply,
+        };
+
+        vec![reply]
+    });
+    let conn = Rc::new(conn);
+    {
+        let mut server = Server::new_listener(conn.clone(), "org.example.Listener").unwrap();
+        let mut client = ObjectManagerClient::new(&mut server,
+                                                  "org.example.Service",
+                                                  "/org/example")
+            .unwrap();
+
+        let a = ObjectPath::new("/org/example/a").unwrap();
+        let b = ObjectPath::new("/org/example/b").unwrap();
+        let count = |client: &ObjectManagerClient, path: &ObjectPath| {
+            client.get(path, "org.example.Iface", "Count").as_ref().and_then(u32::from_dbus)
+        };
+
+        // The objects are seeded from GetManagedObjects.
+        assert_eq!(client.paths(), vec![a.clone()]);
+        assert_eq!(count(&client, &a), Some(1));
+
+        let events = Rc::new(RefCell::new(vec![]));
+        let added_events = events.clone();
+        client.on_object_added(move |path, _| {
+            added_events.borrow_mut().push(format!("added {}", path));
+        });
+        let removed_events = events.clone();
+        client.on_object_removed(move |path| {
+            removed_events.borrow_mut().push(format!("removed {}", path));
+        });
+
+        let signal = |sender: &str, path: &str, interface: &str, member: &str| {
+            let mut msg = Message::new_signal(path, interface, member);
+            msg.set_sender(sender);
+            msg
+        };
+        let added = |sender: &str| {
+            signal(sender, "/org/example", OBJECT_MANAGER_INTERFACE, "InterfacesAdded")
+                .add_argument(&b.to_dbus())
+                .add_argument(&interfaces(2).to_dbus())
+        };
+        let changed = |sender: &str| {
+            let mut values = HashMap::new();
+            values.insert("Count".to_string(),
+                          Variant::new(Value::BasicValue(BasicValue::Uint32(3)), "u"));
+            signal(sender, "/org/example/a", PROPERTIES_INTERFACE, "PropertiesChanged")
+                .add_argument(&"org.example.Iface".to_string())
+                .add_argument(&values.to_dbus())
+                .add_argument(&Vec::<String>::new().to_dbus())
+        };
+        let removed = |sender: &str| {
+            signal(sender, "/org/example", OBJECT_MANAGER_INTERFACE, "InterfacesRemoved")
+                .add_argument(&b.to_dbus())
+                .add_argument(&vec!["org.example.Iface".to_string()].to_dbus())
+        };
+
+        // Signals from connections other than the owner are ignored.
+        server.handle_message(&mut added(":1.8"));
+        server.handle_message(&mut changed(":1.8"));
+        assert_eq!(client.paths(), vec![a.clone()]);
+        assert_eq!(count(&client, &a), Some(1));
+
+        server.handle_message(&mut added(":1.7"));
+        assert_eq!(client.paths(), vec![a.clone(), b.clone()]);
+        assert_eq!(count(&client, &b), Some(2));
+
+        server.handle_message(&mut changed(":1.7"));
+        assert_eq!(count(&client, &a), Some(3));
+
+        server.handle_message(&mut removed(":1.8"));
+        assert!(client.object(&b).is_some());
+        server.handle_message(&mut removed(":1.7"));
+        assert_eq!(client.paths(), vec![a.clone()]);
+
+        assert_eq!(*events.borrow(),
+                   vec!["added /org/example/b".to_string(),
+                        "removed /org/example/b".to_string()]);
+    }
+    drop(conn);
+
+    let calls = bus.join()
+        .unwrap()
+        .into_iter()
+        .map(|msg| msg.member().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(&calls[..6],
+               &["AddMatch",
+                 "GetNameOwner",
+                 "AddMatch",
+                 "AddMatch",
+                 "AddMatch",
+                 "GetManagedObjects"]);
+    assert_eq!(&calls[6..], &["RemoveMatch"; 4]);
+}
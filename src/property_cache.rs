@@ -0,0 +1,294 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use crates::core::ops::DerefMut;
+
+use error::*;
+use proxy::{PROPERTIES_INTERFACE, Proxy, SenderFilter};
+use server::{Server, SubscriptionHandle};
+use target::Target;
+use value::{Value, Variant};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+type ChangeHandler = Rc<RefCell<FnMut(Option<&Value>) -> ()>>;
+
+#[derive(Default)]
+struct CacheState {
+    /// The cached values; invalidated properties have no value.
+    values: HashMap<String, Option<Value>>,
+    handlers: HashMap<String, Vec<ChangeHandler>>,
+}
+
+impl CacheState {
+    fn _update(state: &RefCell<Self>, changed: HashMap<String, Variant>, invalidated: Vec<String>) {
+        let notifications = {
+            let mut state = state.borrow_mut();
+
+            let changes = changed.into_iter()
+                .map(|(name, value)| (name, Some(*value.object)))
+                .chain(invalidated.into_iter().map(|name| (name, None)))
+                .collect::<Vec<_>>();
+
+            changes.into_iter()
+                .map(|(name, value)| {
+                    state.values.insert(name.clone(), value.clone());
+                    let handlers = state.handlers.get(&name).cloned().unwrap_or_default();
+
+                    (value, handlers)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Handlers are called without the state borrowed so that they may query the cache.
+        for (value, handlers) in notifications {
+            for handler in handlers {
+                let mut cb = handler.borrow_mut();
+
+                cb.deref_mut()(value.as_ref());
+            }
+        }
+    }
+}
+
+/// A local copy of the properties of an interface on a remote object.
+///
+/// The values of all properties are fetched when the cache is created and kept up to date using
+/// the `org.freedesktop.DBus.Properties.PropertiesChanged` signal. Properties which are
+/// invalidated by the signal are fetched again the next time they are requested.
+///
+/// Updates are applied by the `Runner` which owns the server used to create the cache. Properties
+/// which are not announced with the signal (see the
+/// `org.freedesktop.DBus.Property.EmitsChangedSignal` annotation) are never updated.
+pub struct PropertyCache {
+    proxy: Proxy,
+    state: Rc<RefCell<CacheState>>,
+    // Keeps the signal handler connected for the lifetime of the cache.
+    _subscription: SubscriptionHandle,
+}
+
+impl PropertyCache {
+    /// Create a cache for the interface of a proxy.
+    ///
+    /// The signal handler is connected to the given server.
+    pub fn new(proxy: Proxy, server: &mut Server) -> Result<Self> {
+        let state = Rc::new(RefCell::new(CacheState::default()));
+
+        // Subscribe before fetching the values so that no changes are missed.
+        let signal = Target::new(PROPERTIES_INTERFACE, proxy.path(), "PropertiesChanged")?;
+        let interface = proxy.interface().to_string();
+        let sender = SenderFilter::new(server, proxy.destination())?;
+        let handler_state = state.clone();
+        let subscription = server.connect(signal, move |ctx| {
+            if !sender.accepts(ctx) {
+                return;
+            }
+
+            match ctx.read::<(String, HashMap<String, Variant>, Vec<String>)>() {
+                Ok((changed_interface, changed, invalidated)) => {
+                    if changed_interface == interface {
+                        CacheState::_update(&handler_state, changed, invalidated);
+                    }
+                },
+                Err(err) => println!("invalid PropertiesChanged signal: {:?}", err),
+            }
+        })?;
+
+        let values = proxy.get_all()?;
+        state.borrow_mut()
+            .values
+            .extend(values.into_iter().map(|(name, value)| (name, Some(value))));
+
+        Ok(PropertyCache {
+            proxy: proxy,
+            state: state,
+            _subscription: subscription,
+        })
+    }
+
+    /// The proxy used to fetch the properties.
+    ///
+    /// Properties may be set using the proxy; the cache is updated once the object announces
+    /// the change.
+    pub fn proxy(&self) -> &Proxy {
+        &self.proxy
+    }
+
+    /// Get the value of a property.
+    ///
+    /// The property is fetched from the object if it is not cached.
+    pub fn get(&self, name: &str) -> Result<Value> {
+        if let Some(value) = self.cached(name) {
+            return Ok(value);
+        }
+
+        let value = self.proxy.get_property_value(name)?;
+        self.state.borrow_mut().values.insert(name.to_string(), Some(value.clone()));
+
+        Ok(value)
+    }
+
+    /// Get the cached value of a property.
+    ///
+    /// Returns `None` if the property is not cached or has been invalidated.
+    pub fn cached(&self, name: &str) -> Option<Value> {
+        self.state.borrow().values.get(name).and_then(Clone::clone)
+    }
+
+    /// Call a function when a property changes.
+    ///
+    /// The callback is given the new value of the property or `None` if it was invalidated.
+    pub fn on_changed<F>(&mut self, name: &str, callback: F) -> &mut Self
+        where F: FnMut(Option<&Value>) -> () + 'static
+    {
+        self.state
+            .borrow_mut()
+            .handlers
+            .entry(name.to_string())
+            .or_insert_with(Vec::new)
+            .push(Rc::new(RefCell::new(callback)));
+
+        self
+    }
+}
+
+#[test]
+fn test_property_cache_update() {
+    use convert::FromDBus;
+    use value::BasicValue;
+
+    let state = Rc::new(RefCell::new(CacheState::default()));
+    state.borrow_mut()
+        .values
+        .insert("Name".to_string(),
+                Some(Value::BasicValue(BasicValue::String("name".to_string()))));
+
+    let seen = Rc::new(RefCell::new(vec![]));
+    for name in &["Count", "Name"] {
+        let seen = seen.clone();
+        let prop = name.to_string();
+        let handler: ChangeHandler = Rc::new(RefCell::new(move |value: Option<&Value>| {
+            seen.borrow_mut().push((prop.clone(), value.and_then(u32::from_dbus)));
+        }));
+        state.borrow_mut().handlers.insert(name.to_string(), vec![handler]);
+    }
+
+    let mut changed = HashMap::new();
+    changed.insert("Count".to_string(),
+                   Variant::new(Value::BasicValue(BasicValue::Uint32(1)), "u"));
+    CacheState::_update(&state, changed, vec!["Name".to_string()]);
+
+    {
+        let state = state.borrow();
+        let count = state.values.get("Count").and_then(Option::as_ref);
+        assert_eq!(count.and_then(u32::from_dbus), Some(1));
+        assert!(state.values.get("Name").unwrap().is_none());
+    }
+    assert_eq!(*seen.borrow(),
+               vec![("Count".to_string(), Some(1)), ("Name".to_string(), None)]);
+}
+
+#[test]
+fn test_property_cache() {
+    use connection::test_connection;
+    use convert::{FromDBus, ToDBus};
+    use message::Message;
+    use value::BasicValue;
+
+    let string = |s: &str| Value::BasicValue(BasicValue::String(s.to_string()));
+    let uint32 = |u: u32| Value::BasicValue(BasicValue::Uint32(u));
+    let (conn, bus) = test_connection(false, move |msg| {
+        let reply = msg.return_message();
+        let reply = match msg.member().unwrap().as_str() {
+            "GetNameOwner" => reply.add_argument(&":1.7".to_string()),
+            "GetAll" => {
+                let mut values = HashMap::new();
+                values.insert("Count".to_string(), Variant::new(uint32(1), "u"));
+                values.insert("Name".to_string(), Variant::new(string("first"), "s"));
+                reply.add_argument(&values.to_dbus())
+            },
+            "Get" => {
+                let (_, name): (String, String) = msg.read().unwrap();
+                assert_eq!(name, "Name");
+                reply.add_argument(&Variant::new(string("second"), "s").to_dbus())
+            },
+            _ => reply,
+        };
+
+        vec![reply]
+    });
+    let conn = Rc::new(conn);
+    {
+        let mut server = Server::new_listener(conn.clone(), "org.example.Listener").unwrap();
+        let proxy = Proxy::new(conn.clone(),
+                               "org.example.Service",
+                               "/org/example",
+                               "org.example.Iface")
+            .unwrap();
+        let mut cache = PropertyCache::new(proxy, &mut server).unwrap();
+        let count = |cache: &PropertyCache| {
+            cache.cached("Count").as_ref().and_then(u32::from_dbus)
+        };
+        let name = |cache: &PropertyCache| {
+            cache.cached("Name").as_ref().and_then(String::from_dbus)
+        };
+
+        // The values are seeded from GetAll.
+        assert_eq!(count(&cache), Some(1));
+        assert_eq!(name(&cache), Some("first".to_string()));
+
+        let seen = Rc::new(RefCell::new(vec![]));
+        let seen_count = seen.clone();
+        cache.on_changed("Count", move |value| {
+            seen_count.borrow_mut().push(value.and_then(u32::from_dbus));
+        });
+
+        let changed = |sender: &str, interface: &str, count: u32| {
+            let mut values = HashMap::new();
+            values.insert("Count".to_string(), Variant::new(uint32(count), "u"));
+            let mut msg = Message::new_signal("/org/example",
+                                              PROPERTIES_INTERFACE,
+                                              "PropertiesChanged")
+                .add_argument(&interface.to_string())
+                .add_argument(&values.to_dbus())
+                .add_argument(&vec!["Name".to_string()].to_dbus());
+            msg.set_sender(sender);
+            msg
+        };
+
+        // Changes from connections other than the owner are ignored, as are other interfaces.
+        server.handle_message(&mut changed(":1.8", "org.example.Iface", 5));
+        server.handle_message(&mut changed(":1.7", "org.example.Other", 6));
+        assert_eq!(count(&cache), Some(1));
+        assert_eq!(name(&cache), Some("first".to_string()));
+        assert!(seen.borrow().is_empty());
+
+        server.handle_message(&mut changed(":1.7", "org.example.Iface", 2));
+        assert_eq!(count(&cache), Some(2));
+        assert_eq!(*seen.borrow(), vec![Some(2)]);
+
+        // Invalidated properties are fetched again when requested.
+        assert_eq!(name(&cache), None);
+        assert_eq!(String::from_dbus(&cache.get("Name").unwrap()),
+                   Some("second".to_string()));
+        assert_eq!(name(&cache), Some("second".to_string()));
+        assert_eq!(u32::from_dbus(&cache.get("Count").unwrap()), Some(2));
+    }
+    drop(conn);
+
+    let calls = bus.join()
+        .unwrap()
+        .into_iter()
+        .map(|msg| msg.member().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(calls,
+               vec!["AddMatch",
+                    "GetNameOwner",
+                    "AddMatch",
+                    "GetAll",
+                    "Get",
+                    "RemoveMatch",
+                    "RemoveMatch"]);
+}
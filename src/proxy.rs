@@ -0,0 +1,503 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use connection::{Connection, DEFAULT_TIMEOUT};
+use context::SignalContext;
+use convert::{DBusType, FromDBus, FromDBusArgs, ToDBus, ToDBusArgs};
+use error::*;
+use match_rule::MatchRule;
+use message::{Message, NO_REPLY_EXPECTED};
+use names::{BusName, InterfaceName, IntoName, ObjectPath};
+use server::{Server, SubscriptionHandle};
+use target::Target;
+use value::{Value, Variant};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// The interface used to access properties.
+pub(crate) const PROPERTIES_INTERFACE: &'static str = "org.freedesktop.DBus.Properties";
+
+/// Filters signals by whether they were sent by the owner of a bus name.
+///
+/// Signals carry the unique name of their sender, so the owner of a well-known name is looked
+/// up when the filter is created and followed using `NameOwnerChanged`. Peer connections have no
+/// bus names and accept every signal.
+#[derive(Clone)]
+pub(crate) struct SenderFilter {
+    owner: Option<Rc<RefCell<Option<String>>>>,
+    // Keeps the owner up to date for as long as a copy of the filter exists.
+    _subscription: Option<Rc<SubscriptionHandle>>,
+}
+
+impl SenderFilter {
+    /// Create a filter for signals sent by the owner of a bus name.
+    ///
+    /// The handler following the owner is connected to the given server.
+    pub(crate) fn new(server: &mut Server, name: &BusName) -> Result<Self> {
+        let conn = server.connection().clone();
+
+        if conn.is_peer() {
+            return Ok(SenderFilter {
+                owner: None,
+                _subscription: None,
+            });
+        }
+
+        if name.is_unique() {
+            return Ok(SenderFilter {
+                owner: Some(Rc::new(RefCell::new(Some(name.to_string())))),
+                _subscription: None,
+            });
+        }
+
+        // Subscribe before looking up the owner so that no changes are missed.
+        let owner = Rc::new(RefCell::new(None));
+        let rule = MatchRule::new_signal()
+            .sender("org.freedesktop.DBus")
+            .interface("org.freedesktop.DBus")
+            .member("NameOwnerChanged")
+            .arg(0, name);
+        let handler_owner = owner.clone();
+        let subscription = server.connect_rule(rule, move |ctx| {
+            match ctx.read::<(String, String, String)>() {
+                Ok((_, _, new_owner)) => {
+                    *handler_owner.borrow_mut() = if new_owner.is_empty() {
+                        None
+                    } else {
+                        Some(new_owner)
+                    };
+                },
+                Err(err) => println!("invalid NameOwnerChanged signal: {:?}", err),
+            }
+        })?;
+
+        match conn.get_name_owner(name) {
+            Ok(unique) => *owner.borrow_mut() = Some(unique),
+            Err(err) => {
+                let has_no_owner = match *err.kind() {
+                    ErrorKind::RemoteError(ref error, _) => {
+                        error == "org.freedesktop.DBus.Error.NameHasNoOwner"
+                    },
+                    _ => false,
+                };
+                if !has_no_owner {
+                    return Err(err);
+                }
+            },
+        }
+
+        Ok(SenderFilter {
+            owner: Some(owner),
+            _subscription: Some(Rc::new(subscription)),
+        })
+    }
+
+    /// Whether the signal was sent by the current owner of the name.
+    pub(crate) fn accepts(&self, ctx: &SignalContext) -> bool {
+        match self.owner {
+            Some(ref owner) => {
+                owner.borrow()
+                    .as_ref()
+                    .map_or(false, |owner| ctx.sender() == Some(owner.as_str()))
+            },
+            None => true,
+        }
+    }
+}
+
+/// A handle to an interface on a remote object.
+///
+/// Method calls wait for their reply, queueing other messages on the connection for the `Runner`
+/// to handle later.
+pub struct Proxy {
+    conn: Rc<Connection>,
+    destination: BusName,
+    path: ObjectPath,
+    interface: InterfaceName,
+    timeout: Option<Duration>,
+}
+
+impl Proxy {
+    /// Create a proxy for an interface on an object owned by the given bus name.
+    ///
+    /// Fails if any of the names are invalid.
+    pub fn new<D, P, I>(conn: Rc<Connection>, destination: D, path: P, interface: I)
+                        -> Result<Self>
+        where D: IntoName<BusName>,
+              P: IntoName<ObjectPath>,
+              I: IntoName<InterfaceName>,
+    {
+        Ok(Proxy {
+            conn: conn,
+            destination: destination.into_name()?,
+            path: path.into_name()?,
+            interface: interface.into_name()?,
+            timeout: Some(Duration::from_secs(DEFAULT_TIMEOUT)),
+        })
+    }
+
+    /// The bus name which owns the object.
+    pub fn destination(&self) -> &BusName {
+        &self.destination
+    }
+
+    /// The path of the object.
+    pub fn path(&self) -> &ObjectPath {
+        &self.path
+    }
+
+    /// The interface on the object.
+    pub fn interface(&self) -> &InterfaceName {
+        &self.interface
+    }
+
+    /// Set how long to wait for replies to method calls.
+    ///
+    /// A timeout of `None` waits forever. The default is 25 seconds.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.timeout = timeout;
+
+        self
+    }
+
+    /// Create a call to a method on the object.
+    ///
+    /// Arguments which `call` cannot send, such as file descriptors, may be added to the message
+    /// before sending it with `call_message`.
+    pub fn method_call(&self, method: &str) -> Message {
+        Message::new_method_call(&self.destination, &self.path, &self.interface, method)
+    }
+
+    /// Send a method call and wait for its reply message.
+    ///
+    /// File descriptors sent with the reply may be taken out of the returned message. Error
+    /// replies are returned as `ErrorKind::RemoteError`.
+    pub fn call_message(&self, msg: Message) -> Result<Message> {
+        self.conn.call(msg, self.timeout)
+    }
+
+    fn _method_call<A>(&self, interface: &str, method: &str, args: A) -> Message
+        where A: ToDBusArgs,
+    {
+        args.to_dbus_args()
+            .iter()
+            .fold(Message::new_method_call(&self.destination, &self.path, interface, method),
+                  |msg, arg| msg.add_argument(arg))
+    }
+
+    fn _call<A, R>(&self, interface: &str, method: &str, args: A) -> Result<R>
+        where A: ToDBusArgs,
+              R: FromDBusArgs,
+    {
+        self.conn
+            .call(self._method_call(interface, method, args), self.timeout)?
+            .read()
+    }
+
+    /// Call a method on the object and wait for its results.
+    ///
+    /// The arguments and results are given as tuples (e.g., `proxy.call::<_, (u32,)>("Method",
+    /// ("arg",))`).
+    pub fn call<A, R>(&self, method: &str, args: A) -> Result<R>
+        where A: ToDBusArgs,
+              R: FromDBusArgs,
+    {
+        self._call(&self.interface, method, args)
+    }
+
+    /// Call a method on the object without waiting for a reply.
+    ///
+    /// The call is marked so that the remote object does not send a reply.
+    pub fn call_noreply<A>(&self, method: &str, args: A) -> Result<()>
+        where A: ToDBusArgs,
+    {
+        let mut msg = self._method_call(&self.interface, method, args);
+        msg.set_flags(NO_REPLY_EXPECTED);
+
+        self.conn.send(msg).map(|_| ())
+    }
+
+    /// Get the value of a property of the interface.
+    pub fn get_property<T>(&self, name: &str) -> Result<T>
+        where T: FromDBus,
+    {
+        let value = self.get_property_value(name)?;
+
+        T::from_dbus(&value).ok_or_else(|| {
+            ErrorKind::ArgumentMismatch(T::signature(), value.get_signature().to_string()).into()
+        })
+    }
+
+    /// Get the value of a property of the interface without converting it.
+    pub fn get_property_value(&self, name: &str) -> Result<Value> {
+        let (value,): (Variant,) = self._call(PROPERTIES_INTERFACE,
+                                              "Get",
+                                              (self.interface.as_str(), name))?;
+
+        Ok(*value.object)
+    }
+
+    /// Set the value of a property of the interface.
+    pub fn set_property<T>(&self, name: &str, value: T) -> Result<()>
+        where T: ToDBus,
+    {
+        let value = Variant::new(value.to_dbus(), &T::signature());
+
+        self._call(PROPERTIES_INTERFACE, "Set", (self.interface.as_str(), name, value))
+    }
+
+    /// Get the values of all of the properties of the interface.
+    pub fn get_all(&self) -> Result<HashMap<String, Value>> {
+        let (values,): (HashMap<String, Variant>,) = self._call(PROPERTIES_INTERFACE,
+                                                                "GetAll",
+                                                                (self.interface.as_str(),))?;
+
+        Ok(values.into_iter()
+            .map(|(name, value)| (name, *value.object))
+            .collect())
+    }
+
+    /// Connect a handler to a signal emitted by the object.
+    ///
+    /// The handler is called by the `Runner` which owns the server. Only signals sent by the
+    /// current owner of the destination are handled. The handler is disconnected when the
+    /// returned handle is dropped.
+    pub fn connect_signal<F>(&self, server: &mut Server, member: &str, mut callback: F)
+                             -> Result<SubscriptionHandle>
+        where F: FnMut(&SignalContext) -> () + 'static
+    {
+        let signal = Target::new(&self.interface, &self.path, member)?;
+        let mut rule = MatchRule::new_signal()
+            .interface(&signal.interface)
+            .path(&signal.object)
+            .member(&signal.method);
+        if self.destination.is_unique() {
+            rule = rule.sender(&self.destination);
+        }
+
+        let sender = SenderFilter::new(server, &self.destination)?;
+        server.connect_rule(rule, move |ctx| {
+            if sender.accepts(ctx) {
+                callback(ctx);
+            }
+        })
+    }
+}
+
+#[test]
+fn test_sender_filter() {
+    use connection::test_connection;
+
+    let (conn, bus) = test_connection(false, |msg| {
+        let reply = match msg.member().unwrap().as_str() {
+            "GetNameOwner" => {
+                let (name,): (String,) = msg.read().unwrap();
+                if name == "org.example.Service" {
+                    msg.return_message().add_argument(&":1.7".to_string())
+                } else {
+                    msg.error_message("org.freedesktop.DBus.Error.NameHasNoOwner")
+                }
+            },
+            _ => msg.return_message(),
+        };
+
+        vec![reply]
+    });
+    let conn = Rc::new(conn);
+    {
+        let mut server = Server::new_listener(conn.clone(), "org.example.Listener").unwrap();
+        let target = Target::new("org.example.Iface", "/org/example", "Changed").unwrap();
+        let accepts = |filter: &SenderFilter, sender: &str| {
+            let mut msg = Message::new_signal("/org/example", "org.example.Iface", "Changed");
+            msg.set_sender(sender);
+            filter.accepts(&SignalContext::new(&conn, &target, &msg))
+        };
+        let owner_changed = |name: &str, old: &str, new: &str| {
+            let mut msg = Message::new_signal("/org/freedesktop/DBus",
+                                              "org.freedesktop.DBus",
+                                              "NameOwnerChanged")
+                .add_argument(&name.to_string())
+                .add_argument(&old.to_string())
+                .add_argument(&new.to_string());
+            msg.set_sender("org.freedesktop.DBus");
+            msg
+        };
+
+        let unique = SenderFilter::new(&mut server, &BusName::new(":1.7").unwrap()).unwrap();
+        assert!(accepts(&unique, ":1.7"));
+        assert!(!accepts(&unique, ":1.8"));
+
+        let service = BusName::new("org.example.Service").unwrap();
+        let filter = SenderFilter::new(&mut server, &service).unwrap();
+        assert!(accepts(&filter, ":1.7"));
+        assert!(!accepts(&filter, ":1.8"));
+
+        server.handle_message(&mut owner_changed("org.example.Service", ":1.7", ":1.8"));
+        assert!(!accepts(&filter, ":1.7"));
+        assert!(accepts(&filter, ":1.8"));
+
+        server.handle_message(&mut owner_changed("org.example.Service", ":1.8", ""));
+        assert!(!accepts(&filter, ":1.8"));
+
+        let missing = BusName::new("org.example.Missing").unwrap();
+        let missing = SenderFilter::new(&mut server, &missing).unwrap();
+        assert!(!accepts(&missing, ":1.7"));
+
+        server.handle_message(&mut owner_changed("org.example.Missing", "", ":1.9"));
+        assert!(accepts(&missing, ":1.9"));
+
+        // The owner is followed for as long as a copy of the filter exists.
+        let copy = missing.clone();
+        drop(missing);
+        server.handle_message(&mut owner_changed("org.example.Missing", ":1.9", ":1.10"));
+        assert!(accepts(&copy, ":1.10"));
+    }
+    drop(conn);
+
+    let calls = bus.join()
+        .unwrap()
+        .into_iter()
+        .map(|msg| msg.member().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(calls,
+               vec!["AddMatch",
+                    "GetNameOwner",
+                    "AddMatch",
+                    "GetNameOwner",
+                    "RemoveMatch",
+                    "RemoveMatch"]);
+}
+
+#[test]
+fn test_call_message_fds() {
+    use connection::test_connection;
+    use fd::UnixFd;
+
+    use std::io::{Read, Write};
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+    use std::os::unix::net::UnixStream;
+
+    // The remote end sends the file descriptor it was given back.
+    let (conn, bus) = test_connection(true, |msg| {
+        let fd = msg.take_fd(0).unwrap();
+        vec![msg.return_message().add_argument(fd)]
+    });
+    let conn = Rc::new(conn);
+    let proxy = Proxy::new(conn.clone(), "org.example.Service", "/org/example", "org.example.Iface")
+        .unwrap();
+    let (local, remote) = UnixStream::pair().unwrap();
+
+    let msg = proxy.method_call("Echo").add_argument(UnixFd::new(remote));
+    assert_eq!(msg.signature(), "h");
+    let reply = proxy.call_message(msg).unwrap();
+    assert_eq!(reply.signature(), "h");
+    assert_eq!(reply.read::<(u32,)>().unwrap(), (0,));
+
+    let fd = reply.take_fd(0).unwrap();
+    let mut received = unsafe { UnixStream::from_raw_fd(fd.into_raw_fd()) };
+    received.write_all(b"ping").unwrap();
+    drop(received);
+
+    let mut data = String::new();
+    (&local).read_to_string(&mut data).unwrap();
+    assert_eq!(data, "ping");
+
+    drop(proxy);
+    drop(conn);
+    bus.join().unwrap();
+}
+
+#[test]
+fn test_proxy_calls() {
+    use connection::test_connection;
+    use message::MessageType;
+    use value::BasicValue;
+
+    let count = || Variant::new(Value::BasicValue(BasicValue::Uint32(7)), "u");
+    let (conn, bus) = test_connection(true, move |msg| {
+        let reply = msg.return_message();
+        let reply = match msg.member().unwrap().as_str() {
+            "Add" => {
+                let (a, b): (u32, u32) = msg.read().unwrap();
+                reply.add_argument(&(a + b))
+            },
+            "Fail" => msg.error_message("org.example.Error.Failed"),
+            "Get" => {
+                let (iface, name): (String, String) = msg.read().unwrap();
+                assert_eq!(iface, "org.example.Iface");
+                assert_eq!(name, "Count");
+                reply.add_argument(&count().to_dbus())
+            },
+            "GetAll" => {
+                let mut props = HashMap::new();
+                props.insert("Count".to_string(), count());
+                reply.add_argument(&props.to_dbus())
+            },
+            "Set" => reply,
+            // Neither of these may be replied to.
+            "Notify" | "Slow" => return vec![],
+            member => panic!("unexpected call: {}", member),
+        };
+
+        vec![reply]
+    });
+    let conn = Rc::new(conn);
+    {
+        let mut proxy = Proxy::new(conn.clone(),
+                                   "org.example.Service",
+                                   "/org/example",
+                                   "org.example.Iface")
+            .unwrap();
+
+        assert_eq!(proxy.call::<_, (u32,)>("Add", (1u32, 2u32)).unwrap(), (3,));
+        match *proxy.call::<_, ()>("Fail", ()).unwrap_err().kind() {
+            ErrorKind::RemoteError(ref name, _) => assert_eq!(name, "org.example.Error.Failed"),
+            ref kind => panic!("unexpected error: {:?}", kind),
+        }
+        proxy.call_noreply("Notify", ("event",)).unwrap();
+
+        assert_eq!(proxy.get_property::<u32>("Count").unwrap(), 7);
+        match *proxy.get_property::<String>("Count").unwrap_err().kind() {
+            ErrorKind::ArgumentMismatch(..) => (),
+            ref kind => panic!("unexpected error: {:?}", kind),
+        }
+        proxy.set_property("Count", 8u32).unwrap();
+        let props = proxy.get_all().unwrap();
+        assert_eq!(props.len(), 1);
+        assert_eq!(u32::from_dbus(&props["Count"]), Some(7));
+
+        proxy.set_timeout(Some(Duration::from_millis(50)));
+        match *proxy.call::<_, ()>("Slow", ()).unwrap_err().kind() {
+            ErrorKind::Timeout => (),
+            ref kind => panic!("unexpected error: {:?}", kind),
+        }
+    }
+    drop(conn);
+
+    let calls = bus.join().unwrap();
+    let call = |member: &str| {
+        calls.iter()
+            .find(|msg| msg.member() == Some(member.to_string()))
+            .unwrap()
+    };
+
+    let add = call("Add");
+    assert_eq!(add.message_type(), MessageType::MethodCall);
+    assert_eq!(add.destination(), Some("org.example.Service".to_string()));
+    assert_eq!(add.path(), Some("/org/example".to_string()));
+    assert_eq!(add.interface(), Some("org.example.Iface".to_string()));
+    assert!(!add.flags().contains(NO_REPLY_EXPECTED));
+
+    let notify = call("Notify");
+    assert!(notify.flags().contains(NO_REPLY_EXPECTED));
+    assert_eq!(notify.read::<(String,)>().unwrap(), ("event".to_string(),));
+
+    let set = call("Set");
+    assert_eq!(set.interface(), Some(PROPERTIES_INTERFACE.to_string()));
+    let (iface, name, value): (String, String, Variant) = set.read().unwrap();
+    assert_eq!((iface.as_str(), name.as_str()), ("org.example.Iface", "Count"));
+    assert_eq!(u32::from_dbus(&value.object), Some(8));
+}
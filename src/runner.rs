@@ -1,18 +1,29 @@
 // Distributed under the OSI-approved BSD 3-Clause License.
 // See accompanying LICENSE file for details.
 
-use connection::Connection;
+use connection::{Connection, RequestNameFlags, DO_NOT_QUEUE};
 use error::*;
-use message::MessageType;
+use message::{Message, MessageType, NO_REPLY_EXPECTED};
+use names::{BusName, IntoName};
 use server::Server;
 
 use std::collections::btree_map::{BTreeMap, Entry};
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 /// An object to handle messages and act on them.
 ///
 /// A `Runner` object listens to the message bus and handles them off to the appropriate objects
 /// and signal handler callbacks.
+///
+/// To integrate with another event loop, watch the runner's file descriptors (see `AsRawFd` and
+/// `task_fd`) for readability and call `dispatch_pending` when either is readable.
+///
+/// Synchronous calls on the connection (e.g., through a `Proxy` or
+/// `MethodContext::credentials`) queue the messages which arrive while they wait for their reply.
+/// Queued messages do not make the file descriptor readable, so call `dispatch_pending` after
+/// making such calls outside of a handler or check `Connection::has_pending` before waiting.
 pub struct Runner {
     conn: Rc<Connection>,
 
@@ -31,6 +42,11 @@ impl Runner {
         })
     }
 
+    /// The connection the runner handles messages for.
+    pub fn connection(&self) -> &Rc<Connection> {
+        &self.conn
+    }
+
     // FIXME: Rename to `new_listener`?
     /// Create a server which will listen for and handle signals.
     pub fn add_listener(&mut self, name: &str) -> Result<&mut Server> {
@@ -43,16 +59,46 @@ impl Runner {
 
     // FIXME: Rename to `new_server`?
     /// Create a server which will expose objects and interfaces to the bus.
+    ///
+    /// Fails if the name is already owned on the bus.
     pub fn add_server<N>(&mut self, name: N) -> Result<&mut Server>
-        where N: ToString,
+        where N: IntoName<BusName>,
     {
+        self.add_server_with_flags(name, DO_NOT_QUEUE)
+    }
+
+    /// Create a server which will expose objects and interfaces to the bus.
+    ///
+    /// The name is requested with the given flags, so the server may wait in the queue for the
+    /// name or allow itself to be replaced.
+    pub fn add_server_with_flags<N>(&mut self, name: N, flags: RequestNameFlags)
+                                    -> Result<&mut Server>
+        where N: IntoName<BusName>,
+    {
+        let name = name.into_name()?;
+
         match self.servers.entry(name.to_string()) {
             Entry::Vacant(v) => {
-                let server = Server::new(self.conn.clone(), name)?;
+                let server = Server::new_with_flags(self.conn.clone(), name, flags)?;
 
                 Ok(v.insert(server))
             },
-            Entry::Occupied(_) => bail!(ErrorKind::ServerAlreadyRegistered(name.to_string())),
+            Entry::Occupied(_) => bail!(ErrorKind::ServerAlreadyRegistered(name.into_string())),
+        }
+    }
+
+    /// Create a server which will expose objects and interfaces to a peer.
+    ///
+    /// The runner's connection must be a peer connection (e.g., one accepted by a `Listener`).
+    /// Only one peer server may be added; it is registered with an empty name.
+    pub fn add_peer_server(&mut self) -> Result<&mut Server> {
+        match self.servers.entry(String::new()) {
+            Entry::Vacant(v) => {
+                let server = Server::new_peer(self.conn.clone())?;
+
+                Ok(v.insert(server))
+            },
+            Entry::Occupied(_) => bail!(ErrorKind::ServerAlreadyRegistered(String::new())),
         }
     }
 
@@ -66,24 +112,276 @@ impl Runner {
         }
     }
 
-    // FIXME: Allow this to hook into other event loops.
+    fn _unknown_object(conn: &Connection, m: &Message) {
+        if m.flags().contains(NO_REPLY_EXPECTED) {
+            return;
+        }
+
+        let reply = m.error_message("org.freedesktop.DBus.Error.UnknownObject")
+            .add_argument(&format!("unknown object: {}", m.path().unwrap_or_default()));
+
+        if let Err(err) = conn.send(reply) {
+            println!("failed to send a reply for {:?}: {:?}", m, err);
+        }
+    }
+
+    fn _handle_message(&mut self, mut message: Message) {
+        if let MessageType::Signal = message.message_type() {
+            for listener in self.listeners.iter_mut() {
+                listener.handle_message(&mut message);
+            }
+        }
+
+        let unhandled = self.servers.iter_mut().fold(Some(&mut message), |opt_m, (_, server)| {
+            opt_m.and_then(|m| server.handle_message(m))
+        });
+
+        if let Some(m) = unhandled {
+            if let MessageType::MethodCall = m.message_type() {
+                Self::_unknown_object(&self.conn, m);
+            }
+        }
+
+        self._flush_properties_changed();
+    }
+
+    fn _flush_properties_changed(&self) {
+        for (name, server) in self.servers.iter() {
+            if let Err(err) = server.flush_properties_changed() {
+                println!("failed to send property changes for {}: {:?}", name, err);
+            }
+        }
+    }
+
+    /// Read and handle a single message or poll spawned futures which have been woken.
+    ///
+    /// A timeout of `None` blocks until there is something to do while a timeout of zero never
+    /// blocks. Returns whether any work was done. A message which cannot be read is dropped and
+    /// its error returned; only `Disconnected` and I/O errors mean that the connection is no
+    /// longer usable.
+    pub fn process_one(&mut self, timeout: Option<Duration>) -> Result<bool> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            let polled = self.conn.poll_tasks();
+
+            if let Some(message) = self.conn.read_message(Some(Duration::from_secs(0)))? {
+                self._handle_message(message);
+
+                return Ok(true);
+            }
+
+            if polled {
+                self._flush_properties_changed();
+
+                return Ok(true);
+            }
+
+            let remaining = deadline.map(|deadline| {
+                let now = Instant::now();
+                if deadline <= now {
+                    Duration::from_secs(0)
+                } else {
+                    deadline - now
+                }
+            });
+
+            if !self.conn.wait(remaining)? {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// A file descriptor which is readable when spawned futures need to be polled.
+    pub fn task_fd(&self) -> RawFd {
+        self.conn.task_fd()
+    }
+
+    /// Handle all messages which are available without blocking.
+    ///
+    /// This is meant to be called when the runner's file descriptor is readable. Returns the
+    /// number of messages handled.
+    pub fn dispatch_pending(&mut self) -> Result<usize> {
+        let mut count = 0;
+        while self.process_one(Some(Duration::from_secs(0)))? {
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
     /// Run an event loop to handle messages.
+    ///
+    /// This blocks until the connection is closed or reading from it fails. Messages which cannot
+    /// be read (e.g., with malformed headers) are dropped. See `dispatch_pending` for use with
+    /// other event loops.
     pub fn run(&mut self) -> () {
-        let listeners = &mut self.listeners;
-        let servers = &mut self.servers;
-
         // TODO: add dummy objects to servers
 
-        self.conn.iter().fold((), |_, mut message| {
-            if let MessageType::Signal = message.message_type() {
-                for listener in listeners.iter_mut() {
-                    listener.handle_message(&mut message);
+        loop {
+            if let Err(err) = self.process_one(None) {
+                match *err.kind() {
+                    ErrorKind::Disconnected |
+                    ErrorKind::Io(_) => {
+                        println!("failed to read a message: {:?}", err);
+                        break;
+                    },
+                    _ => println!("dropped an unreadable message: {:?}", err),
                 }
             }
+        }
+    }
+}
+
+impl AsRawFd for Runner {
+    /// The socket of the runner's connection.
+    ///
+    /// Messages queued by synchronous calls are not signalled by the socket; see `Runner`.
+    fn as_raw_fd(&self) -> RawFd {
+        self.conn.as_raw_fd()
+    }
+}
+
+#[test]
+fn test_process_one() {
+    use connection::test_connection;
+    use interface::{Interface, Interfaces, Method};
+
+    use std::cell::Cell;
+
+    let (conn, bus) = test_connection(false, |msg| {
+        let mut replies = vec![msg.return_message().add_argument(&1u32)];
+        // Call the server and an unknown object once the server has its name.
+        if msg.member() == Some("RequestName".to_string()) {
+            for path in &["/org/example", "/org/example/missing"] {
+                replies.push(Message::new_method_call("org.example.Test",
+                                                      path,
+                                                      "org.example.Iface",
+                                                      "Ping"));
+            }
+        }
+        replies
+    });
 
-            servers.iter_mut().fold(Some(&mut message), |opt_m, (_, server)| {
-                opt_m.and_then(|m| server.handle_message(m))
+    let pings = Rc::new(Cell::new(0));
+    {
+        let mut runner = Runner::new(conn).unwrap();
+        {
+            let pings = pings.clone();
+            let method = Method::new(move |_, _| {
+                pings.set(pings.get() + 1);
+                Ok(vec![])
             });
-        });
+            let iface = Interface::new().add_method("Ping", method);
+            let ifaces = Interfaces::new().add_interface("org.example.Iface", iface).unwrap();
+            let server = runner.add_server("org.example.Test").unwrap();
+            server.add_object("/org/example", ifaces).unwrap();
+        }
+
+        assert!(runner.process_one(Some(Duration::from_secs(5))).unwrap());
+        assert!(runner.process_one(Some(Duration::from_secs(5))).unwrap());
+        assert!(!runner.conn.has_pending());
+        assert_eq!(runner.dispatch_pending().unwrap(), 0);
+        assert!(!runner.process_one(Some(Duration::from_secs(0))).unwrap());
     }
+    assert_eq!(pings.get(), 1);
+
+    let replies = bus.join()
+        .unwrap()
+        .into_iter()
+        .filter_map(|msg| {
+            match msg.message_type() {
+                MessageType::MethodReturn => Some("return"),
+                MessageType::Error => Some("error"),
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(replies, vec!["return", "error"]);
+}
+
+#[test]
+fn test_run_drops_unreadable_messages() {
+    use fd::UnixFd;
+    use interface::{Interface, Interfaces, Method};
+    use transport::Transport;
+
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    let (client, mut remote) = Transport::pair();
+    let peer = thread::spawn(move || {
+        let call = |serial: u32| {
+            let mut msg = Message::new_method_call("org.example.Test",
+                                                   "/org/example",
+                                                   "org.example.Iface",
+                                                   "Ping");
+            msg.message.serial = serial;
+            msg
+        };
+
+        // The header claims a file descriptor which is never sent.
+        let (fd, _) = UnixStream::pair().unwrap();
+        let (missing_fd, _) = call(1).add_argument(UnixFd::new(fd)).into_parts();
+        remote.send(&missing_fd, &[]).unwrap();
+        remote.send(&call(2).message, &[]).unwrap();
+
+        loop {
+            let (msg, _) = remote.read_message(None).unwrap().unwrap();
+            let msg = Message::new(msg);
+            if msg.message_type() != MessageType::Signal {
+                // Dropping the transport disconnects the runner.
+                return msg;
+            }
+        }
+    });
+
+    let mut runner = Runner::new(Connection::new_peer(client).unwrap()).unwrap();
+    {
+        let iface = Interface::new().add_method("Ping", Method::new(|_, _| Ok(vec![])));
+        let ifaces = Interfaces::new().add_interface("org.example.Iface", iface).unwrap();
+        runner.add_peer_server().unwrap().add_object("/org/example", ifaces).unwrap();
+    }
+    runner.run();
+
+    let reply = peer.join().unwrap();
+    assert_eq!(reply.message_type(), MessageType::MethodReturn);
+    assert_eq!(reply.reply_serial(), Some(2));
+}
+
+#[test]
+fn test_unknown_object_no_reply() {
+    use connection::test_connection;
+
+    let (conn, bus) = test_connection(true, |_| vec![]);
+    {
+        let mut runner = Runner::new(conn).unwrap();
+        runner.add_peer_server().unwrap();
+
+        let call = |serial: u32| {
+            let mut msg = Message::new_method_call("org.example.Test",
+                                                   "/org/example/missing",
+                                                   "org.example.Iface",
+                                                   "Ping");
+            msg.set_serial(serial);
+            msg
+        };
+
+        runner._handle_message(call(1));
+
+        let mut no_reply = call(2);
+        no_reply.set_flags(NO_REPLY_EXPECTED);
+        runner._handle_message(no_reply);
+    }
+
+    let replies = bus.join()
+        .unwrap()
+        .into_iter()
+        .filter(|msg| msg.message_type() != MessageType::Signal)
+        .collect::<Vec<_>>();
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].message_type(), MessageType::Error);
+    assert_eq!(replies[0].reply_serial(), Some(1));
+    assert_eq!(replies[0].error_name(),
+               Some("org.freedesktop.DBus.Error.UnknownObject".to_string()));
 }
@@ -3,40 +3,156 @@
 
 use crates::core::ops::DerefMut;
 
-use connection::{Connection, ReleaseNameReply, DO_NOT_QUEUE};
+use connection::{Connection, ReleaseNameReply, RequestNameFlags, RequestNameReply, DO_NOT_QUEUE};
+use context::SignalContext;
+use convert::FromDBusArgs;
 use error::*;
-use interface::InterfacesBuilder;
+use interface::{Argument, Interface, Interfaces, InterfacesBuilder, Method, MethodResult, Signal};
+use match_rule::MatchRule;
 use message::{Message, MessageType};
+use names::{BusName, IntoName, ObjectPath};
 use object::Object;
 use target::Target;
+use value::{Array, BasicValue, Dictionary, Path, Value};
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
 use std::collections::btree_map::{BTreeMap, Entry};
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 
-type SignalHandler = Rc<RefCell<FnMut(&Connection, &Target) -> ()>>;
-type SignalHandlers = Vec<SignalHandler>;
-type SignalHandlerMap = BTreeMap<Target, SignalHandlers>;
+type SignalHandler = Rc<RefCell<FnMut(&SignalContext) -> ()>>;
 
-fn _add_handler(handlers: &mut SignalHandlerMap, signal: Target, handler: SignalHandler) {
-    match handlers.entry(signal) {
-        Entry::Vacant(v) => {
-            v.insert(vec![handler]);
-        },
-        Entry::Occupied(o) => o.into_mut().push(handler),
-    };
+struct Subscription {
+    id: u64,
+    rule: MatchRule,
+    handler: SignalHandler,
+}
+
+type OwnershipHandler = Box<FnMut(bool) -> ()>;
+
+type Subscriptions = Rc<RefCell<Vec<Subscription>>>;
+type SubscriptionsRef = Weak<RefCell<Vec<Subscription>>>;
+
+#[must_use = "the handler is disconnected when the handle is dropped; use `detach` to keep it"]
+/// A handle to a signal handler connected to a server.
+///
+/// The handler is disconnected and its match rule removed from the bus when the handle is
+/// dropped.
+pub struct SubscriptionHandle {
+    conn: Weak<Connection>,
+    subscriptions: SubscriptionsRef,
+    id: u64,
+    rule: MatchRule,
+    connected: bool,
+}
+
+impl SubscriptionHandle {
+    /// The match rule used for the subscription.
+    pub fn match_rule(&self) -> &MatchRule {
+        &self.rule
+    }
+
+    /// Disconnect the handler.
+    pub fn disconnect(mut self) -> Result<()> {
+        self._disconnect()
+    }
+
+    /// Keep the handler connected for as long as the server exists.
+    pub fn detach(mut self) {
+        self.connected = false;
+    }
+
+    fn _disconnect(&mut self) -> Result<()> {
+        if !self.connected {
+            return Ok(());
+        }
+        self.connected = false;
+
+        if let Some(subscriptions) = self.subscriptions.upgrade() {
+            let id = self.id;
+            // Handlers may own other handles, so they are dropped once the list is released.
+            let removed = {
+                let mut subscriptions = subscriptions.borrow_mut();
+                subscriptions.iter()
+                    .position(|sub| sub.id == id)
+                    .map(|index| subscriptions.remove(index))
+            };
+            drop(removed);
+        }
+
+        match self.conn.upgrade() {
+            Some(conn) => conn.remove_match(&self.rule.to_string()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for SubscriptionHandle {
+    fn drop(&mut self) {
+        if let Err(err) = self._disconnect() {
+            println!("failed to remove match {}: {:?}", self.rule, err);
+        }
+    }
+}
+
+type ObjectMap = Rc<RefCell<BTreeMap<String, Object>>>;
+type ObjectMapRef = Weak<RefCell<BTreeMap<String, Object>>>;
+
+pub(crate) const OBJECT_MANAGER_INTERFACE: &'static str = "org.freedesktop.DBus.ObjectManager";
+
+struct ObjectManagerInterface;
+
+impl ObjectManagerInterface {
+    fn get_managed_objects(objects: ObjectMapRef) -> MethodResult {
+        let sobjects = objects.upgrade().expect("get_managed_objects: object map no longer exists?");
+        let objects_ref = sobjects.borrow();
+
+        let managed = objects_ref.iter()
+            .map(|(path, object)| {
+                (BasicValue::ObjectPath(Path(path.clone())),
+                 Value::Dictionary(object.interfaces().get_interfaces_and_properties()))
+            })
+            .collect();
+
+        Ok(vec![Value::Dictionary(Dictionary::new_with_sig(managed,
+                                                           "a{oa{sa{sv}}}".to_string()))])
+    }
+
+    pub fn new(objects: ObjectMapRef) -> Result<Interface> {
+        Ok(Interface::new()
+            .add_method("GetManagedObjects",
+                        Method::new(move |_, _| Self::get_managed_objects(objects.clone()))
+                            .add_result(Argument::new("objpath_interfaces_and_properties",
+                                                      "a{oa{sa{sv}}}")?))
+            .add_signal("InterfacesAdded",
+                        Signal::new()
+                            .add_argument(Argument::new("object_path", "o")?)
+                            .add_argument(Argument::new("interfaces_and_properties",
+                                                        "a{sa{sv}}")?))
+            .add_signal("InterfacesRemoved",
+                        Signal::new()
+                            .add_argument(Argument::new("object_path", "o")?)
+                            .add_argument(Argument::new("interfaces", "as")?)))
+    }
 }
 
 /// A representation of a collection of objects which implement an interface.
+///
+/// Servers which handle method calls expose the `org.freedesktop.DBus.ObjectManager` interface on
+/// the root object (`/`) which covers all other objects on the server.
 pub struct Server {
     conn: Rc<Connection>,
     name: String,
     can_handle: bool,
 
-    // TODO: store children information
-    objects: BTreeMap<String, Object>,
-    signals: SignalHandlerMap,
-    namespace_signals: SignalHandlerMap,
+    root: Option<Object>,
+    root_added: bool,
+    objects: ObjectMap,
+    subscriptions: Subscriptions,
+    next_handler_id: Cell<u64>,
+
+    owns_name: Cell<bool>,
+    ownership_changed: RefCell<Option<OwnershipHandler>>,
 }
 
 impl Server {
@@ -49,68 +165,192 @@ impl Server {
             name: name.to_string(),
             can_handle: false,
 
-            objects: BTreeMap::new(),
-            signals: SignalHandlerMap::new(),
-            namespace_signals: SignalHandlerMap::new(),
+            root: None,
+            root_added: false,
+            objects: Rc::new(RefCell::new(BTreeMap::new())),
+            subscriptions: Rc::new(RefCell::new(vec![])),
+            next_handler_id: Cell::new(0),
+
+            owns_name: Cell::new(false),
+            ownership_changed: RefCell::new(None),
         })
     }
 
     /// Create a new `Server` to handle method calls from the bus.
+    ///
+    /// Fails if the name is already owned on the bus.
     pub fn new<N>(conn: Rc<Connection>, name: N) -> Result<Self>
-        where N: ToString,
+        where N: IntoName<BusName>,
     {
-        let name = name.to_string();
-        conn.request_name(&name, DO_NOT_QUEUE)?;
+        Self::new_with_flags(conn, name, DO_NOT_QUEUE)
+    }
+
+    /// Create a new `Server` to handle method calls from the bus with the given request flags.
+    ///
+    /// Without `DO_NOT_QUEUE`, the server may be created while waiting in the queue for the name
+    /// (see `owns_name`). Fails if the name is already owned on the bus and the request was not
+    /// queued.
+    pub fn new_with_flags<N>(conn: Rc<Connection>, name: N, flags: RequestNameFlags)
+                             -> Result<Self>
+        where N: IntoName<BusName>,
+    {
+        let name = name.into_name()?.into_string();
+        let owns_name = match conn.request_name(&name, flags)? {
+            RequestNameReply::PrimaryOwner |
+            RequestNameReply::AlreadyOwner => true,
+            RequestNameReply::InQueue => false,
+            RequestNameReply::Exists => bail!(ErrorKind::NameExists(name)),
+        };
 
         // TODO: Add match for the server.
-        // TODO: add root object
-        // TODO: add ObjectManager interface
+
+        Self::_new_handler(conn, name, owns_name)
+    }
+
+    /// Create a new `Server` to handle method calls from a peer.
+    ///
+    /// Peer connections have no bus, so no name is requested and the server has an empty name.
+    pub fn new_peer(conn: Rc<Connection>) -> Result<Self> {
+        if !conn.is_peer() {
+            bail!(ErrorKind::NotPeer);
+        }
+
+        Self::_new_handler(conn, String::new(), false)
+    }
+
+    fn _new_handler(conn: Rc<Connection>, name: String, owns_name: bool) -> Result<Self> {
+        let objects = Rc::new(RefCell::new(BTreeMap::new()));
+        let root = Self::_root_object(&objects, Interfaces::new())?;
 
         Ok(Server {
             conn: conn,
             name: name,
             can_handle: true,
 
-            objects: BTreeMap::new(),
-            signals: SignalHandlerMap::new(),
-            namespace_signals: SignalHandlerMap::new(),
+            root: Some(root),
+            root_added: false,
+            objects: objects,
+            subscriptions: Rc::new(RefCell::new(vec![])),
+            next_handler_id: Cell::new(0),
+
+            owns_name: Cell::new(owns_name),
+            ownership_changed: RefCell::new(None),
         })
     }
 
+    fn _root_object(objects: &ObjectMap, ifaces: InterfacesBuilder) -> Result<Object> {
+        let children = Rc::new(RefCell::new(vec![]));
+        let ifaces = ifaces.add_interface(OBJECT_MANAGER_INTERFACE,
+                                          ObjectManagerInterface::new(Rc::downgrade(objects))?)?
+            .finalize(&children)?;
+
+        Ok(Object::new("/", ifaces, children))
+    }
+
     /// The name of the server.
     pub fn name(&self) -> &str {
         &self.name
     }
 
+    /// The connection the server uses.
+    pub fn connection(&self) -> &Rc<Connection> {
+        &self.conn
+    }
+
+    /// Whether the server is currently the primary owner of its name.
+    pub fn owns_name(&self) -> bool {
+        self.owns_name.get()
+    }
+
+    /// Set a callback for when the server gains or loses ownership of its name.
+    ///
+    /// The callback is given whether the server now owns the name. A server which allowed
+    /// replacement is told when another connection has replaced it.
+    pub fn on_ownership_changed<F>(&mut self, callback: F) -> &mut Self
+        where F: FnMut(bool) -> () + 'static
+    {
+        *self.ownership_changed.borrow_mut() = Some(Box::new(callback));
+
+        self
+    }
+
+    fn _track_ownership(&self, m: &Message) {
+        let ownership_rule = |member: &str| {
+            MatchRule::new_signal()
+                .sender("org.freedesktop.DBus")
+                .interface("org.freedesktop.DBus")
+                .member(member)
+                .arg(0, &self.name)
+        };
+
+        let owns_name = if ownership_rule("NameAcquired").matches(m) {
+            true
+        } else if ownership_rule("NameLost").matches(m) {
+            false
+        } else {
+            return;
+        };
+
+        if self.owns_name.replace(owns_name) != owns_name {
+            if let Some(ref mut callback) = *self.ownership_changed.borrow_mut() {
+                callback(owns_name);
+            }
+        }
+    }
+
     /// Add an object to the server with the given interfaces.
+    ///
+    /// The `org.freedesktop.DBus.ObjectManager.InterfacesAdded` signal is emitted for the new
+    /// object.
+    ///
+    /// The interfaces of the root object (`/`) are added alongside the
+    /// `org.freedesktop.DBus.ObjectManager` interface the server provides there. No signal is
+    /// emitted for it since it is not one of the objects the interface manages.
     pub fn add_object<P>(&mut self, path: P, ifaces: InterfacesBuilder) -> Result<&mut Self>
-        where P: ToString,
+        where P: IntoName<ObjectPath>,
     {
         if !self.can_handle {
             bail!(ErrorKind::NoServerName);
         }
 
-        // TODO: Validate the path is valid.
+        let path = path.into_name()?.into_string();
+        if path == "/" {
+            if self.root_added {
+                bail!(ErrorKind::PathAlreadyRegistered(path));
+            }
+
+            self.root = Some(Self::_root_object(&self.objects, ifaces)?);
+            self.root_added = true;
+            self._update_children();
+
+            return Ok(self);
+        }
 
-        match self.objects.entry(path.to_string()) {
+        let signal = match self.objects.borrow_mut().entry(path.clone()) {
             Entry::Vacant(v) => {
-                // TODO: store this
                 let children = Rc::new(RefCell::new(vec![]));
                 let finalized_ifaces = ifaces.finalize(&children)?;
-                let obj = Object::new(path, finalized_ifaces);
+                let obj = Object::new(&path, finalized_ifaces, children);
 
-                // TODO: emit InterfacesAdded signal
+                let signal = Self::_interfaces_added(&obj);
 
                 v.insert(obj);
 
-                Ok(())
+                signal
             },
-            Entry::Occupied(_) => bail!(ErrorKind::PathAlreadyRegistered(path.to_string())),
-        }
-        .map(|_| self)
+            Entry::Occupied(_) => bail!(ErrorKind::PathAlreadyRegistered(path)),
+        };
+        self._update_children();
+
+        self.conn.send(signal)?;
+
+        Ok(self)
     }
 
     /// Remove an object from the server.
+    ///
+    /// The `org.freedesktop.DBus.ObjectManager.InterfacesRemoved` signal is emitted for the
+    /// removed object. Removing the root object only removes the interfaces added to it.
     pub fn remove_object<P>(&mut self, path: P) -> Result<&mut Self>
         where P: AsRef<str>,
     {
@@ -118,9 +358,20 @@ impl Server {
             bail!(ErrorKind::NoServerName);
         }
 
-        match self.objects.remove(path.as_ref()) {
-            Some(_) => {
-                // TODO: emit InterfacesRemoved signal
+        // The root object goes back to only providing the object manager.
+        if path.as_ref() == "/" && self.root_added {
+            self.root = Some(Self::_root_object(&self.objects, Interfaces::new())?);
+            self.root_added = false;
+            self._update_children();
+
+            return Ok(self);
+        }
+
+        let removed = self.objects.borrow_mut().remove(path.as_ref());
+        match removed {
+            Some(obj) => {
+                self._update_children();
+                self.conn.send(Self::_interfaces_removed(&obj))?;
 
                 Ok(self)
             },
@@ -128,94 +379,221 @@ impl Server {
         }
     }
 
-    /// Connect a handler to a specific object's signal.
+    fn _update_children(&self) {
+        let objects = self.objects.borrow();
+
+        for object in objects.values().chain(self.root.iter()) {
+            let prefix = if object.path() == "/" {
+                "/".to_string()
+            } else {
+                format!("{}/", object.path())
+            };
+
+            // Only the first component below the object is a child node.
+            let children = objects.keys()
+                .filter(|path| path.starts_with(&prefix))
+                .filter_map(|path| path[prefix.len()..].split('/').next())
+                .map(ToString::to_string)
+                .collect::<BTreeSet<_>>();
+
+            object.set_children(children.into_iter().collect());
+        }
+    }
+
+    fn _interfaces_added(obj: &Object) -> Message {
+        Message::new_signal("/", OBJECT_MANAGER_INTERFACE, "InterfacesAdded")
+            .add_argument(&Value::BasicValue(BasicValue::ObjectPath(Path(obj.path().to_string()))))
+            .add_argument(&Value::Dictionary(obj.interfaces().get_interfaces_and_properties()))
+    }
+
+    fn _interfaces_removed(obj: &Object) -> Message {
+        let names = obj.interfaces()
+            .interface_names()
+            .into_iter()
+            .map(|name| Value::BasicValue(BasicValue::String(name)))
+            .collect();
+
+        Message::new_signal("/", OBJECT_MANAGER_INTERFACE, "InterfacesRemoved")
+            .add_argument(&Value::BasicValue(BasicValue::ObjectPath(Path(obj.path().to_string()))))
+            .add_argument(&Value::Array(Array::new_with_sig(names, "as".to_string())))
+    }
+
+    /// Connect a handler to messages matching a rule.
     ///
-    /// This will register a callback to listen to a specific object's signals.
-    pub fn connect<F>(&mut self, signal: Target, callback: F) -> Result<&mut Self>
-        where F: FnMut(&Connection, &Target) -> () + 'static
+    /// The rule is added to the bus and incoming signals are tested against it locally. The
+    /// handler is disconnected when the returned handle is dropped.
+    pub fn connect_rule<F>(&mut self, rule: MatchRule, callback: F) -> Result<SubscriptionHandle>
+        where F: FnMut(&SignalContext) -> () + 'static
     {
-        let dbus_match = format!("type='signal',interface='{}',path='{}',member='{}'",
-                                 signal.interface,
-                                 signal.object,
-                                 signal.method);
-        self.conn.add_match(&dbus_match)?;
+        self.conn.add_match(&rule.to_string())?;
 
-        _add_handler(&mut self.signals, signal, Rc::new(RefCell::new(callback)));
+        let id = self.next_handler_id.get();
+        self.next_handler_id.set(id + 1);
 
-        Ok(self)
+        self.subscriptions.borrow_mut().push(Subscription {
+            id: id,
+            rule: rule.clone(),
+            handler: Rc::new(RefCell::new(callback)),
+        });
+
+        Ok(SubscriptionHandle {
+            conn: Rc::downgrade(&self.conn),
+            subscriptions: Rc::downgrade(&self.subscriptions),
+            id: id,
+            rule: rule,
+            connected: true,
+        })
+    }
+
+    /// Connect a handler to a specific object's signal.
+    ///
+    /// This will register a callback to listen to a specific object's signals. The callback is
+    /// given the context of the signal, including its sender and arguments.
+    ///
+    /// The handler is disconnected when the returned handle is dropped.
+    pub fn connect<F>(&mut self, signal: Target, callback: F) -> Result<SubscriptionHandle>
+        where F: FnMut(&SignalContext) -> () + 'static
+    {
+        let rule = MatchRule::new_signal()
+            .interface(&signal.interface)
+            .path(&signal.object)
+            .member(&signal.method);
+
+        self.connect_rule(rule, callback)
     }
 
     /// Connect a handler to a set of objects' signals.
     ///
-    /// Any object underneath the requested object path's hierarchy emitting the requested signal
+    /// The object emitting the requested signal or any object underneath its path's hierarchy
     /// will trigger the callback.
-    pub fn connect_namespace<F>(&mut self, signal: Target, callback: F) -> Result<&mut Self>
-        where F: FnMut(&Connection, &Target) -> () + 'static
+    ///
+    /// The handler is disconnected when the returned handle is dropped.
+    pub fn connect_namespace<F>(&mut self, signal: Target, callback: F)
+                                -> Result<SubscriptionHandle>
+        where F: FnMut(&SignalContext) -> () + 'static
     {
-        let dbus_match = format!("type='signal',interface='{}',path_namespace='{}',member='{}'",
-                                 signal.interface,
-                                 signal.object,
-                                 signal.method);
-        self.conn.add_match(&dbus_match)?;
+        let rule = MatchRule::new_signal()
+            .interface(&signal.interface)
+            .path_namespace(&signal.object)
+            .member(&signal.method);
 
-        _add_handler(&mut self.namespace_signals,
-                     signal,
-                     Rc::new(RefCell::new(callback)));
+        self.connect_rule(rule, callback)
+    }
 
-        Ok(self)
+    /// Connect a handler to a specific object's signal with typed arguments.
+    ///
+    /// Signals with arguments which do not match the requested types are ignored.
+    pub fn connect_typed<T, F>(&mut self, signal: Target, callback: F)
+                               -> Result<SubscriptionHandle>
+        where T: FromDBusArgs + 'static,
+              F: FnMut(&SignalContext, T) -> () + 'static
+    {
+        self.connect(signal, Self::_typed_handler(callback))
+    }
+
+    /// Connect a handler to a set of objects' signals with typed arguments.
+    ///
+    /// Signals with arguments which do not match the requested types are ignored.
+    pub fn connect_namespace_typed<T, F>(&mut self, signal: Target, callback: F)
+                                         -> Result<SubscriptionHandle>
+        where T: FromDBusArgs + 'static,
+              F: FnMut(&SignalContext, T) -> () + 'static
+    {
+        self.connect_namespace(signal, Self::_typed_handler(callback))
+    }
+
+    fn _typed_handler<T, F>(mut callback: F) -> impl FnMut(&SignalContext) + 'static
+        where T: FromDBusArgs + 'static,
+              F: FnMut(&SignalContext, T) -> () + 'static
+    {
+        move |ctx: &SignalContext| {
+            match ctx.read() {
+                Ok(args) => callback(ctx, args),
+                Err(err) => {
+                    println!("ignoring signal {:?} with unexpected arguments: {:?}",
+                             ctx.target(),
+                             err)
+                },
+            }
+        }
+    }
+
+    /// Send `PropertiesChanged` signals for property changes on all objects on the server.
+    ///
+    /// This is called by the `Runner` after every message it handles, but needs to be called
+    /// manually if properties change outside of the handling of a message.
+    pub fn flush_properties_changed(&self) -> Result<()> {
+        let objects = self.objects.borrow();
+        for object in objects.values().chain(self.root.iter()) {
+            object.flush_properties_changed(&self.conn)?;
+        }
+
+        Ok(())
     }
 
     /// Handle a message with the appropriate handler.
     ///
+    /// Method calls are dispatched to the object registered at the message's path.
+    ///
     /// Returns `None` if the message was consumed, otherwise it returns the original message for
     /// further processing.
     pub fn handle_message<'b>(&self, m: &'b mut Message) -> Option<&'b mut Message> {
         match m.message_type() {
             MessageType::MethodCall => self._call_method(m),
-            MessageType::Signal => Some(self._match_signal(m)),
+            MessageType::Signal => {
+                if self.can_handle && !self.conn.is_peer() {
+                    self._track_ownership(m);
+                }
+
+                Some(self._match_signal(m))
+            },
             _ => Some(m),
         }
     }
 
     fn _call_method<'b>(&self, m: &'b mut Message) -> Option<&'b mut Message> {
-        let conn = self.conn.clone();
-        self.objects.iter().fold(Some(m), |opt_m, (_, object)| {
-            opt_m.and_then(|mut m| {
-                match object.handle_message(&conn, &mut m) {
-                    None => Some(m),
-                    Some(Ok(())) => None,
-                    Some(Err(())) => {
-                        println!("failed to send a reply for {:?}", m);
-                        None
-                    },
-                }
-            })
-        })
+        let path = match m.path() {
+            Some(path) => path,
+            None => return Some(m),
+        };
+
+        let objects = self.objects.borrow();
+        let opt_object = if path == "/" {
+            self.root.as_ref()
+        } else {
+            objects.get(&path)
+        };
+
+        let res = match opt_object.and_then(|object| object.handle_message(&self.conn, m)) {
+            None => Some(m),
+            Some(Ok(())) => None,
+            Some(Err(())) => {
+                println!("failed to send a reply for {:?}", m);
+                None
+            },
+        };
+
+        res
     }
 
     fn _match_signal<'b>(&self, m: &'b mut Message) -> &'b mut Message {
-        let conn = self.conn.clone();
+        if let Some(signal) = Target::extract(m) {
+            let ctx = SignalContext::new(&self.conn, &signal, m);
 
-        Target::extract(m).map(|signal| {
-            for handlers in self.signals.get(&signal) {
-                for handler in handlers.iter() {
-                    let mut cb = handler.borrow_mut();
+            // Collect the handlers first so that handlers may disconnect themselves.
+            let handlers = self.subscriptions
+                .borrow()
+                .iter()
+                .filter(|sub| sub.rule.matches(ctx.message()))
+                .map(|sub| sub.handler.clone())
+                .collect::<Vec<_>>();
 
-                    cb.deref_mut()(&conn, &signal);
-                }
-            }
-
-            let matched_handlers =
-                self.namespace_signals.iter().filter(|&(expect, _)| expect.namespace_eq(&signal));
-
-            for (_, handlers) in matched_handlers {
-                for handler in handlers.iter() {
-                    let mut cb = handler.borrow_mut();
+            for handler in handlers {
+                let mut cb = handler.borrow_mut();
 
-                    cb.deref_mut()(&conn, &signal);
-                }
+                cb.deref_mut()(&ctx);
             }
-        });
+        }
 
         m
     }
@@ -223,7 +601,7 @@ impl Server {
 
 impl Drop for Server {
     fn drop(&mut self) {
-        if !self.can_handle {
+        if !self.can_handle || self.conn.is_peer() {
             return;
         }
 
@@ -233,10 +611,13 @@ impl Drop for Server {
                 match reply {
                     ReleaseNameReply::Released => (),
                     ReleaseNameReply::NonExistent => {
-                        panic!("internal error: non-existent name {}?!", self.name)
+                        println!("failed to release {}: the name does not exist", self.name)
                     },
+                    // The name may have been lost to another connection.
                     ReleaseNameReply::NotOwner => {
-                        panic!("internal error: not the owner of {}?!", self.name)
+                        if self.owns_name.get() {
+                            println!("failed to release {}: not the owner", self.name)
+                        }
                     },
                 }
             },
@@ -244,3 +625,357 @@ impl Drop for Server {
         }
     }
 }
+
+#[cfg(test)]
+fn _test_interfaces() -> InterfacesBuilder {
+    use interface::{Property, PropertyGetResult, PropertyReadHandler};
+    use value::Signature;
+
+    struct Count;
+
+    impl PropertyReadHandler for Count {
+        fn get(&self) -> PropertyGetResult {
+            Ok(Value::BasicValue(BasicValue::Uint32(1)))
+        }
+    }
+
+    Interfaces::new()
+        .add_interface("org.example.Counter",
+                       Interface::new()
+                           .add_property("Count",
+                                         Property::new_ro("u", Box::new(Count)).unwrap()))
+        .unwrap()
+}
+
+#[test]
+fn test_object_manager() {
+    let path = BasicValue::ObjectPath(Path("/org/example/counter".to_string()));
+    let is_path = |value: &Value| {
+        match *value {
+            Value::BasicValue(BasicValue::ObjectPath(Path(ref path))) => {
+                path == "/org/example/counter"
+            },
+            _ => false,
+        }
+    };
+    let objects: ObjectMap = Rc::new(RefCell::new(BTreeMap::new()));
+    let children = Rc::new(RefCell::new(vec![]));
+    let ifaces = _test_interfaces().finalize(&children).unwrap();
+    objects.borrow_mut()
+        .insert("/org/example/counter".to_string(),
+                Object::new("/org/example/counter", ifaces, children));
+
+    let count = |interfaces: &Value| {
+        let interfaces = match *interfaces {
+            Value::Dictionary(ref dict) => dict,
+            ref value => panic!("unexpected interfaces: {:?}", value),
+        };
+        assert!(interfaces.map
+            .contains_key(&BasicValue::String("org.freedesktop.DBus.Properties".to_string())));
+        let counter = &interfaces.map[&BasicValue::String("org.example.Counter".to_string())];
+        match *counter {
+            Value::Dictionary(ref dict) => {
+                match dict.map[&BasicValue::String("Count".to_string())] {
+                    Value::Variant(ref count) => {
+                        match *count.object {
+                            Value::BasicValue(BasicValue::Uint32(count)) => count,
+                            ref value => panic!("unexpected count: {:?}", value),
+                        }
+                    },
+                    ref value => panic!("unexpected count: {:?}", value),
+                }
+            },
+            ref value => panic!("unexpected properties: {:?}", value),
+        }
+    };
+
+    let managed = match ObjectManagerInterface::get_managed_objects(Rc::downgrade(&objects)) {
+        Ok(managed) => managed,
+        Err(_) => panic!("GetManagedObjects failed"),
+    };
+    match managed[0] {
+        Value::Dictionary(ref dict) => {
+            assert_eq!(dict.map.len(), 1);
+            assert_eq!(count(&dict.map[&path]), 1);
+        },
+        ref value => panic!("unexpected managed objects: {:?}", value),
+    }
+
+    let objects = objects.borrow();
+    let object = &objects["/org/example/counter"];
+
+    let added = Server::_interfaces_added(object).values().unwrap().unwrap();
+    assert_eq!(added.len(), 2);
+    assert!(is_path(&added[0]));
+    assert_eq!(count(&added[1]), 1);
+
+    let removed = Server::_interfaces_removed(object).values().unwrap().unwrap();
+    assert_eq!(removed.len(), 2);
+    assert!(is_path(&removed[0]));
+    match removed[1] {
+        Value::Array(ref array) => {
+            assert!(array.objects.iter().any(|name| {
+                match *name {
+                    Value::BasicValue(BasicValue::String(ref name)) => {
+                        name == "org.example.Counter"
+                    },
+                    _ => false,
+                }
+            }));
+        },
+        ref value => panic!("unexpected interfaces: {:?}", value),
+    }
+}
+
+#[test]
+fn test_object_children() {
+    use connection::test_connection;
+
+    let (conn, _) = test_connection(false, |msg| vec![msg.return_message().add_argument(&1u32)]);
+    let mut server = Server::new(Rc::new(conn), "org.example.Test").unwrap();
+    server.add_object("/org/example", _test_interfaces()).unwrap();
+    server.add_object("/org/example/a", _test_interfaces()).unwrap();
+    server.add_object("/org/example/b/c", _test_interfaces()).unwrap();
+    server.add_object("/net/example", _test_interfaces()).unwrap();
+
+    let children = |path: &str| server.objects.borrow()[path].children();
+    assert_eq!(server.root.as_ref().unwrap().children(), vec!["net", "org"]);
+    assert_eq!(children("/org/example"), vec!["a", "b"]);
+    assert!(children("/org/example/a").is_empty());
+
+    server.remove_object("/net/example").unwrap();
+    assert_eq!(server.root.as_ref().unwrap().children(), vec!["org"]);
+}
+
+#[test]
+fn test_root_object() {
+    use connection::test_connection;
+
+    let (conn, _) = test_connection(false, |msg| vec![msg.return_message().add_argument(&1u32)]);
+    let mut server = Server::new(Rc::new(conn), "org.example.Test").unwrap();
+    server.add_object("/org/example", _test_interfaces()).unwrap();
+    server.add_object("/", _test_interfaces()).unwrap();
+    match server.add_object("/", _test_interfaces()) {
+        Err(err) => {
+            match *err.kind() {
+                ErrorKind::PathAlreadyRegistered(ref path) => assert_eq!(path, "/"),
+                ref kind => panic!("unexpected error: {:?}", kind),
+            }
+        },
+        Ok(_) => panic!("the root object was added twice"),
+    }
+
+    {
+        let root = server.root.as_ref().unwrap();
+        let names = root.interfaces().interface_names();
+        assert!(names.contains(&"org.example.Counter".to_string()));
+        assert!(names.contains(&OBJECT_MANAGER_INTERFACE.to_string()));
+        assert_eq!(root.children(), vec!["org"]);
+    }
+
+    server.remove_object("/").unwrap();
+    let names = server.root.as_ref().unwrap().interfaces().interface_names();
+    assert!(!names.contains(&"org.example.Counter".to_string()));
+    assert!(names.contains(&OBJECT_MANAGER_INTERFACE.to_string()));
+    assert_eq!(server.root.as_ref().unwrap().children(), vec!["org"]);
+    assert!(server.remove_object("/").is_err());
+}
+
+#[test]
+fn test_method_dispatch() {
+    use crates::dbus_bytestream::message::HEADER_FIELD_INTERFACE;
+    use connection::test_connection;
+
+    let name = "org.example.Test";
+    let (conn, _) = test_connection(false, |msg| vec![msg.return_message().add_argument(&1u32)]);
+    let mut server = Server::new(Rc::new(conn), name).unwrap();
+    let calls = Rc::new(RefCell::new(vec![]));
+    for node in &["a", "b"] {
+        let node_calls = calls.clone();
+        let node_name = node.to_string();
+        let method = Method::new(move |_, _| {
+            node_calls.borrow_mut().push(node_name.clone());
+            Ok(vec![])
+        });
+        let iface = Interface::new().add_method("Name", method);
+        let ifaces = Interfaces::new().add_interface("org.example.Node", iface).unwrap();
+        server.add_object(format!("/org/example/{}", node), ifaces).unwrap();
+    }
+
+    let call = |path: &str| Message::new_method_call(name, path, "org.example.Node", "Name");
+
+    assert!(server.handle_message(&mut call("/org/example/a")).is_none());
+    assert!(server.handle_message(&mut call("/org/example/b")).is_none());
+
+    // Calls without an interface are dispatched to the interface with the method.
+    let mut no_interface = call("/org/example/b");
+    no_interface.message.headers.retain(|field| {
+        match field.objects.first() {
+            Some(&Value::BasicValue(BasicValue::Byte(code))) => code != HEADER_FIELD_INTERFACE,
+            _ => true,
+        }
+    });
+    assert_eq!(no_interface.interface(), None);
+    assert!(server.handle_message(&mut no_interface).is_none());
+
+    // Calls to paths without an object are left for the runner to reject.
+    assert!(server.handle_message(&mut call("/org/example/missing")).is_some());
+
+    assert_eq!(*calls.borrow(), vec!["a", "b", "b"]);
+}
+
+#[test]
+fn test_signal_context() {
+    use crates::dbus_bytestream::message::HEADER_FIELD_SENDER;
+    use connection::test_connection;
+    use value::{Signature, Struct, Variant};
+
+    let (conn, _) = test_connection(false, |msg| vec![msg.return_message().add_argument(&1u32)]);
+    let mut server = Server::new_listener(Rc::new(conn), "org.example.Listener").unwrap();
+    let seen = Rc::new(RefCell::new(vec![]));
+    let typed = Rc::new(RefCell::new(vec![]));
+
+    let target = |path: &str| Target::new("org.example.Iface", path, "Changed").unwrap();
+    {
+        let seen = seen.clone();
+        server.connect(target("/org/example/a"), move |ctx| {
+                seen.borrow_mut().push((ctx.sender().map(ToString::to_string),
+                                        ctx.path().to_string(),
+                                        ctx.arguments().unwrap().len()));
+            })
+            .unwrap()
+            .detach();
+    }
+    {
+        let typed = typed.clone();
+        server.connect_namespace_typed(target("/org/example"),
+                                     move |ctx, (name, count): (String, u32)| {
+                typed.borrow_mut().push((ctx.path().to_string(), name, count));
+            })
+            .unwrap()
+            .detach();
+    }
+
+    let signal = |path: &str, sender: &str| {
+        let mut msg = Message::new_signal(path, "org.example.Iface", "Changed");
+        let sender = Value::BasicValue(BasicValue::String(sender.to_string()));
+        msg.message.headers.push(Struct {
+            objects: vec![Value::BasicValue(BasicValue::Byte(HEADER_FIELD_SENDER)),
+                          Value::Variant(Variant::new(sender, "s"))],
+            signature: Signature("(yv)".to_string()),
+        });
+        msg
+    };
+
+    let mut a = signal("/org/example/a", ":1.5")
+        .add_argument(&"a".to_string())
+        .add_argument(&1u32);
+    assert!(server.handle_message(&mut a).is_some());
+    let mut b = signal("/org/example/b", ":1.6")
+        .add_argument(&"b".to_string())
+        .add_argument(&2u32);
+    assert!(server.handle_message(&mut b).is_some());
+    // Signals with unexpected arguments are not given to typed handlers.
+    let mut untyped = signal("/org/example/a", ":1.7").add_argument(&3u32);
+    assert!(server.handle_message(&mut untyped).is_some());
+
+    assert_eq!(*seen.borrow(),
+               vec![(Some(":1.5".to_string()), "/org/example/a".to_string(), 2),
+                    (Some(":1.7".to_string()), "/org/example/a".to_string(), 1)]);
+    assert_eq!(*typed.borrow(),
+               vec![("/org/example/a".to_string(), "a".to_string(), 1),
+                    ("/org/example/b".to_string(), "b".to_string(), 2)]);
+}
+
+#[test]
+fn test_subscription_handle() {
+    use connection::test_connection;
+
+    let (conn, bus) = test_connection(false, |msg| vec![msg.return_message()]);
+    let conn = Rc::new(conn);
+    let target = |member: &str| Target::new("org.example.Iface", "/org/example", member).unwrap();
+
+    let (dropped_rule, kept_rule) = {
+        let mut server = Server::new_listener(conn.clone(), "org.example.Listener").unwrap();
+
+        let handle = server.connect(target("Dropped"), |_| ()).unwrap();
+        let dropped_rule = handle.match_rule().to_string();
+        assert_eq!(server.subscriptions.borrow().len(), 1);
+        drop(handle);
+        assert!(server.subscriptions.borrow().is_empty());
+
+        // Detached handlers stay connected.
+        let handle = server.connect(target("Kept"), |_| ()).unwrap();
+        let kept_rule = handle.match_rule().to_string();
+        handle.detach();
+        assert_eq!(server.subscriptions.borrow().len(), 1);
+
+        (dropped_rule, kept_rule)
+    };
+    drop(conn);
+
+    let calls = bus.join()
+        .unwrap()
+        .into_iter()
+        .map(|msg| (msg.member().unwrap(), msg.read::<(String,)>().unwrap().0))
+        .collect::<Vec<_>>();
+    assert_eq!(calls,
+               vec![("AddMatch".to_string(), dropped_rule.clone()),
+                    ("RemoveMatch".to_string(), dropped_rule),
+                    ("AddMatch".to_string(), kept_rule)]);
+}
+
+#[test]
+fn test_release_lost_name() {
+    use crates::dbus_bytestream::message::HEADER_FIELD_SENDER;
+    use connection::test_connection;
+    use value::{Signature, Struct, Variant};
+
+    let (conn, bus) = test_connection(false, |msg| {
+        let reply = msg.return_message();
+        let reply = match msg.member().unwrap().as_str() {
+            "RequestName" => reply.add_argument(&1u32),
+            "ReleaseName" => {
+                let (name,): (String,) = msg.read().unwrap();
+                match name.as_str() {
+                    "org.example.Lost" => reply.add_argument(&3u32),
+                    _ => reply.add_argument(&2u32),
+                }
+            },
+            _ => reply,
+        };
+
+        vec![reply]
+    });
+    let conn = Rc::new(conn);
+
+    {
+        let server = Server::new(conn.clone(), "org.example.Lost").unwrap();
+        let mut lost = Message::new_signal("/org/freedesktop/DBus",
+                                           "org.freedesktop.DBus",
+                                           "NameLost")
+            .add_argument(&"org.example.Lost".to_string());
+        let sender = Value::BasicValue(BasicValue::String("org.freedesktop.DBus".to_string()));
+        lost.message.headers.push(Struct {
+            objects: vec![Value::BasicValue(BasicValue::Byte(HEADER_FIELD_SENDER)),
+                          Value::Variant(Variant::new(sender, "s"))],
+            signature: Signature("(yv)".to_string()),
+        });
+        server._track_ownership(&lost);
+        assert!(!server.owns_name());
+    }
+    {
+        // The bus claims the name does not exist even though the server owns it.
+        let server = Server::new(conn.clone(), "org.example.Gone").unwrap();
+        assert!(server.owns_name());
+    }
+    drop(conn);
+
+    let released = bus.join()
+        .unwrap()
+        .into_iter()
+        .filter(|msg| msg.member() == Some("ReleaseName".to_string()))
+        .map(|msg| msg.read::<(String,)>().unwrap().0)
+        .collect::<Vec<_>>();
+    assert_eq!(released, vec!["org.example.Lost", "org.example.Gone"]);
+}
@@ -0,0 +1,431 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use convert::DBusType;
+use error::*;
+use value::Signature;
+
+use std::fmt;
+
+/// The maximum length of a signature.
+const MAX_SIGNATURE_LENGTH: usize = 255;
+/// The maximum nesting depth of arrays and of structures.
+const MAX_NESTING_DEPTH: usize = 32;
+
+type ValidateResult = ::std::result::Result<(), &'static str>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// A single complete type in a D-Bus signature.
+///
+/// Signatures may be parsed from strings or built up from the constructor functions:
+///
+/// ```
+/// use rust_bus::Sig;
+///
+/// let sig = Sig::array(Sig::dict(Sig::string(), Sig::variant()));
+/// assert_eq!(sig.to_string(), "a{sv}");
+/// assert_eq!(Sig::parse("a{sv}").unwrap(), sig);
+/// ```
+pub enum Sig {
+    /// An unsigned 8-bit integer (`y`).
+    Byte,
+    /// A boolean (`b`).
+    Boolean,
+    /// A signed 16-bit integer (`n`).
+    Int16,
+    /// An unsigned 16-bit integer (`q`).
+    Uint16,
+    /// A signed 32-bit integer (`i`).
+    Int32,
+    /// An unsigned 32-bit integer (`u`).
+    Uint32,
+    /// A signed 64-bit integer (`x`).
+    Int64,
+    /// An unsigned 64-bit integer (`t`).
+    Uint64,
+    /// A double-precision floating point number (`d`).
+    Double,
+    /// A string (`s`).
+    String,
+    /// An object path (`o`).
+    ObjectPath,
+    /// A signature (`g`).
+    Signature,
+    /// A Unix file descriptor (`h`).
+    UnixFd,
+    /// A value of any type (`v`).
+    Variant,
+    /// An array of values (`a`).
+    Array(Box<Sig>),
+    /// An entry in a dictionary (`{}`); only valid as the element of an array.
+    DictEntry(Box<Sig>, Box<Sig>),
+    /// A structure of values (`()`).
+    Struct(Vec<Sig>),
+}
+
+impl Sig {
+    /// The signature for a byte.
+    pub fn byte() -> Self {
+        Sig::Byte
+    }
+
+    /// The signature for a boolean.
+    pub fn boolean() -> Self {
+        Sig::Boolean
+    }
+
+    /// The signature for a signed 16-bit integer.
+    pub fn int16() -> Self {
+        Sig::Int16
+    }
+
+    /// The signature for an unsigned 16-bit integer.
+    pub fn uint16() -> Self {
+        Sig::Uint16
+    }
+
+    /// The signature for a signed 32-bit integer.
+    pub fn int32() -> Self {
+        Sig::Int32
+    }
+
+    /// The signature for an unsigned 32-bit integer.
+    pub fn uint32() -> Self {
+        Sig::Uint32
+    }
+
+    /// The signature for a signed 64-bit integer.
+    pub fn int64() -> Self {
+        Sig::Int64
+    }
+
+    /// The signature for an unsigned 64-bit integer.
+    pub fn uint64() -> Self {
+        Sig::Uint64
+    }
+
+    /// The signature for a double.
+    pub fn double() -> Self {
+        Sig::Double
+    }
+
+    /// The signature for a string.
+    pub fn string() -> Self {
+        Sig::String
+    }
+
+    /// The signature for an object path.
+    pub fn object_path() -> Self {
+        Sig::ObjectPath
+    }
+
+    /// The signature for a signature.
+    pub fn signature() -> Self {
+        Sig::Signature
+    }
+
+    /// The signature for a Unix file descriptor.
+    pub fn unix_fd() -> Self {
+        Sig::UnixFd
+    }
+
+    /// The signature for a variant.
+    pub fn variant() -> Self {
+        Sig::Variant
+    }
+
+    /// The signature for an array of the given type.
+    pub fn array(elem: Sig) -> Self {
+        Sig::Array(Box::new(elem))
+    }
+
+    /// The signature for a dictionary entry.
+    ///
+    /// Wrap in `Sig::array` to create a dictionary.
+    pub fn dict(key: Sig, value: Sig) -> Self {
+        Sig::DictEntry(Box::new(key), Box::new(value))
+    }
+
+    /// The signature for a structure of the given types.
+    pub fn structure(fields: Vec<Sig>) -> Self {
+        Sig::Struct(fields)
+    }
+
+    /// The signature of a Rust type.
+    pub fn of<T>() -> Result<Self>
+        where T: DBusType,
+    {
+        Self::parse(&T::signature())
+    }
+
+    /// Whether the type is a basic type (which may be used as a dictionary key).
+    pub fn is_basic(&self) -> bool {
+        match *self {
+            Sig::Variant | Sig::Array(_) | Sig::DictEntry(_, _) | Sig::Struct(_) => false,
+            _ => true,
+        }
+    }
+
+    /// Parse a single complete type.
+    pub fn parse(sig: &str) -> Result<Self> {
+        let mut sigs = Self::parse_list(sig)?;
+        if sigs.len() != 1 {
+            bail!(ErrorKind::InvalidSignature(sig.to_string(),
+                                              "must be a single complete type".to_string()));
+        }
+
+        Ok(sigs.remove(0))
+    }
+
+    /// Parse a signature made of any number of complete types.
+    pub fn parse_list(sig: &str) -> Result<Vec<Self>> {
+        _parse_list(sig).map_err(|reason| {
+            ErrorKind::InvalidSignature(sig.to_string(), reason.to_string()).into()
+        })
+    }
+
+    /// Check that the type follows the restrictions of the specification.
+    pub fn validate(&self) -> Result<()> {
+        let sig = self.to_string();
+
+        _validate_length(&sig)
+            .and_then(|_| self._validate(0, 0, false))
+            .map_err(|reason| ErrorKind::InvalidSignature(sig, reason.to_string()).into())
+    }
+
+    fn _validate(&self, arrays: usize, structs: usize, in_array: bool) -> ValidateResult {
+        match *self {
+            Sig::Array(ref elem) => {
+                if arrays == MAX_NESTING_DEPTH {
+                    return Err("arrays are nested too deeply");
+                }
+
+                elem._validate(arrays + 1, structs, true)
+            },
+            Sig::DictEntry(ref key, ref value) => {
+                if !in_array {
+                    return Err("dictionary entries must be array elements");
+                }
+                if !key.is_basic() {
+                    return Err("dictionary keys must be basic types");
+                }
+                if structs == MAX_NESTING_DEPTH {
+                    return Err("structures are nested too deeply");
+                }
+
+                value._validate(arrays, structs + 1, false)
+            },
+            Sig::Struct(ref fields) => {
+                if fields.is_empty() {
+                    return Err("empty structure");
+                }
+                if structs == MAX_NESTING_DEPTH {
+                    return Err("structures are nested too deeply");
+                }
+
+                fields.iter().fold(Ok(()), |res, field| {
+                    res.and_then(|_| field._validate(arrays, structs + 1, false))
+                })
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn _write(&self, out: &mut String) {
+        match *self {
+            Sig::Byte => out.push('y'),
+            Sig::Boolean => out.push('b'),
+            Sig::Int16 => out.push('n'),
+            Sig::Uint16 => out.push('q'),
+            Sig::Int32 => out.push('i'),
+            Sig::Uint32 => out.push('u'),
+            Sig::Int64 => out.push('x'),
+            Sig::Uint64 => out.push('t'),
+            Sig::Double => out.push('d'),
+            Sig::String => out.push('s'),
+            Sig::ObjectPath => out.push('o'),
+            Sig::Signature => out.push('g'),
+            Sig::UnixFd => out.push('h'),
+            Sig::Variant => out.push('v'),
+            Sig::Array(ref elem) => {
+                out.push('a');
+                elem._write(out);
+            },
+            Sig::DictEntry(ref key, ref value) => {
+                out.push('{');
+                key._write(out);
+                value._write(out);
+                out.push('}');
+            },
+            Sig::Struct(ref fields) => {
+                out.push('(');
+                for field in fields {
+                    field._write(out);
+                }
+                out.push(')');
+            },
+        }
+    }
+}
+
+impl fmt::Display for Sig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        self._write(&mut out);
+        write!(f, "{}", out)
+    }
+}
+
+fn _validate_length(sig: &str) -> ValidateResult {
+    if sig.len() > MAX_SIGNATURE_LENGTH {
+        Err("longer than 255 characters")
+    } else {
+        Ok(())
+    }
+}
+
+fn _parse_list(sig: &str) -> ::std::result::Result<Vec<Sig>, &'static str> {
+    // Checking the length first also bounds the recursion of the parser.
+    _validate_length(sig)?;
+
+    let mut chars = sig.chars().peekable();
+    let mut sigs = vec![];
+
+    while chars.peek().is_some() {
+        let parsed = _parse_one(&mut chars)?;
+        parsed._validate(0, 0, false)?;
+        sigs.push(parsed);
+    }
+
+    Ok(sigs)
+}
+
+fn _parse_one<I>(chars: &mut ::std::iter::Peekable<I>) -> ::std::result::Result<Sig, &'static str>
+    where I: Iterator<Item = char>,
+{
+    let c = match chars.next() {
+        Some(c) => c,
+        None => return Err("incomplete type"),
+    };
+
+    Ok(match c {
+        'y' => Sig::Byte,
+        'b' => Sig::Boolean,
+        'n' => Sig::Int16,
+        'q' => Sig::Uint16,
+        'i' => Sig::Int32,
+        'u' => Sig::Uint32,
+        'x' => Sig::Int64,
+        't' => Sig::Uint64,
+        'd' => Sig::Double,
+        's' => Sig::String,
+        'o' => Sig::ObjectPath,
+        'g' => Sig::Signature,
+        'h' => Sig::UnixFd,
+        'v' => Sig::Variant,
+        'a' => Sig::array(_parse_one(chars)?),
+        '(' => {
+            let mut fields = vec![];
+            loop {
+                match chars.peek() {
+                    Some(&')') => {
+                        chars.next();
+                        break;
+                    },
+                    Some(_) => fields.push(_parse_one(chars)?),
+                    None => return Err("unterminated structure"),
+                }
+            }
+
+            Sig::structure(fields)
+        },
+        '{' => {
+            let key = _parse_one(chars)?;
+            let value = _parse_one(chars)?;
+            if chars.next() != Some('}') {
+                return Err("dictionary entries must contain exactly two types");
+            }
+
+            Sig::dict(key, value)
+        },
+        ')' | '}' => return Err("unbalanced brackets"),
+        _ => return Err("unknown type code"),
+    })
+}
+
+/// A trait for values which may be converted into a single complete type.
+pub trait IntoSig {
+    /// Parse or validate the value as a signature.
+    fn into_sig(self) -> Result<Sig>;
+}
+
+impl IntoSig for Sig {
+    fn into_sig(self) -> Result<Sig> {
+        self.validate()?;
+        Ok(self)
+    }
+}
+
+impl<'a> IntoSig for &'a Sig {
+    fn into_sig(self) -> Result<Sig> {
+        self.clone().into_sig()
+    }
+}
+
+impl<'a> IntoSig for &'a str {
+    fn into_sig(self) -> Result<Sig> {
+        Sig::parse(self)
+    }
+}
+
+impl<'a> IntoSig for &'a String {
+    fn into_sig(self) -> Result<Sig> {
+        Sig::parse(self)
+    }
+}
+
+impl IntoSig for String {
+    fn into_sig(self) -> Result<Sig> {
+        Sig::parse(&self)
+    }
+}
+
+impl IntoSig for Signature {
+    fn into_sig(self) -> Result<Sig> {
+        Sig::parse(&self.0)
+    }
+}
+
+#[test]
+fn test_parse_signatures() {
+    assert_eq!(Sig::parse("a{sv}").unwrap(),
+               Sig::array(Sig::dict(Sig::string(), Sig::variant())));
+    assert_eq!(Sig::parse("(ia(ss))").unwrap(),
+               Sig::structure(vec![Sig::int32(),
+                                   Sig::array(Sig::structure(vec![Sig::string(),
+                                                                  Sig::string()]))]));
+    assert_eq!(Sig::parse_list("sua{oa{sa{sv}}}").unwrap().len(), 3);
+    assert_eq!(Sig::parse_list("").unwrap(), vec![]);
+
+    assert!(Sig::parse("").is_err());
+    assert!(Sig::parse("ss").is_err());
+    assert!(Sig::parse("a").is_err());
+    assert!(Sig::parse("()").is_err());
+    assert!(Sig::parse("(s").is_err());
+    assert!(Sig::parse("{sv}").is_err());
+    assert!(Sig::parse("a{vs}").is_err());
+    assert!(Sig::parse("a{sss}").is_err());
+    assert!(Sig::parse("z").is_err());
+    assert!(Sig::parse(&format!("{}y", "a".repeat(33))).is_err());
+    assert!(Sig::parse(&format!("{}y", "a".repeat(32))).is_ok());
+    assert!(Sig::parse_list(&"s".repeat(256)).is_err());
+}
+
+#[test]
+fn test_build_signatures() {
+    assert_eq!(Sig::array(Sig::dict(Sig::object_path(), Sig::variant())).to_string(),
+               "a{ov}");
+    assert!(Sig::dict(Sig::string(), Sig::variant()).validate().is_err());
+    assert!(Sig::structure(vec![]).validate().is_err());
+    assert_eq!(Sig::of::<(u32, Vec<String>)>().unwrap().to_string(), "(uas)");
+}
@@ -1,17 +1,19 @@
 // Distributed under the OSI-approved BSD 3-Clause License.
 // See accompanying LICENSE file for details.
 
+use error::*;
 use message::Message;
+use names::{InterfaceName, IntoName, MemberName, ObjectPath};
 
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 /// A representation of a signal which may be emitted.
 pub struct Target {
     /// The interface the signal belongs to.
-    pub interface: String,
+    pub interface: InterfaceName,
     /// The object path which will emit the signal.
-    pub object: String,
+    pub object: ObjectPath,
     /// The method name of the signal.
-    pub method: String,
+    pub method: MemberName,
 }
 
 struct SignalHeaders {
@@ -22,23 +24,26 @@ struct SignalHeaders {
 
 impl Target {
     /// Create a new `Target` structure.
-    pub fn new<I, O, M>(interface: I, object: O, method: M) -> Self
-        where I: ToString,
-              O: ToString,
-              M: ToString,
+    ///
+    /// Fails if any of the names are invalid.
+    pub fn new<I, O, M>(interface: I, object: O, method: M) -> Result<Self>
+        where I: IntoName<InterfaceName>,
+              O: IntoName<ObjectPath>,
+              M: IntoName<MemberName>,
     {
-        Target {
-            interface: interface.to_string(),
-            object: object.to_string(),
-            method: method.to_string(),
-        }
+        Ok(Target {
+            interface: interface.into_name()?,
+            object: object.into_name()?,
+            method: method.into_name()?,
+        })
     }
 
     /// Extract the signal from a `Message`.
     ///
     /// Returns `None` if parsing fails.
     pub fn extract(m: &Message) -> Option<Self> {
-        SignalHeaders::new(m).map(|hdrs| Self::new(hdrs.interface, hdrs.object, hdrs.method))
+        SignalHeaders::new(m)
+            .and_then(|hdrs| Self::new(hdrs.interface, hdrs.object, hdrs.method).ok())
     }
 
     /// Test if a `Target` matches this target.
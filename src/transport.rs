@@ -0,0 +1,807 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use crates::dbus_bytestream::demarshal::demarshal;
+use crates::dbus_bytestream::marshal::Marshal;
+use crates::dbus_bytestream::message;
+use crates::libc;
+
+use address::Address;
+use auth::{AuthPolicy, Authenticator};
+use context::Credentials;
+use error::*;
+use fd::{self, UnixFd};
+use value::{Array, BasicValue, Signature, Value};
+
+use std::collections::VecDeque;
+use std::env;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+/// The largest message allowed by the specification.
+const MAX_MESSAGE_SIZE: usize = 1 << 27;
+/// The size of the fixed portion of a message header.
+const FIXED_HEADER_SIZE: usize = 16;
+/// The header field holding the number of file descriptors sent with a message.
+const HEADER_FIELD_UNIX_FDS: u8 = 9;
+/// The deepest nesting of containers allowed when converting big-endian messages.
+const MAX_DEPTH: usize = 64;
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 |
+    (bytes[3] as u32) << 24
+}
+
+fn read_u32_be(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 |
+    (bytes[3] as u32)
+}
+
+fn pad_to(len: usize, align: usize) -> usize {
+    (len + align - 1) / align * align
+}
+
+/// The alignment of values of a type.
+fn _alignment(code: u8) -> usize {
+    match code {
+        b'y' | b'g' | b'v' => 1,
+        b'n' | b'q' => 2,
+        b'x' | b't' | b'd' | b'(' | b'{' => 8,
+        _ => 4,
+    }
+}
+
+/// The length of the first complete type in a signature.
+fn _type_len(sig: &[u8]) -> Option<usize> {
+    match *sig.first()? {
+        b'a' => _type_len(&sig[1..]).map(|len| len + 1),
+        open @ b'(' | open @ b'{' => {
+            let close = if open == b'(' { b')' } else { b'}' };
+            let mut len = 1;
+            while *sig.get(len)? != close {
+                len += _type_len(&sig[len..])?;
+            }
+            Some(len + 1)
+        },
+        _ => Some(1),
+    }
+}
+
+fn _skip(buf: &[u8], offset: &mut usize, size: usize) -> Option<()> {
+    let end = offset.checked_add(size)?;
+    if buf.len() < end {
+        return None;
+    }
+    *offset = end;
+
+    Some(())
+}
+
+fn _swap(buf: &mut [u8], offset: &mut usize, size: usize) -> Option<()> {
+    let start = *offset;
+    _skip(buf, offset, size)?;
+    buf[start..*offset].reverse();
+
+    Some(())
+}
+
+/// Convert a big-endian value at an offset into a little-endian one in place.
+///
+/// Returns the length of the value's type in the signature or `None` if the value is malformed.
+fn _swap_value(buf: &mut [u8], offset: &mut usize, sig: &[u8], depth: usize) -> Option<usize> {
+    if MAX_DEPTH < depth {
+        return None;
+    }
+
+    let code = *sig.first()?;
+    *offset = pad_to(*offset, _alignment(code));
+
+    match code {
+        b'y' => _skip(buf, offset, 1).map(|_| 1),
+        b'n' | b'q' => _swap(buf, offset, 2).map(|_| 1),
+        b'b' | b'i' | b'u' | b'h' => _swap(buf, offset, 4).map(|_| 1),
+        b'x' | b't' | b'd' => _swap(buf, offset, 8).map(|_| 1),
+        b's' | b'o' => {
+            _swap(buf, offset, 4)?;
+            let len = read_u32(&buf[*offset - 4..]) as usize;
+            _skip(buf, offset, len.checked_add(1)?).map(|_| 1)
+        },
+        b'g' => {
+            let len = *buf.get(*offset)? as usize;
+            _skip(buf, offset, len + 2).map(|_| 1)
+        },
+        b'v' => {
+            let len = *buf.get(*offset)? as usize;
+            let start = *offset + 1;
+            _skip(buf, offset, len + 2)?;
+            let inner = buf[start..start + len].to_vec();
+            if _swap_value(buf, offset, &inner, depth + 1)? != inner.len() {
+                return None;
+            }
+            Some(1)
+        },
+        b'a' => {
+            let elem_len = _type_len(&sig[1..])?;
+            let elem = &sig[1..1 + elem_len];
+            _swap(buf, offset, 4)?;
+            let len = read_u32(&buf[*offset - 4..]) as usize;
+            // The padding before the first element is present even in empty arrays.
+            *offset = pad_to(*offset, _alignment(elem[0]));
+            let end = offset.checked_add(len)?;
+            if buf.len() < end {
+                return None;
+            }
+            while *offset < end {
+                _swap_value(buf, offset, elem, depth + 1)?;
+            }
+            if *offset != end {
+                return None;
+            }
+            Some(1 + elem_len)
+        },
+        b'(' | b'{' => {
+            let len = _type_len(sig)?;
+            let mut pos = 1;
+            while pos < len - 1 {
+                pos += _swap_value(buf, offset, &sig[pos..len - 1], depth + 1)?;
+            }
+            Some(len)
+        },
+        _ => None,
+    }
+}
+
+/// Convert the body of a big-endian message into a little-endian one in place.
+fn _swap_body(msg: &mut message::Message) -> Option<()> {
+    let sig = match msg.get_header(message::HEADER_FIELD_SIGNATURE) {
+        Some(v) => {
+            match *v.object {
+                Value::BasicValue(BasicValue::Signature(Signature(ref sig))) => sig.clone(),
+                _ => return None,
+            }
+        },
+        None => String::new(),
+    };
+
+    let mut sig = sig.as_bytes();
+    let mut offset = 0;
+    while !sig.is_empty() {
+        let len = _swap_value(&mut msg.body, &mut offset, sig, 0)?;
+        sig = &sig[len..];
+    }
+
+    Some(())
+}
+
+fn _encode(msg: &message::Message) -> Vec<u8> {
+    let mut buf = vec![b'l', msg.message_type, msg.flags, 1];
+    (msg.body.len() as u32).dbus_encode(&mut buf);
+    msg.serial.dbus_encode(&mut buf);
+    let fields = msg.headers.iter().cloned().map(Value::Struct).collect();
+    Value::Array(Array::new_with_sig(fields, "a(yv)".to_string())).dbus_encode(&mut buf);
+    let header_len = pad_to(buf.len(), 8);
+    buf.resize(header_len, 0);
+    buf.extend_from_slice(&msg.body);
+
+    buf
+}
+
+#[cfg(target_os = "linux")]
+fn _peer_credentials(stream: &UnixStream) -> Result<Credentials> {
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(stream.as_raw_fd(),
+                         libc::SOL_SOCKET,
+                         libc::SO_PEERCRED,
+                         &mut cred as *mut libc::ucred as *mut libc::c_void,
+                         &mut len)
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    Ok(Credentials {
+        unix_user_id: Some(cred.uid),
+        process_id: Some(cred.pid as u32),
+        ..Credentials::default()
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn _peer_credentials(stream: &UnixStream) -> Result<Credentials> {
+    let mut uid: libc::uid_t = unsafe { mem::zeroed() };
+    let mut gid: libc::gid_t = unsafe { mem::zeroed() };
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    Ok(Credentials {
+        unix_user_id: Some(uid),
+        unix_group_ids: Some(vec![gid]),
+        ..Credentials::default()
+    })
+}
+
+/// A socket connected to a D-Bus server.
+enum Stream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Stream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match *self {
+            Stream::Unix(ref stream) => stream.set_nonblocking(nonblocking),
+            Stream::Tcp(ref stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match *self {
+            Stream::Unix(ref stream) => stream.set_read_timeout(timeout),
+            Stream::Tcp(ref stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        match *self {
+            Stream::Unix(ref stream) => stream.shutdown(Shutdown::Both),
+            Stream::Tcp(ref stream) => stream.shutdown(Shutdown::Both),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Unix(ref mut stream) => stream.read(buf),
+            Stream::Tcp(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Unix(ref mut stream) => stream.write(buf),
+            Stream::Tcp(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Stream::Unix(ref mut stream) => stream.flush(),
+            Stream::Tcp(ref mut stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            Stream::Unix(ref stream) => stream.as_raw_fd(),
+            Stream::Tcp(ref stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+/// A byte stream to a bus which sends and receives whole messages.
+pub struct Transport {
+    stream: Stream,
+    rbuf: Vec<u8>,
+    rfds: VecDeque<UnixFd>,
+    peer_credentials: Option<Credentials>,
+    unix_fds: bool,
+}
+
+impl Transport {
+    /// Connect to the first reachable server in a `;`-separated list of addresses.
+    pub fn open(addresses: &str, auth: &mut Authenticator) -> Result<Self> {
+        let mut last_err = None;
+
+        for address in Address::parse_list(addresses)? {
+            match Self::connect(&address, auth) {
+                Ok(transport) => return Ok(transport),
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        // Parsing guarantees at least one address was tried.
+        Err(last_err.unwrap())
+    }
+
+    /// Connect to the address given in an environment variable.
+    ///
+    /// If the variable is not set, the default address is used, if any.
+    pub fn open_env(variable: &str, default: Option<&str>, auth: &mut Authenticator)
+                    -> Result<Self> {
+        match (env::var(variable), default) {
+            (Ok(addresses), _) => Self::open(&addresses, auth),
+            (Err(_), Some(default)) => Self::open(default, auth),
+            (Err(_), None) => bail!(ErrorKind::MissingAddress(variable.to_string())),
+        }
+    }
+
+    /// Connect to a single address.
+    pub fn connect(address: &Address, auth: &mut Authenticator) -> Result<Self> {
+        match address.transport() {
+            "unix" => {
+                if let Some(path) = address.get("path") {
+                    Self::connect_path(path, auth)
+                } else if let Some(name) = address.get("abstract") {
+                    Self::connect_abstract(name, auth)
+                } else if address.get("tmpdir").is_some() || address.get("dir").is_some() {
+                    bail!(ErrorKind::InvalidAddress(address.to_string(),
+                                                    "only servers may listen on a directory"
+                                                        .to_string()))
+                } else {
+                    bail!(ErrorKind::InvalidAddress(address.to_string(),
+                                                    "missing 'path' or 'abstract' parameter"
+                                                        .to_string()))
+                }
+            },
+            "tcp" => {
+                let host = address.get("host").unwrap_or("localhost");
+                let port = address.require("port")?
+                    .parse::<u16>()
+                    .map_err(|_| {
+                        ErrorKind::InvalidAddress(address.to_string(), "invalid port".to_string())
+                    })?;
+                let family = address.get("family");
+
+                let addrs = (host, port).to_socket_addrs()?
+                    .filter(|addr| {
+                        match family {
+                            Some("ipv4") => addr.is_ipv4(),
+                            Some("ipv6") => addr.is_ipv6(),
+                            _ => true,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                Self::new(Stream::Tcp(TcpStream::connect(&addrs[..])?), auth)
+            },
+            transport => {
+                bail!(ErrorKind::InvalidAddress(address.to_string(),
+                                                format!("unsupported transport '{}'", transport)))
+            },
+        }
+    }
+
+    fn connect_path(path: &str, auth: &mut Authenticator) -> Result<Self> {
+        Self::new(Stream::Unix(UnixStream::connect(path)?), auth)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn connect_abstract(name: &str, auth: &mut Authenticator) -> Result<Self> {
+        Self::new(Stream::Unix(fd::connect_abstract(name)?), auth)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn connect_abstract(name: &str, _: &mut Authenticator) -> Result<Self> {
+        bail!(ErrorKind::InvalidAddress(format!("unix:abstract={}", name),
+                                        "abstract sockets are not supported".to_string()))
+    }
+
+    fn new(mut stream: Stream, auth: &mut Authenticator) -> Result<Self> {
+        let can_pass_fds = match stream {
+            Stream::Unix(_) => true,
+            Stream::Tcp(_) => false,
+        };
+        let unix_fds = auth.authenticate(&mut stream, can_pass_fds)?;
+
+        Ok(Transport {
+            stream: stream,
+            rbuf: vec![],
+            rfds: VecDeque::new(),
+            peer_credentials: None,
+            unix_fds: unix_fds,
+        })
+    }
+
+    /// Authenticate a client which connected to a listening socket.
+    ///
+    /// Any read timeout set on the stream only applies to the handshake; it is cleared once the
+    /// client has authenticated.
+    pub fn accept(stream: UnixStream, guid: &str, policy: &AuthPolicy) -> Result<Self> {
+        let credentials = _peer_credentials(&stream)?;
+        let mut stream = Stream::Unix(stream);
+        let unix_fds = policy.accept(&mut stream, guid, &credentials)?;
+        stream.set_read_timeout(None)?;
+
+        Ok(Transport {
+            stream: stream,
+            rbuf: vec![],
+            rfds: VecDeque::new(),
+            peer_credentials: Some(credentials),
+            unix_fds: unix_fds,
+        })
+    }
+
+    /// The credentials of the client, for transports which were accepted from a listener.
+    pub fn peer_credentials(&self) -> Option<&Credentials> {
+        self.peer_credentials.as_ref()
+    }
+
+    /// Whether Unix file descriptors may be passed over the transport.
+    pub fn unix_fds(&self) -> bool {
+        self.unix_fds
+    }
+
+    /// Send a message over the transport along with its file descriptors.
+    pub fn send(&mut self, msg: &message::Message, fds: &[UnixFd]) -> Result<()> {
+        if !fds.is_empty() && !self.unix_fds {
+            bail!(ErrorKind::UnixFdsNotSupported);
+        }
+
+        let buf = _encode(msg);
+
+        self.stream.set_nonblocking(false)?;
+
+        let mut written = 0;
+        if !fds.is_empty() {
+            // The file descriptors are attached to the first byte of the message.
+            let raw_fds = fds.iter().map(AsRawFd::as_raw_fd).collect::<Vec<_>>();
+            written = fd::send_with_fds(self.stream.as_raw_fd(), &buf, &raw_fds)?;
+        }
+        self.stream.write_all(&buf[written..])?;
+
+        Ok(())
+    }
+
+    /// Whether a complete message has been received, but not yet read.
+    pub fn has_pending(&self) -> bool {
+        self._message_size().map_or(false, |size| size <= self.rbuf.len())
+    }
+
+    /// Read a message from the transport.
+    ///
+    /// A timeout of `None` blocks until a message is available. Returns `None` if no message
+    /// arrived within the timeout.
+    pub fn read_message(&mut self, timeout: Option<Duration>)
+                        -> Result<Option<(message::Message, Vec<UnixFd>)>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            if let Some(msg) = self._parse_message()? {
+                return Ok(Some(msg));
+            }
+
+            let remaining = deadline.map(|deadline| {
+                let now = Instant::now();
+                if deadline <= now {
+                    Duration::from_secs(0)
+                } else {
+                    deadline - now
+                }
+            });
+
+            if !self._fill(remaining)? {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn _fill(&mut self, timeout: Option<Duration>) -> Result<bool> {
+        match timeout {
+            Some(timeout) if timeout == Duration::from_secs(0) => {
+                self.stream.set_nonblocking(true)?
+            },
+            _ => {
+                self.stream.set_nonblocking(false)?;
+                self.stream.set_read_timeout(timeout)?;
+            },
+        }
+
+        let mut buf = [0; 4096];
+        let res = if self.unix_fds {
+            let mut fds = vec![];
+            let res = fd::recv_with_fds(self.stream.as_raw_fd(), &mut buf, &mut fds);
+            self.rfds.extend(fds);
+            match res {
+                // Messages can no longer be matched with their file descriptors.
+                Ok((_, true)) => {
+                    return Err(self._close("file descriptors were truncated".to_string()))
+                },
+                Ok((n, false)) => Ok(n),
+                Err(err) => Err(err),
+            }
+        } else {
+            self.stream.read(&mut buf)
+        };
+        match res {
+            Ok(0) => bail!(ErrorKind::Disconnected),
+            Ok(n) => {
+                self.rbuf.extend_from_slice(&buf[..n]);
+                Ok(true)
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => Ok(true),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock ||
+                            err.kind() == io::ErrorKind::TimedOut => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn _message_size(&self) -> Option<usize> {
+        if self.rbuf.len() < FIXED_HEADER_SIZE {
+            return None;
+        }
+
+        let read: fn(&[u8]) -> u32 = if self.rbuf[0] == b'B' {
+            read_u32_be
+        } else {
+            read_u32
+        };
+        let body_len = read(&self.rbuf[4..8]) as usize;
+        let fields_len = read(&self.rbuf[12..16]) as usize;
+
+        Some(pad_to(FIXED_HEADER_SIZE + fields_len, 8) + body_len)
+    }
+
+    /// Close the transport after receiving data which cannot be read.
+    ///
+    /// Buffered data is discarded and further reads fail with `Disconnected`.
+    fn _close(&mut self, reason: String) -> Error {
+        self.rbuf.clear();
+        self.rfds.clear();
+        if let Err(err) = self.stream.shutdown() {
+            println!("failed to shut down the connection: {:?}", err);
+        }
+
+        ErrorKind::InvalidMessage(reason).into()
+    }
+
+    fn _parse_message(&mut self) -> Result<Option<(message::Message, Vec<UnixFd>)>> {
+        let size = match self._message_size() {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+
+        // The stream cannot be resynchronized after a message which cannot be framed.
+        let big_endian = match self.rbuf[0] {
+            b'l' => false,
+            b'B' => true,
+            endian => return Err(self._close(format!("invalid endianness: {:?}", endian))),
+        };
+        if MAX_MESSAGE_SIZE < size {
+            return Err(self._close(format!("message too large: {} bytes", size)));
+        }
+        if self.rbuf.len() < size {
+            return Ok(None);
+        }
+
+        let mut header = self.rbuf.drain(..size).collect::<Vec<_>>();
+        if big_endian {
+            header[4..8].reverse();
+            header[8..12].reverse();
+        }
+        let body_len = read_u32(&header[4..8]) as usize;
+        let body = header.split_off(size - body_len);
+        // Big-endian messages are converted so that they may be read like any other.
+        if big_endian && _swap_value(&mut header, &mut 12usize, b"a(yv)", 0).is_none() {
+            bail!(ErrorKind::InvalidMessage("invalid header fields".to_string()));
+        }
+
+        // Start at the length of the header field array.
+        let mut offset = 12;
+        let fields = demarshal(&mut header, &mut offset, &mut "a(yv)".to_string())
+            .map_err(ErrorKind::ExtractArguments)?;
+        let headers = match fields {
+            Value::Array(array) => {
+                array.objects
+                    .into_iter()
+                    .filter_map(|field| {
+                        match field {
+                            Value::Struct(field) => Some(field),
+                            _ => None,
+                        }
+                    })
+                    .collect()
+            },
+            _ => bail!(ErrorKind::InvalidMessage("invalid header fields".to_string())),
+        };
+
+        let mut msg = message::Message {
+            big_endian: false,
+            message_type: header[1],
+            flags: header[2],
+            version: header[3],
+            serial: read_u32(&header[8..12]),
+            headers: headers,
+            body: body,
+        };
+        if big_endian && _swap_body(&mut msg).is_none() {
+            bail!(ErrorKind::InvalidMessage("invalid message body".to_string()));
+        }
+
+        let unix_fds = msg.get_header(HEADER_FIELD_UNIX_FDS)
+            .and_then(|v| {
+                match *v.object {
+                    Value::BasicValue(BasicValue::Uint32(count)) => Some(count as usize),
+                    _ => None,
+                }
+            })
+            .unwrap_or(0);
+        if self.rfds.len() < unix_fds {
+            bail!(ErrorKind::InvalidMessage(format!("expected {} file descriptors, received {}",
+                                                    unix_fds,
+                                                    self.rfds.len())));
+        }
+        let fds = self.rfds.drain(..unix_fds).collect();
+
+        Ok(Some((msg, fds)))
+    }
+}
+
+impl AsRawFd for Transport {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+impl Transport {
+    /// A pair of transports connected to each other without authentication.
+    pub fn pair() -> (Self, Self) {
+        let (left, right) = UnixStream::pair().unwrap();
+        let transport = |stream| {
+            Transport {
+                stream: Stream::Unix(stream),
+                rbuf: vec![],
+                rfds: VecDeque::new(),
+                peer_credentials: None,
+                unix_fds: true,
+            }
+        };
+
+        (transport(left), transport(right))
+    }
+}
+
+#[cfg(test)]
+fn _test_message() -> Vec<u8> {
+    use message::Message;
+
+    _encode(&Message::new_method_call("org.example.Test", "/a", "org.example.Iface", "Method")
+        .add_argument(&"value".to_string())
+        .message)
+}
+
+#[test]
+fn test_parse_message_short() {
+    let (mut transport, _peer) = Transport::pair();
+    let buf = _test_message();
+
+    transport.rbuf = buf[..FIXED_HEADER_SIZE - 1].to_vec();
+    assert_eq!(transport._message_size(), None);
+    assert!(transport._parse_message().unwrap().is_none());
+
+    transport.rbuf = buf[..buf.len() - 1].to_vec();
+    assert_eq!(transport._message_size(), Some(buf.len()));
+    assert!(!transport.has_pending());
+    assert!(transport._parse_message().unwrap().is_none());
+    assert_eq!(transport.rbuf.len(), buf.len() - 1);
+}
+
+#[test]
+fn test_parse_message_padding() {
+    use message::Message;
+
+    let (mut transport, _peer) = Transport::pair();
+    let buf = _test_message();
+
+    // The body starts at the next 8-byte boundary after the header fields.
+    let fields_len = read_u32(&buf[12..16]) as usize;
+    assert!(fields_len % 8 != 0);
+    let body_offset = pad_to(FIXED_HEADER_SIZE + fields_len, 8);
+    assert_eq!(buf.len(), body_offset + read_u32(&buf[4..8]) as usize);
+
+    transport.rbuf = buf.clone();
+    transport.rbuf.extend_from_slice(&buf);
+    assert_eq!(transport._message_size(), Some(buf.len()));
+
+    let (msg, fds) = transport._parse_message().unwrap().unwrap();
+    assert!(fds.is_empty());
+    assert_eq!(msg.body, &buf[body_offset..]);
+    let msg = Message::new(msg);
+    assert_eq!(msg.path(), Some("/a".to_string()));
+    assert_eq!(msg.member(), Some("Method".to_string()));
+    assert_eq!(msg.read::<(String,)>().unwrap(), ("value".to_string(),));
+
+    // The following message is left intact.
+    assert_eq!(transport.rbuf, buf);
+    assert!(transport.has_pending());
+}
+
+#[test]
+fn test_parse_message_invalid() {
+    let check = |buf: Vec<u8>| {
+        let (mut transport, _peer) = Transport::pair();
+        transport.rbuf = buf;
+
+        assert!(transport._parse_message().is_err());
+        assert!(transport.rbuf.is_empty());
+        match *transport.read_message(None).unwrap_err().kind() {
+            ErrorKind::Disconnected => (),
+            ref kind => panic!("unexpected error: {:?}", kind),
+        }
+    };
+
+    let mut endianness = _test_message();
+    endianness[0] = b'X';
+    check(endianness);
+
+    let mut oversize = _test_message();
+    oversize[4..8].copy_from_slice(&[0, 0, 0, 0x10]);
+    check(oversize);
+}
+
+#[test]
+fn test_parse_message_big_endian() {
+    use message::Message;
+
+    let push_u32 = |buf: &mut Vec<u8>, value: u32| {
+        buf.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8,
+                                value as u8]);
+    };
+    let pad = |buf: &mut Vec<u8>, align: usize| {
+        let len = pad_to(buf.len(), align);
+        buf.resize(len, 0);
+    };
+
+    // The header fields start at an 8-byte boundary, so alignment within them is the same.
+    let mut fields = vec![];
+    for &(code, sig, value) in &[(1, b'o', "/a"), (3, b's', "Method"), (8, b'g', "sau")] {
+        pad(&mut fields, 8);
+        fields.extend_from_slice(&[code, 1, sig, 0]);
+        if sig == b'g' {
+            fields.push(value.len() as u8);
+        } else {
+            push_u32(&mut fields, value.len() as u32);
+        }
+        fields.extend_from_slice(value.as_bytes());
+        fields.push(0);
+    }
+
+    let mut body = vec![];
+    push_u32(&mut body, 5);
+    body.extend_from_slice(b"value\0");
+    pad(&mut body, 4);
+    push_u32(&mut body, 8);
+    push_u32(&mut body, 1);
+    push_u32(&mut body, 2);
+
+    let mut buf = vec![b'B', 1, 0, 1];
+    push_u32(&mut buf, body.len() as u32);
+    push_u32(&mut buf, 7);
+    push_u32(&mut buf, fields.len() as u32);
+    buf.extend_from_slice(&fields);
+    pad(&mut buf, 8);
+    buf.extend_from_slice(&body);
+
+    let (mut transport, _peer) = Transport::pair();
+    transport.rbuf = buf.clone();
+    assert_eq!(transport._message_size(), Some(buf.len()));
+
+    let (msg, _) = transport._parse_message().unwrap().unwrap();
+    assert_eq!(msg.serial, 7);
+    let msg = Message::new(msg);
+    assert_eq!(msg.path(), Some("/a".to_string()));
+    assert_eq!(msg.member(), Some("Method".to_string()));
+    assert_eq!(msg.read::<(String, Vec<u32>)>().unwrap(),
+               ("value".to_string(), vec![1, 2]));
+    assert!(transport.rbuf.is_empty());
+
+    // A value which runs past the end of the message is rejected without closing the transport.
+    let len = buf.len();
+    buf[len - 12..len - 8].copy_from_slice(&[0, 0, 0, 12]);
+    transport.rbuf = buf;
+    assert!(transport._parse_message().is_err());
+    assert!(transport.rbuf.is_empty());
+    assert!(transport.read_message(Some(Duration::from_secs(0))).unwrap().is_none());
+}